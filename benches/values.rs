@@ -0,0 +1,53 @@
+//! Compares the VM and tree-walker on value-heavy workloads.
+//!
+//! Run with `cargo bench`, and again with `cargo bench --features nan-boxing` to compare the
+//! two VM value representations.
+
+use rlox::value::Value;
+use rlox::{Backend, Interpreter};
+use std::io::{self, Cursor};
+use std::mem;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 5;
+
+const BENCHES: [(&str, &str); 3] = [
+    (
+        "fib",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } fib(24);",
+    ),
+    (
+        "loop",
+        "var sum = 0; for (var i = 0; i < 300000; i = i + 1) { sum = sum + i * 2 - 1; } sum;",
+    ),
+    (
+        "strings",
+        "var s = \"\"; var eq = 0;
+         for (var i = 0; i < 20000; i = i + 1) { s = \"k\" + i; if (s == \"k7\") eq = eq + 1; }
+         eq;",
+    ),
+];
+
+fn time(backend: Backend, source: &str) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let mut interpreter = Interpreter::with_io(Cursor::new(""), io::sink());
+        interpreter.set_backend(backend);
+
+        let start = Instant::now();
+        interpreter.eval_str(source).expect("benchmark script runs");
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main() {
+    println!("VM value size: {} bytes", mem::size_of::<Value>());
+    println!("{:<10} {:>14} {:>14}", "bench", "tree-walker", "vm");
+
+    for (name, source) in BENCHES {
+        let tree = time(Backend::TreeWalker, source);
+        let vm = time(Backend::Vm, source);
+        println!("{:<10} {:>12.2?} {:>12.2?}", name, tree, vm);
+    }
+}
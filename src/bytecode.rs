@@ -0,0 +1,573 @@
+//! On-disk format for compiled scripts.
+//!
+//! All integers are little-endian. A file is laid out as:
+//!
+//! ```text
+//! magic        b"RLOX"
+//! version      u16
+//! source hash  u64   FNV-1a of the source text
+//! function           the top-level script
+//! ```
+//!
+//! and each function as its name, arity and upvalue count, its constant pool, its code, and a
+//! run-length encoded line table of `(line, count)` pairs. Nested functions are stored inline
+//! in the constant pool of the function that creates them.
+//!
+//! Files are checked when they are read, so that running one can't make the VM read past
+//! its code, constants, stack or upvalues however the file was damaged or made.
+
+use crate::chunk::{Chunk, OpCode};
+use crate::symbol::intern;
+use crate::value::{Constant, Function};
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 4] = b"RLOX";
+pub const VERSION: u16 = 4;
+
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+const TAG_NAME: u8 = 6;
+
+pub struct CompiledScript {
+    pub source_hash: u64,
+    pub function: Function,
+}
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// 64-bit FNV-1a, used because it is stable across builds and platforms.
+pub fn hash_source(source: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in source.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn write_script(function: &Function, source_hash: u64) -> Result<Vec<u8>, String> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&source_hash.to_le_bytes());
+    write_function(&mut bytes, function)?;
+    Ok(bytes)
+}
+
+pub fn read_script(bytes: &[u8]) -> Result<CompiledScript, String> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("Not a compiled rlox script.".to_string());
+    }
+
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(format!(
+            "Unsupported bytecode version {} (expected {}).",
+            version, VERSION
+        ));
+    }
+
+    let source_hash = u64::from_le_bytes(reader.array()?);
+    let function = reader.function()?;
+
+    if reader.position != bytes.len() {
+        return Err("Unexpected trailing data in compiled script.".to_string());
+    }
+    // The VM runs the script like a call with no arguments, from a closure with no upvalues.
+    if function.arity != 0 || function.upvalue_count != 0 {
+        return Err("Invalid top-level function in compiled script.".to_string());
+    }
+    verify(&function)?;
+
+    Ok(CompiledScript {
+        source_hash,
+        function,
+    })
+}
+
+/// Reads only the header, so a cache can be validated without decoding the whole file.
+pub fn read_source_hash(bytes: &[u8]) -> Option<u64> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len()).ok()? != MAGIC {
+        return None;
+    }
+    if u16::from_le_bytes(reader.array().ok()?) != VERSION {
+        return None;
+    }
+    Some(u64::from_le_bytes(reader.array().ok()?))
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) -> Result<(), String> {
+    let value = u32::try_from(value).map_err(|_| "Compiled script is too large.".to_string())?;
+    bytes.extend_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) -> Result<(), String> {
+    write_u32(bytes, value.len())?;
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &Function) -> Result<(), String> {
+    write_str(bytes, &function.name)?;
+    write_u32(bytes, function.arity)?;
+    write_u32(bytes, function.upvalue_count)?;
+
+    let chunk = &function.chunk;
+    write_u32(bytes, chunk.constants.len())?;
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(n) => {
+                bytes.push(TAG_NUMBER);
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::String(s) => {
+                bytes.push(TAG_STRING);
+                write_str(bytes, s)?;
+            }
+            Constant::Name(name) => {
+                bytes.push(TAG_NAME);
+                write_str(bytes, name.as_str())?;
+            }
+            Constant::Function(nested) => {
+                bytes.push(TAG_FUNCTION);
+                write_function(bytes, nested)?;
+            }
+        }
+    }
+
+    write_u32(bytes, chunk.code.len())?;
+    bytes.extend_from_slice(&chunk.code);
+
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for &line in &chunk.lines {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    write_u32(bytes, runs.len())?;
+    for (line, count) in runs {
+        write_u32(bytes, line)?;
+        write_u32(bytes, count)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that every instruction `function` can reach is whole and refers only to constants
+/// of the right kind, and to locals and upvalues that exist. It follows each path through the
+/// code with what it knows about the stack, which must be the same wherever paths meet.
+/// Nested functions are checked too.
+fn verify(function: &Function) -> Result<(), String> {
+    let chunk = &function.chunk;
+    if chunk.code.is_empty() {
+        return Err(format!("Invalid bytecode in {}: it has no code.", function));
+    }
+    let mut states: Vec<Option<StackState>> = vec![None; chunk.code.len()];
+    let start = StackState {
+        height: 1 + function.arity,
+        tries: vec![],
+        captured: vec![],
+    };
+    let mut pending = vec![(0, start)];
+
+    while let Some((offset, mut state)) = pending.pop() {
+        let invalid = |reason: &str| {
+            format!(
+                "Invalid bytecode in {} at offset {}: {}.",
+                function, offset, reason
+            )
+        };
+        match &states[offset] {
+            Some(known) if *known == state => continue,
+            Some(_) => return Err(invalid("paths meet with different stacks")),
+            None => states[offset] = Some(state.clone()),
+        }
+
+        let byte = |index: usize| {
+            chunk
+                .code
+                .get(offset + index)
+                .copied()
+                .ok_or_else(|| invalid("truncated instruction"))
+        };
+        let operand = || Ok::<_, String>(((byte(1)? as usize) << 8) | byte(2)? as usize);
+        let jump = |forward: bool| {
+            let distance = operand()?;
+            let target = if forward {
+                Some(offset + 3 + distance)
+            } else {
+                (offset + 3).checked_sub(distance)
+            };
+            target
+                .filter(|&target| target < chunk.code.len())
+                .ok_or_else(|| invalid("jumps out of the code"))
+        };
+        let constant = || {
+            let index = operand()?;
+            chunk
+                .constants
+                .get(index)
+                .ok_or_else(|| invalid("constant out of range"))
+        };
+        let name = || match constant()? {
+            Constant::Name(_) => Ok(()),
+            _ => Err(invalid("expected a name constant")),
+        };
+
+        let op = OpCode::from_byte(chunk.code[offset]).ok_or_else(|| invalid("unknown opcode"))?;
+        let (next, pops, pushes) = match op {
+            OpCode::Constant => match constant()? {
+                Constant::Number(_) | Constant::String(_) => (offset + 3, 0, 1),
+                _ => return Err(invalid("expected a number or string constant")),
+            },
+            OpCode::Nil | OpCode::True | OpCode::False => (offset + 1, 0, 1),
+            OpCode::Pop | OpCode::Print => (offset + 1, 1, 0),
+            OpCode::GetLocal | OpCode::SetLocal => {
+                if byte(1)? as usize >= state.height {
+                    return Err(invalid("local slot out of range"));
+                }
+                match op {
+                    OpCode::GetLocal => (offset + 2, 0, 1),
+                    _ => (offset + 2, 1, 1),
+                }
+            }
+            OpCode::GetGlobal => {
+                name()?;
+                (offset + 3, 0, 1)
+            }
+            OpCode::DefineGlobal => {
+                name()?;
+                (offset + 3, 1, 0)
+            }
+            OpCode::SetGlobal | OpCode::GetProperty => {
+                name()?;
+                (offset + 3, 1, 1)
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                if byte(1)? as usize >= function.upvalue_count {
+                    return Err(invalid("upvalue out of range"));
+                }
+                match op {
+                    OpCode::GetUpvalue => (offset + 2, 0, 1),
+                    _ => (offset + 2, 1, 1),
+                }
+            }
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => (offset + 1, 2, 1),
+            OpCode::Not | OpCode::Negate => (offset + 1, 1, 1),
+            OpCode::Jump | OpCode::Loop => {
+                pending.push((jump(op == OpCode::Jump)?, state));
+                continue;
+            }
+            OpCode::JumpIfFalse => {
+                // The condition stays on the stack either way.
+                let target = jump(true)?;
+                state.pop(1).map_err(invalid)?;
+                state.height += 1;
+                pending.push((target, state.clone()));
+                (offset + 3, 0, 0)
+            }
+            OpCode::Call => (offset + 2, byte(1)? as usize + 1, 1),
+            OpCode::Closure => {
+                let Constant::Function(nested) = constant()? else {
+                    return Err(invalid("expected a function constant"));
+                };
+                for upvalue in 0..nested.upvalue_count {
+                    let index = byte(4 + 2 * upvalue)? as usize;
+                    let count = match byte(3 + 2 * upvalue)? {
+                        1 => state.height,
+                        0 => function.upvalue_count,
+                        _ => return Err(invalid("invalid upvalue kind")),
+                    };
+                    if index >= count {
+                        return Err(invalid("captured variable out of range"));
+                    }
+                    if count == state.height && !state.captured.contains(&index) {
+                        state.captured.push(index);
+                    }
+                }
+                (offset + 3 + 2 * nested.upvalue_count, 0, 1)
+            }
+            OpCode::CloseUpvalue => {
+                let top = state.height.wrapping_sub(1);
+                state.captured.retain(|&slot| slot != top);
+                (offset + 1, 1, 0)
+            }
+            OpCode::Try => {
+                // The handler runs with the thrown value and its error object pushed.
+                let mut handler = state.clone();
+                handler.height += 2;
+                pending.push((jump(true)?, handler));
+                state.tries.push(state.height);
+                (offset + 3, 0, 0)
+            }
+            OpCode::EndTry => {
+                if state.tries.pop().is_none() {
+                    return Err(invalid("leaves a try block it isn't in"));
+                }
+                (offset + 1, 0, 0)
+            }
+            OpCode::Import => match constant()? {
+                Constant::String(_) => (offset + 3, 0, 1),
+                _ => return Err(invalid("expected a string constant")),
+            },
+            OpCode::Throw | OpCode::Rethrow | OpCode::Return => {
+                let pops = if op == OpCode::Rethrow { 2 } else { 1 };
+                if op == OpCode::Return && !state.tries.is_empty() {
+                    return Err(invalid("returns from inside a try block"));
+                }
+                state.pop(pops).map_err(invalid)?;
+                continue;
+            }
+        };
+
+        state.pop(pops).map_err(invalid)?;
+        state.height += pushes;
+        if next >= chunk.code.len() {
+            return Err(invalid("runs past the end of the code"));
+        }
+        pending.push((next, state));
+    }
+
+    for constant in &chunk.constants {
+        if let Constant::Function(nested) = constant {
+            verify(nested)?;
+        }
+    }
+    Ok(())
+}
+
+/// What `verify` knows about the stack at an instruction.
+#[derive(Clone, PartialEq)]
+struct StackState {
+    /// Values on the stack, counting the callee and its arguments.
+    height: usize,
+    /// The height at which each `try` block around the instruction was entered, innermost
+    /// last. A handler gets the stack back as it was then, so code inside can't pop below it.
+    tries: Vec<usize>,
+    /// Slots closures have captured and that are still open. Only `OP_CLOSE_UPVALUE` may
+    /// pop one, since an open upvalue must not outlive its slot.
+    captured: Vec<usize>,
+}
+
+impl StackState {
+    /// Takes `count` values off the stack, or says why the code may not.
+    fn pop(&mut self, count: usize) -> Result<(), &'static str> {
+        let floor = self.tries.last().copied().unwrap_or(0);
+        let height = self
+            .height
+            .checked_sub(count)
+            .filter(|&height| height >= floor)
+            .ok_or("stack underflow")?;
+        if self.captured.iter().any(|&slot| slot >= height) {
+            return Err("pops a captured variable");
+        }
+        self.height = height;
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| "Truncated compiled script.".to_string())?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| "Invalid string in compiled script.".to_string())
+    }
+
+    fn function(&mut self) -> Result<Function, String> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+
+        let mut chunk = Chunk::new();
+        let constant_count = self.u32()?;
+        for _ in 0..constant_count {
+            let constant = match self.take(1)?[0] {
+                TAG_NUMBER => Constant::Number(f64::from_le_bytes(self.array()?)),
+                TAG_STRING => Constant::String(self.string()?.into()),
+                TAG_NAME => Constant::Name(intern(&self.string()?)),
+                TAG_FUNCTION => Constant::Function(Rc::new(self.function()?)),
+                tag => return Err(format!("Unknown constant tag {} in compiled script.", tag)),
+            };
+            chunk.constants.push(constant);
+        }
+
+        let code_len = self.u32()?;
+        chunk.code = self.take(code_len)?.to_vec();
+
+        let run_count = self.u32()?;
+        for _ in 0..run_count {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            if chunk.lines.len() + count > chunk.code.len() {
+                return Err("Line table does not match code in compiled script.".to_string());
+            }
+            chunk.lines.extend(std::iter::repeat_n(line, count));
+        }
+        if chunk.lines.len() != chunk.code.len() {
+            return Err("Line table does not match code in compiled script.".to_string());
+        }
+
+        Ok(Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::debug::disassemble_function;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile_source(source: &str) -> Function {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        compile(&Parser::new(tokens).parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trips_nested_functions() {
+        let source = "fun outer(a) {\n  fun inner() { return a + \"!\"; }\n  return inner;\n}\nprint outer(1.5)();";
+        let function = compile_source(source);
+
+        let bytes = write_script(&function, hash_source(source)).unwrap();
+        let script = read_script(&bytes).unwrap();
+
+        assert_eq!(script.source_hash, hash_source(source));
+        assert_eq!(read_source_hash(&bytes), Some(hash_source(source)));
+        assert_eq!(
+            disassemble_function(&script.function),
+            disassemble_function(&function)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_headers_and_truncation() {
+        let function = compile_source("print 1;");
+        let mut bytes = write_script(&function, 0).unwrap();
+
+        assert_eq!(
+            read_script(b"nope").err().unwrap(),
+            "Not a compiled rlox script."
+        );
+        assert_eq!(
+            read_script(&bytes[..bytes.len() - 1]).err().unwrap(),
+            "Truncated compiled script."
+        );
+
+        bytes[4] = 9;
+        assert_eq!(
+            read_script(&bytes).err().unwrap(),
+            "Unsupported bytecode version 9 (expected 4)."
+        );
+    }
+
+    #[test]
+    fn rejects_code_the_vm_could_not_run() {
+        let corrupt = |source: &str, change: &dyn Fn(&mut Chunk)| {
+            let mut function = compile_source(source);
+            change(&mut function.chunk);
+            read_script(&write_script(&function, 0).unwrap())
+                .err()
+                .unwrap()
+        };
+        let find = |chunk: &Chunk, op: OpCode| {
+            let offset = chunk.code.iter().position(|&byte| byte == op as u8);
+            offset.unwrap()
+        };
+
+        // print 1; compiles to CONSTANT 0, PRINT, NIL, RETURN.
+        assert_eq!(
+            corrupt("print 1;", &|chunk| chunk.code[0] = 200),
+            "Invalid bytecode in <script> at offset 0: unknown opcode."
+        );
+        assert_eq!(
+            corrupt("print 1;", &|chunk| chunk.code[2] = 7),
+            "Invalid bytecode in <script> at offset 0: constant out of range."
+        );
+        assert_eq!(
+            corrupt("print 1;", &|chunk| {
+                chunk.code.truncate(2);
+                chunk.lines.truncate(2);
+            }),
+            "Invalid bytecode in <script> at offset 0: truncated instruction."
+        );
+        assert_eq!(
+            corrupt("print 1;", &|chunk| chunk.code[4] = OpCode::Pop as u8),
+            "Invalid bytecode in <script> at offset 5: stack underflow."
+        );
+        assert_eq!(
+            corrupt("{ var a = 1; print a; }", &|chunk| {
+                let offset = find(chunk, OpCode::GetLocal);
+                chunk.code[offset + 1] = 5;
+            }),
+            "Invalid bytecode in <script> at offset 3: local slot out of range."
+        );
+        assert_eq!(
+            corrupt("if (true) print 1;", &|chunk| {
+                let offset = find(chunk, OpCode::JumpIfFalse);
+                chunk.code[offset + 1] = 0xff;
+            }),
+            "Invalid bytecode in <script> at offset 1: jumps out of the code."
+        );
+        assert_eq!(
+            corrupt("{ var a = 1; fun f() { return a; } }", &|chunk| {
+                let offset = find(chunk, OpCode::CloseUpvalue);
+                chunk.code[offset] = OpCode::Pop as u8;
+            }),
+            "Invalid bytecode in <script> at offset 9: pops a captured variable."
+        );
+        assert_eq!(
+            corrupt("print 1;", &|chunk| chunk.code[0] =
+                OpCode::GetUpvalue as u8),
+            "Invalid bytecode in <script> at offset 0: upvalue out of range."
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use crate::environment::Environment;
+use crate::expr::LiteralValue;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+pub type NativeFn = dyn Fn(&[LiteralValue]) -> Result<LiteralValue, String>;
+
+/// A function declared in Lox source, together with the scope it closes over.
+pub struct LoxFunction {
+    pub declaration: Token,
+    pub params: Vec<Token>,
+    pub body: Rc<Vec<Stmt>>,
+    pub closure: Rc<RefCell<Environment>>,
+    /// The file the function was declared in, if it came from one.
+    pub file: Option<Rc<str>>,
+}
+
+impl LoxFunction {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.lexeme.as_str()
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// A function implemented in Rust and exposed to scripts as a global.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub fun: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, fun: F) -> Self
+    where
+        F: Fn(&[LiteralValue]) -> Result<LiteralValue, String> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity,
+            fun: Box::new(fun),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
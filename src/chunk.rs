@@ -0,0 +1,213 @@
+use crate::scanner::TokenType;
+use crate::value::Constant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Try,
+    EndTry,
+    Throw,
+    Rethrow,
+    Import,
+    GetProperty,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 38] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Try,
+        OpCode::EndTry,
+        OpCode::Throw,
+        OpCode::Rethrow,
+        OpCode::Import,
+        OpCode::GetProperty,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::Nil => "OP_NIL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Pop => "OP_POP",
+            OpCode::GetLocal => "OP_GET_LOCAL",
+            OpCode::SetLocal => "OP_SET_LOCAL",
+            OpCode::GetGlobal => "OP_GET_GLOBAL",
+            OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::SetGlobal => "OP_SET_GLOBAL",
+            OpCode::GetUpvalue => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue => "OP_SET_UPVALUE",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::NotEqual => "OP_NOT_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::GreaterEqual => "OP_GREATER_EQUAL",
+            OpCode::Less => "OP_LESS",
+            OpCode::LessEqual => "OP_LESS_EQUAL",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump => "OP_JUMP",
+            OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::Loop => "OP_LOOP",
+            OpCode::Call => "OP_CALL",
+            OpCode::Closure => "OP_CLOSURE",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Try => "OP_TRY",
+            OpCode::EndTry => "OP_END_TRY",
+            OpCode::Throw => "OP_THROW",
+            OpCode::Rethrow => "OP_RETHROW",
+            OpCode::Import => "OP_IMPORT",
+            OpCode::GetProperty => "OP_GET_PROPERTY",
+        }
+    }
+
+    /// The token an instruction was compiled from, used to build the same error reports as
+    /// the tree-walking interpreter.
+    pub fn operator_token(self) -> (TokenType, &'static str) {
+        match self {
+            OpCode::Equal => (TokenType::EqualEqual, "=="),
+            OpCode::NotEqual => (TokenType::BangEqual, "!="),
+            OpCode::Greater => (TokenType::Greater, ">"),
+            OpCode::GreaterEqual => (TokenType::GreaterEqual, ">="),
+            OpCode::Less => (TokenType::Less, "<"),
+            OpCode::LessEqual => (TokenType::LessEqual, "<="),
+            OpCode::Add => (TokenType::Plus, "+"),
+            OpCode::Subtract | OpCode::Negate => (TokenType::Minus, "-"),
+            OpCode::Multiply => (TokenType::Star, "*"),
+            OpCode::Divide => (TokenType::Slash, "/"),
+            OpCode::Not => (TokenType::Bang, "!"),
+            OpCode::Print => (TokenType::Print, "print"),
+            OpCode::Call => (TokenType::RightParen, ")"),
+            OpCode::Throw => (TokenType::Throw, "throw"),
+            OpCode::Import => (TokenType::Import, "import"),
+            _ => (TokenType::EOF, ""),
+        }
+    }
+}
+
+/// A compiled sequence of instructions with its constant pool and a line table holding the
+/// source line of every byte in `code`.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    pub lines: Vec<usize>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        self.lines.push(line);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line);
+    }
+
+    pub fn write_u16(&mut self, value: u16, line: usize) {
+        self.write((value >> 8) as u8, line);
+        self.write((value & 0xff) as u8, line);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        ((self.code[offset] as u16) << 8) | self.code[offset + 1] as u16
+    }
+
+    /// Adds a value to the constant pool, reusing an existing slot for equal strings and
+    /// numbers, and returns its index.
+    pub fn add_constant(&mut self, value: Constant) -> usize {
+        let existing = self
+            .constants
+            .iter()
+            .position(|constant| match (constant, &value) {
+                (Constant::Number(l), Constant::Number(r)) => l.to_bits() == r.to_bits(),
+                (Constant::String(l), Constant::String(r)) => l == r,
+                (Constant::Name(l), Constant::Name(r)) => l == r,
+                _ => false,
+            });
+
+        match existing {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        }
+    }
+}
@@ -0,0 +1,733 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::error::RuntimeError;
+use crate::expr::{Expr, LiteralValue};
+use crate::limits::{self, Limits, StackGuard};
+use crate::scanner::{Token, TokenType};
+use crate::stmt::Stmt;
+use crate::symbol::{intern, Symbol};
+use crate::value::{Constant, Function};
+use std::rc::Rc;
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    is_captured: bool,
+}
+
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+/// A `try` block being compiled, innermost last. A `return` inside one has to leave it, and
+/// run its `finally` block if it has one, before leaving the function.
+#[derive(Clone)]
+enum TryContext {
+    Catch,
+    Finally(Vec<Stmt>),
+}
+
+struct FunctionState {
+    function: Function,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    tries: Vec<TryContext>,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize) -> Self {
+        Self {
+            function: Function {
+                name: name.to_string(),
+                arity,
+                ..Function::default()
+            },
+            // Slot zero holds the function being called.
+            locals: vec![Local {
+                name: intern(""),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+            tries: vec![],
+        }
+    }
+}
+
+/// Compiles a parsed program into bytecode for the virtual machine.
+pub struct Compiler {
+    states: Vec<FunctionState>,
+    line: usize,
+}
+
+/// Compiles a whole script. The resulting function leaves the value of a trailing expression
+/// statement on the stack as its return value, matching `Interpreter::interpret`.
+pub fn compile(statements: &[Stmt]) -> Result<Function, RuntimeError> {
+    let _stack = StackGuard::new(Limits::default().max_stack_bytes);
+    let mut compiler = Compiler {
+        states: vec![FunctionState::new("", 0)],
+        line: 1,
+    };
+
+    let (last, rest) = match statements.split_last() {
+        Some((Stmt::Expression { expression }, rest)) => (Some(expression), rest),
+        _ => (None, statements),
+    };
+
+    for statement in rest {
+        compiler.statement(statement)?;
+    }
+
+    match last {
+        Some(expression) => compiler.expression(expression)?,
+        None => compiler.emit_op(OpCode::Nil),
+    }
+    compiler.emit_op(OpCode::Return);
+
+    let state = compiler.states.pop().expect("script state");
+    Ok(state.function)
+}
+
+impl Compiler {
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("function state")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.check_stack()?;
+        // Literals carry no line, so the code for one, folded or not, takes the line of the
+        // statement it is in rather than that of whatever was compiled before it.
+        if let Some(line) = stmt.line() {
+            self.line = line;
+        }
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.expression(expression)?;
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                self.expression(expression)?;
+                self.line = keyword.line_number;
+                self.emit_op(OpCode::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => self.emit_op(OpCode::Nil),
+                }
+                self.define_variable(name)?;
+            }
+            Stmt::Block { statements } => self.block(statements)?,
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then_branch)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(condition)?;
+
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(body)?;
+                self.emit_loop(loop_start)?;
+
+                self.patch_jump(exit_jump)?;
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer)?;
+                }
+
+                let loop_start = self.chunk().code.len();
+                let exit_jump = match condition {
+                    Some(condition) => {
+                        self.expression(condition)?;
+                        let jump = self.emit_jump(OpCode::JumpIfFalse);
+                        self.emit_op(OpCode::Pop);
+                        Some(jump)
+                    }
+                    None => None,
+                };
+
+                self.statement(body)?;
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit_op(OpCode::Pop);
+                }
+                self.emit_loop(loop_start)?;
+
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump)?;
+                    self.emit_op(OpCode::Pop);
+                }
+                self.end_scope();
+            }
+            Stmt::Function { name, params, body } => {
+                // Declare the name first so the body can refer to itself recursively.
+                if self.state().scope_depth > 0 {
+                    self.add_local(name)?;
+                }
+                self.function(name, params, body)?;
+                if self.state().scope_depth == 0 {
+                    let constant = self.identifier_constant(name)?;
+                    self.emit_op(OpCode::DefineGlobal);
+                    self.emit_u16(constant);
+                }
+            }
+            Stmt::Return { value, .. } => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.emit_op(OpCode::Nil),
+                }
+                if !self.state().tries.is_empty() {
+                    self.leave_tries()?;
+                }
+                self.emit_op(OpCode::Return);
+            }
+            Stmt::Import { path, name, .. } => {
+                let constant = self.make_constant(Constant::String(path.clone()))?;
+                self.emit_op(OpCode::Import);
+                self.emit_u16(constant);
+                self.define_variable(name)?;
+            }
+            Stmt::Throw { keyword, value } => {
+                self.expression(value)?;
+                self.line = keyword.line_number;
+                self.emit_op(OpCode::Throw);
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => match finally {
+                Some(finally) => self.try_finally(body, catch.as_ref(), finally)?,
+                None => {
+                    let (name, catch) = catch.as_ref().expect("try has a catch or finally");
+                    self.try_catch(body, name, catch)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// A handler for the body that binds the thrown value to `name`. The error object the VM
+    /// pushes above the value is kept in an unnamed local.
+    fn try_catch(
+        &mut self,
+        body: &[Stmt],
+        name: &Token,
+        catch: &[Stmt],
+    ) -> Result<(), RuntimeError> {
+        self.state().tries.push(TryContext::Catch);
+        let handler = self.emit_jump(OpCode::Try);
+        self.block(body)?;
+        self.state().tries.pop();
+        self.emit_op(OpCode::EndTry);
+        let end = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(handler)?;
+        self.begin_scope();
+        self.add_local(name)?;
+        self.add_local(&self.hidden_local())?;
+        for statement in catch {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+
+        self.patch_jump(end)
+    }
+
+    /// Runs the finally block after the body, whether or not the catch clause handled an
+    /// error. An unhandled error is raised again once the finally block is done.
+    fn try_finally(
+        &mut self,
+        body: &[Stmt],
+        catch: Option<&(Token, Vec<Stmt>)>,
+        finally: &[Stmt],
+    ) -> Result<(), RuntimeError> {
+        self.state()
+            .tries
+            .push(TryContext::Finally(finally.to_vec()));
+        let handler = self.emit_jump(OpCode::Try);
+        match catch {
+            Some((name, catch)) => self.try_catch(body, name, catch)?,
+            None => self.block(body)?,
+        }
+        self.state().tries.pop();
+        self.emit_op(OpCode::EndTry);
+        self.block(finally)?;
+        let end = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(handler)?;
+        self.begin_scope();
+        self.add_local(&self.hidden_local())?;
+        self.add_local(&self.hidden_local())?;
+        self.block(finally)?;
+        self.emit_op(OpCode::Rethrow);
+        self.discard_scope();
+
+        self.patch_jump(end)
+    }
+
+    /// Leaves every `try` block around a `return`, running their finally blocks. The value
+    /// being returned is kept in an unnamed local meanwhile.
+    fn leave_tries(&mut self) -> Result<(), RuntimeError> {
+        self.begin_scope();
+        self.add_local(&self.hidden_local())?;
+        let slot = self.state().locals.len() - 1;
+
+        let tries = std::mem::take(&mut self.state().tries);
+        for (depth, context) in tries.iter().enumerate().rev() {
+            self.emit_op(OpCode::EndTry);
+            if let TryContext::Finally(finally) = context {
+                self.state().tries = tries[..depth].to_vec();
+                self.block(finally)?;
+            }
+        }
+        self.state().tries = tries;
+
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(slot as u8);
+        self.discard_scope();
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        name: &Token,
+        params: &[Token],
+        body: &[Stmt],
+    ) -> Result<(), RuntimeError> {
+        self.states
+            .push(FunctionState::new(name.lexeme.as_str(), params.len()));
+        self.begin_scope();
+
+        for param in params {
+            self.add_local(param)?;
+        }
+        for statement in body {
+            self.statement(statement)?;
+        }
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+
+        let mut state = self.states.pop().expect("function state");
+        state.function.upvalue_count = state.upvalues.len();
+
+        let constant = self.make_constant(Constant::Function(Rc::new(state.function)))?;
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+
+        Ok(())
+    }
+
+    /// Compiles an expression by handing each kind of node to a method of its own. Deep
+    /// expressions recurse through here, so it does no work itself and keeps its frame small.
+    fn expression(&mut self, expr: &Expr) -> Result<(), RuntimeError> {
+        self.check_stack()?;
+        match expr {
+            Expr::Literal { value } => self.literal(value),
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Unary { operator, right } => self.unary(operator, right),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => self.binary(left, operator, right),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => self.logical(left, operator, right),
+            Expr::Variable { name } => {
+                self.line = name.line_number;
+                self.named_variable(name, false)
+            }
+            Expr::Get { object, name } => self.get(object, name),
+            Expr::Assign { name, value } => {
+                self.expression(value)?;
+                self.line = name.line_number;
+                self.named_variable(name, true)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.call(callee, paren, arguments),
+        }
+    }
+
+    fn literal(&mut self, value: &LiteralValue) -> Result<(), RuntimeError> {
+        match value {
+            LiteralValue::Number(n) => self.emit_constant(Constant::Number(*n))?,
+            LiteralValue::StringValue(s) => self.emit_constant(Constant::String(s.clone()))?,
+            LiteralValue::True => self.emit_op(OpCode::True),
+            LiteralValue::False => self.emit_op(OpCode::False),
+            LiteralValue::Nil => self.emit_op(OpCode::Nil),
+            LiteralValue::Function(_) | LiteralValue::Native(_) => {
+                return Err(self.error("Can't compile a function value as a literal."))
+            }
+            LiteralValue::Error(_) => {
+                return Err(self.error("Can't compile an error value as a literal."))
+            }
+            LiteralValue::Module(_) => {
+                return Err(self.error("Can't compile a module value as a literal."))
+            }
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self, operator: &Token, right: &Expr) -> Result<(), RuntimeError> {
+        self.expression(right)?;
+        self.line = operator.line_number;
+        match operator.token_type {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            TokenType::Bang => self.emit_op(OpCode::Not),
+            _ => return Err(self.error_at(operator, "Invalid unary operator.")),
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<(), RuntimeError> {
+        self.expression(left)?;
+        self.expression(right)?;
+        self.line = operator.line_number;
+        let op = match operator.token_type {
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            _ => return Err(self.error_at(operator, "Invalid binary operator.")),
+        };
+        self.emit_op(op);
+        Ok(())
+    }
+
+    fn logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<(), RuntimeError> {
+        self.expression(left)?;
+        self.line = operator.line_number;
+
+        if operator.token_type == TokenType::Or {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump)?;
+            self.emit_op(OpCode::Pop);
+            self.expression(right)?;
+            self.patch_jump(end_jump)
+        } else {
+            let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            self.expression(right)?;
+            self.patch_jump(end_jump)
+        }
+    }
+
+    fn get(&mut self, object: &Expr, name: &Token) -> Result<(), RuntimeError> {
+        self.expression(object)?;
+        self.line = name.line_number;
+        let constant = self.identifier_constant(name)?;
+        self.emit_op(OpCode::GetProperty);
+        self.emit_u16(constant);
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<(), RuntimeError> {
+        self.expression(callee)?;
+        for argument in arguments {
+            self.expression(argument)?;
+        }
+        self.line = paren.line_number;
+        self.emit_op(OpCode::Call);
+        self.emit_byte(arguments.len() as u8);
+        Ok(())
+    }
+
+    /// Fails like the parser does for code nested too deeply, if the compiler has run out
+    /// of stack. It recurses through the syntax tree with bigger frames than the parser.
+    fn check_stack(&self) -> Result<(), RuntimeError> {
+        if limits::stack_exhausted() {
+            return Err(self.error("Code is nested too deeply."));
+        }
+        Ok(())
+    }
+
+    fn named_variable(&mut self, name: &Token, assign: bool) -> Result<(), RuntimeError> {
+        let depth = self.states.len() - 1;
+
+        if let Some(slot) = self.resolve_local(depth, name.lexeme.symbol()) {
+            self.emit_op(if assign {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            });
+            self.emit_byte(slot as u8);
+        } else if let Some(index) = self.resolve_upvalue(depth, name)? {
+            self.emit_op(if assign {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            });
+            self.emit_byte(index as u8);
+        } else {
+            let constant = self.identifier_constant(name)?;
+            self.emit_op(if assign {
+                OpCode::SetGlobal
+            } else {
+                OpCode::GetGlobal
+            });
+            self.emit_u16(constant);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_local(&self, depth: usize, name: Symbol) -> Option<usize> {
+        self.states[depth]
+            .locals
+            .iter()
+            .rposition(|local| local.depth > 0 && local.name == name)
+    }
+
+    fn resolve_upvalue(
+        &mut self,
+        depth: usize,
+        name: &Token,
+    ) -> Result<Option<usize>, RuntimeError> {
+        if depth == 0 {
+            return Ok(None);
+        }
+
+        if let Some(local) = self.resolve_local(depth - 1, name.lexeme.symbol()) {
+            self.states[depth - 1].locals[local].is_captured = true;
+            return self.add_upvalue(depth, local as u8, true, name).map(Some);
+        }
+
+        match self.resolve_upvalue(depth - 1, name)? {
+            Some(upvalue) => self
+                .add_upvalue(depth, upvalue as u8, false, name)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        depth: usize,
+        index: u8,
+        is_local: bool,
+        name: &Token,
+    ) -> Result<usize, RuntimeError> {
+        let upvalues = &mut self.states[depth].upvalues;
+
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return Ok(existing);
+        }
+
+        if upvalues.len() >= MAX_UPVALUES {
+            return Err(self.error_at(name, "Too many closure variables in function."));
+        }
+
+        upvalues.push(UpvalueRef { index, is_local });
+        Ok(upvalues.len() - 1)
+    }
+
+    fn define_variable(&mut self, name: &Token) -> Result<(), RuntimeError> {
+        if self.state().scope_depth > 0 {
+            return self.add_local(name);
+        }
+
+        let constant = self.identifier_constant(name)?;
+        self.emit_op(OpCode::DefineGlobal);
+        self.emit_u16(constant);
+        Ok(())
+    }
+
+    fn add_local(&mut self, name: &Token) -> Result<(), RuntimeError> {
+        if self.state().locals.len() >= MAX_LOCALS {
+            return Err(self.error_at(name, "Too many local variables in function."));
+        }
+
+        let depth = self.state().scope_depth;
+        self.state().locals.push(Local {
+            name: name.lexeme.symbol(),
+            depth,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state().scope_depth -= 1;
+
+        loop {
+            let state = self.state();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => local.is_captured,
+                _ => break,
+            };
+            state.locals.pop();
+
+            self.emit_op(if captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+        }
+    }
+
+    /// Closes a scope whose locals the instruction just emitted removes from the stack.
+    fn discard_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        state.locals.retain(|local| local.depth <= depth);
+    }
+
+    /// A name for a slot the compiler uses itself. No identifier can refer to it.
+    fn hidden_local(&self) -> Token {
+        Token::new(TokenType::Identifier, "", None, self.line)
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> Result<u16, RuntimeError> {
+        self.make_constant(Constant::Name(name.lexeme.symbol()))
+    }
+
+    fn make_constant(&mut self, value: Constant) -> Result<u16, RuntimeError> {
+        let index = self.chunk().add_constant(value);
+        if index > u16::MAX as usize {
+            return Err(self.error("Too many constants in one chunk."));
+        }
+        Ok(index as u16)
+    }
+
+    fn emit_constant(&mut self, value: Constant) -> Result<(), RuntimeError> {
+        let constant = self.make_constant(value)?;
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(constant);
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.chunk().write(byte, line);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        let line = self.line;
+        self.chunk().write_op(op, line);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let line = self.line;
+        self.chunk().write_u16(value, line);
+    }
+
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<(), RuntimeError> {
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(self.error("Too much code to jump over."));
+        }
+
+        let code = &mut self.chunk().code;
+        code[offset] = (jump >> 8) as u8;
+        code[offset + 1] = (jump & 0xff) as u8;
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), RuntimeError> {
+        self.emit_op(OpCode::Loop);
+
+        let offset = self.chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            return Err(self.error("Loop body too large."));
+        }
+        self.emit_u16(offset as u16);
+        Ok(())
+    }
+
+    fn error(&self, message: &str) -> RuntimeError {
+        let token = Token::new(TokenType::EOF, "", None, self.line);
+        RuntimeError::new(message, self.line, token)
+    }
+
+    fn error_at(&self, token: &Token, message: &str) -> RuntimeError {
+        RuntimeError::new(message, token.line_number, token.clone())
+    }
+}
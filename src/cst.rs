@@ -0,0 +1,505 @@
+//! A lossless concrete syntax tree.
+//!
+//! Where the AST keeps only what evaluation needs, the CST keeps every token of the source.
+//! Whitespace and comments are attached as leading trivia to the token that follows them (the
+//! end-of-file token collects whatever trails the last statement), so printing a tree gives back
+//! its source byte for byte. The parser builds this tree for every source, and the AST the
+//! backends run is lowered from it with [`SyntaxNode::to_stmts`].
+
+use crate::error::LoxError;
+use crate::expr::{Expr, LiteralValue};
+use crate::parser::Parser;
+use crate::scanner::{LiteralValue as ScannerLiteralValue, Scanner, Token, TokenType};
+use crate::stmt::Stmt;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Program,
+
+    // Declarations and statements
+    VarDecl,
+    FunDecl,
+    Import,
+    ParamList,
+    /// `: Type` after a variable, a parameter or a function's parameter list.
+    TypeAnnotation,
+    Block,
+    ExprStmt,
+    For,
+    If,
+    Print,
+    Return,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    While,
+
+    // Expressions
+    Assign,
+    Binary,
+    Call,
+    ArgList,
+    Get,
+    Grouping,
+    Literal,
+    Logical,
+    Unary,
+    Variable,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    leading_trivia: Vec<Token>,
+    token: Token,
+    offset: usize,
+}
+
+impl SyntaxToken {
+    /// Attaches each run of `Whitespace` and `Comment` tokens to the token after it. Offsets
+    /// count every lexeme before the token, so they are source offsets only when the source
+    /// was scanned with its trivia.
+    pub(crate) fn group(tokens: Vec<Token>) -> Vec<SyntaxToken> {
+        let mut syntax_tokens = vec![];
+        let mut trivia = vec![];
+        let mut offset = 0;
+        for token in tokens {
+            let length = token.lexeme.as_str().chars().count();
+            match token.token_type {
+                TokenType::Whitespace | TokenType::Comment => trivia.push(token),
+                _ => syntax_tokens.push(SyntaxToken {
+                    leading_trivia: std::mem::take(&mut trivia),
+                    token,
+                    offset,
+                }),
+            }
+            offset += length;
+        }
+        syntax_tokens
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Character offsets of the token in the source, not counting its trivia.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.token.lexeme.as_str().chars().count()
+    }
+
+    /// The `Whitespace` and `Comment` tokens between the previous token and this one.
+    pub fn leading_trivia(&self) -> &[Token] {
+        &self.leading_trivia
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl From<SyntaxNode> for SyntaxElement {
+    fn from(node: SyntaxNode) -> Self {
+        SyntaxElement::Node(node)
+    }
+}
+
+impl From<SyntaxToken> for SyntaxElement {
+    fn from(token: SyntaxToken) -> Self {
+        SyntaxElement::Token(token)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxNode {
+    kind: SyntaxKind,
+    children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    pub(crate) fn new(kind: SyntaxKind, children: Vec<SyntaxElement>) -> Self {
+        Self { kind, children }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn children(&self) -> &[SyntaxElement] {
+        &self.children
+    }
+
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    pub fn child_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Token(token) => Some(&token.token),
+            SyntaxElement::Node(_) => None,
+        })
+    }
+
+    /// Every token in the subtree, in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// Character offsets of the node in the source, from its first token to its last.
+    pub fn range(&self) -> Range<usize> {
+        let tokens = self.tokens();
+        match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => first.range().start..last.range().end,
+            _ => 0..0,
+        }
+    }
+
+    /// The type annotation of a `VarDecl` node, or the return type of a `FunDecl` node.
+    pub fn type_annotation(&self) -> Option<&SyntaxNode> {
+        self.child_nodes()
+            .find(|node| node.kind == SyntaxKind::TypeAnnotation)
+    }
+
+    /// The initializer of a `VarDecl` node, if it has one.
+    pub fn initializer(&self) -> Option<&SyntaxNode> {
+        self.child_nodes()
+            .find(|node| node.kind != SyntaxKind::TypeAnnotation)
+    }
+
+    /// The body of a `FunDecl` node, which comes after its parameters and return type.
+    pub fn body(&self) -> &SyntaxNode {
+        self.child_nodes().last().expect("malformed syntax tree")
+    }
+
+    fn first_token(&self) -> &Token {
+        self.child_tokens().next().expect("malformed syntax tree")
+    }
+
+    fn nth_node(&self, n: usize) -> &SyntaxNode {
+        self.child_nodes().nth(n).expect("malformed syntax tree")
+    }
+
+    /// The statements of a `Program` or `Block` node, as the backends run them.
+    pub fn to_stmts(&self) -> Vec<Stmt> {
+        self.child_nodes().filter_map(SyntaxNode::to_stmt).collect()
+    }
+
+    /// Lowers a declaration or statement node, or returns `None` for any other kind.
+    pub fn to_stmt(&self) -> Option<Stmt> {
+        let stmt = match self.kind {
+            SyntaxKind::VarDecl => Stmt::Var {
+                name: self
+                    .child_tokens()
+                    .nth(1)
+                    .expect("malformed syntax tree")
+                    .clone(),
+                initializer: self.initializer().map(SyntaxNode::lower_expr),
+            },
+            SyntaxKind::FunDecl => Stmt::Function {
+                name: self
+                    .child_tokens()
+                    .nth(1)
+                    .expect("malformed syntax tree")
+                    .clone(),
+                params: self
+                    .nth_node(0)
+                    .child_tokens()
+                    .filter(|token| token.token_type == TokenType::Identifier)
+                    .cloned()
+                    .collect(),
+                body: Rc::new(self.body().to_stmts()),
+            },
+            SyntaxKind::Import => {
+                let mut tokens = self.child_tokens();
+                let keyword = tokens.next().expect("malformed syntax tree");
+                let path = match &tokens.next().expect("malformed syntax tree").literal {
+                    Some(ScannerLiteralValue::StringValue(path)) => path.clone(),
+                    _ => unreachable!("string tokens carry their text"),
+                };
+                Stmt::Import {
+                    keyword: keyword.clone(),
+                    path,
+                    name: tokens.nth(1).expect("malformed syntax tree").clone(),
+                }
+            }
+            SyntaxKind::Block => Stmt::Block {
+                statements: self.to_stmts(),
+            },
+            SyntaxKind::ExprStmt => Stmt::Expression {
+                expression: self.nth_node(0).lower_expr(),
+            },
+            SyntaxKind::For => self.lower_for(),
+            SyntaxKind::If => Stmt::If {
+                keyword: self.first_token().clone(),
+                condition: self.nth_node(0).lower_expr(),
+                then_branch: Box::new(self.nth_node(1).lower_stmt()),
+                else_branch: self
+                    .child_nodes()
+                    .nth(2)
+                    .map(|node| Box::new(node.lower_stmt())),
+            },
+            SyntaxKind::Print => Stmt::Print {
+                keyword: self.first_token().clone(),
+                expression: self.nth_node(0).lower_expr(),
+            },
+            SyntaxKind::Return => Stmt::Return {
+                keyword: self.first_token().clone(),
+                value: self.child_nodes().next().map(SyntaxNode::lower_expr),
+            },
+            SyntaxKind::Throw => Stmt::Throw {
+                keyword: self.first_token().clone(),
+                value: self.nth_node(0).lower_expr(),
+            },
+            SyntaxKind::Try => {
+                let clause = |kind| self.child_nodes().find(|node| node.kind == kind);
+                Stmt::Try {
+                    keyword: self.first_token().clone(),
+                    body: self.nth_node(0).to_stmts(),
+                    catch: clause(SyntaxKind::Catch).map(|catch| {
+                        let name = catch.child_tokens().nth(2).expect("malformed syntax tree");
+                        (name.clone(), catch.nth_node(0).to_stmts())
+                    }),
+                    finally: clause(SyntaxKind::Finally)
+                        .map(|finally| finally.nth_node(0).to_stmts()),
+                }
+            }
+            SyntaxKind::While => Stmt::While {
+                keyword: self.first_token().clone(),
+                condition: self.nth_node(0).lower_expr(),
+                body: Box::new(self.nth_node(1).lower_stmt()),
+            },
+            _ => return None,
+        };
+        Some(stmt)
+    }
+
+    /// Lowers an expression node, or returns `None` for any other kind.
+    pub fn to_expr(&self) -> Option<Expr> {
+        let expr = match self.kind {
+            SyntaxKind::Assign => Expr::Assign {
+                name: self.nth_node(0).first_token().clone(),
+                value: Box::new(self.nth_node(1).lower_expr()),
+            },
+            SyntaxKind::Binary => Expr::Binary {
+                left: Box::new(self.nth_node(0).lower_expr()),
+                operator: self.first_token().clone(),
+                right: Box::new(self.nth_node(1).lower_expr()),
+            },
+            SyntaxKind::Logical => Expr::Logical {
+                left: Box::new(self.nth_node(0).lower_expr()),
+                operator: self.first_token().clone(),
+                right: Box::new(self.nth_node(1).lower_expr()),
+            },
+            SyntaxKind::Call => {
+                let arguments = self.nth_node(1);
+                Expr::Call {
+                    callee: Box::new(self.nth_node(0).lower_expr()),
+                    paren: arguments
+                        .child_tokens()
+                        .last()
+                        .expect("malformed syntax tree")
+                        .clone(),
+                    arguments: arguments
+                        .child_nodes()
+                        .map(SyntaxNode::lower_expr)
+                        .collect(),
+                }
+            }
+            SyntaxKind::Get => Expr::Get {
+                object: Box::new(self.nth_node(0).lower_expr()),
+                name: self
+                    .child_tokens()
+                    .nth(1)
+                    .expect("malformed syntax tree")
+                    .clone(),
+            },
+            SyntaxKind::Grouping => Expr::Grouping {
+                expression: Box::new(self.nth_node(0).lower_expr()),
+            },
+            SyntaxKind::Literal => Expr::Literal {
+                value: literal_value(self.first_token()),
+            },
+            SyntaxKind::Unary => Expr::Unary {
+                operator: self.first_token().clone(),
+                right: Box::new(self.nth_node(0).lower_expr()),
+            },
+            SyntaxKind::Variable => Expr::Variable {
+                name: self.first_token().clone(),
+            },
+            _ => return None,
+        };
+        Some(expr)
+    }
+
+    fn lower_stmt(&self) -> Stmt {
+        self.to_stmt().expect("expected a statement node")
+    }
+
+    fn lower_expr(&self) -> Expr {
+        self.to_expr().expect("expected an expression node")
+    }
+
+    /// The initializer, condition, increment and body of a `For` node. Each clause is
+    /// optional, so the clauses are told apart by the `;` and `)` tokens around them rather
+    /// than by position.
+    pub fn for_clauses(&self) -> [Option<&SyntaxNode>; 4] {
+        let mut clauses = [None; 4];
+        let mut semicolons = 0;
+
+        for child in self.children.iter().skip(2) {
+            match child {
+                SyntaxElement::Token(token) => match token.token.token_type {
+                    TokenType::Semicolon => semicolons += 1,
+                    TokenType::RightParen => semicolons = 3,
+                    _ => {}
+                },
+                SyntaxElement::Node(node) => {
+                    clauses[semicolons] = Some(node);
+                    // The initializer statement owns its own `;`.
+                    semicolons = semicolons.max(1);
+                }
+            }
+        }
+
+        clauses
+    }
+
+    fn lower_for(&self) -> Stmt {
+        let [initializer, condition, increment, body] = self.for_clauses();
+        Stmt::For {
+            keyword: self.first_token().clone(),
+            initializer: initializer.map(|node| Box::new(node.lower_stmt())),
+            condition: condition.map(SyntaxNode::lower_expr),
+            increment: increment.map(SyntaxNode::lower_expr),
+            body: Box::new(body.expect("malformed syntax tree").lower_stmt()),
+        }
+    }
+}
+
+fn literal_value(token: &Token) -> LiteralValue {
+    match (&token.token_type, &token.literal) {
+        (TokenType::True, _) => LiteralValue::True,
+        (TokenType::False, _) => LiteralValue::False,
+        (TokenType::Number, Some(ScannerLiteralValue::FloatValue(n))) => LiteralValue::Number(*n),
+        (TokenType::String, Some(ScannerLiteralValue::StringValue(s))) => {
+            LiteralValue::StringValue(s.clone())
+        }
+        _ => LiteralValue::Nil,
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for trivia in &self.leading_trivia {
+            f.write_str(trivia.lexeme.as_str())?;
+        }
+        f.write_str(self.token.lexeme.as_str())
+    }
+}
+
+impl fmt::Display for SyntaxElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxElement::Node(node) => node.fmt(f),
+            SyntaxElement::Token(token) => token.fmt(f),
+        }
+    }
+}
+
+/// Prints the exact source text the node was parsed from.
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.children.iter().try_for_each(|child| child.fmt(f))
+    }
+}
+
+/// Parses `source`, keeping its trivia, into a `Program` node. Code nested more than
+/// `max_nesting` deep is rejected, as with [`Parser::max_nesting`].
+pub fn parse(source: &str, max_nesting: usize) -> Result<SyntaxNode, LoxError> {
+    let tokens = Scanner::new(source)
+        .with_trivia()
+        .scan_tokens()
+        .map_err(LoxError::Scan)?;
+    Parser::new(tokens)
+        .max_nesting(max_nesting)
+        .parse_tree()
+        .map_err(LoxError::Parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limits;
+
+    fn parse(source: &str) -> Result<SyntaxNode, LoxError> {
+        super::parse(source, Limits::default().max_nesting)
+    }
+
+    const PROGRAM: &str = "// Counter\r\nfun make(start: Number, step) : Function {\n\tvar n: Number = start;\n  fun next() { n = n + step; return n; }\n  return next;\n}\n\nvar c = make(0, -1); // counts down\nfor (var i = 0; i < 3; i = i + 1) print c();\nfor (;;) { if (!(c() > -10) or false) print \"héllo\"; else while (nil) {} }\ntry { throw c(); } catch (e) { print e; } finally {}\nimport \"lib.lox\" as lib; print lib.f(1).g;\n1 + 2 // no semicolon";
+
+    #[test]
+    fn round_trips_source_byte_for_byte() {
+        assert_eq!(parse(PROGRAM).unwrap().to_string(), PROGRAM);
+        assert_eq!(parse("").unwrap().to_string(), "");
+        assert_eq!(
+            parse("  // only a comment").unwrap().to_string(),
+            "  // only a comment"
+        );
+    }
+
+    #[test]
+    fn attaches_trivia_to_the_following_token() {
+        let tree = parse("var a = 1; // one\n  print a;").unwrap();
+        let print = tree.tokens()[5];
+
+        assert_eq!(print.token().token_type, TokenType::Print);
+        let trivia: Vec<&str> = print
+            .leading_trivia()
+            .iter()
+            .map(|token| token.lexeme.as_str())
+            .collect();
+        assert_eq!(trivia, [" ", "// one", "\n  "]);
+        assert_eq!(print.range(), 20..25);
+
+        let print_stmt = tree.child_nodes().nth(1).unwrap();
+        assert_eq!(print_stmt.kind(), SyntaxKind::Print);
+        assert_eq!(print_stmt.range(), 20..28);
+    }
+
+    #[test]
+    fn lowers_to_the_same_ast_with_or_without_trivia() {
+        let tokens = Scanner::new(PROGRAM).scan_tokens().unwrap();
+        let expected = Parser::new(tokens).parse().unwrap();
+
+        let tree = parse(PROGRAM).unwrap();
+        assert_eq!(tree.kind(), SyntaxKind::Program);
+        assert_eq!(format!("{:?}", tree.to_stmts()), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn rejects_code_nested_deeper_than_the_limit() {
+        assert!(super::parse("print (1) + 2;", 4).is_ok());
+        match super::parse("print ((((1))));", 4) {
+            Err(LoxError::Parse(error)) => assert_eq!(error.message, "Code is nested too deeply."),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
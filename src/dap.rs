@@ -0,0 +1,701 @@
+//! A Debug Adapter Protocol server, so that editors can drive the [debugger](crate::debugger).
+//!
+//! The server speaks DAP over a pair of streams, normally stdin and stdout, and debugs a
+//! single script on the tree-walker. A session goes `initialize`, `launch` (with `program`
+//! and optionally `stopOnEntry`), any number of `setBreakpoints`, then `configurationDone`,
+//! which starts the script. Requests are only answered between `configurationDone` and the
+//! end of the script while it is paused, since the script runs on the thread that reads them.
+//!
+//! Breakpoints can only stop where a statement starts, so those on any other line, such as
+//! a blank line or one past the end of the file, are reported unverified and never hit.
+//!
+//! Everything the script prints is forwarded as `output` events. There is a single thread,
+//! and frame ids count from 1 at the innermost frame. Each frame has a Locals scope and all
+//! share a Globals scope; variables have no children since Lox has no compound values.
+
+use crate::debugger::{self, Debugger, Frontend, PauseReason, Paused, Resume};
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+use crate::json::Json;
+use crate::protocol;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const THREAD_ID: f64 = 1.0;
+/// The variables reference of the Globals scope. Frame `id`'s locals use `id + 1`.
+const GLOBALS_REFERENCE: f64 = 1.0;
+
+/// Serves one debug session on `input` and `output`, returning once the client disconnects or
+/// the input ends.
+pub fn serve<R, W>(input: R, output: W) -> Result<(), LoxError>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    let connection = Rc::new(RefCell::new(Connection {
+        input: Box::new(input),
+        output: Box::new(output),
+        seq: 0,
+        disconnected: false,
+    }));
+
+    let mut launch = None;
+    let mut breakpoints = vec![];
+    loop {
+        let Some(request) = connection.borrow_mut().read()? else {
+            return Ok(());
+        };
+        let mut connection = connection.borrow_mut();
+        match command(&request) {
+            "initialize" => {
+                let capabilities = Json::object([
+                    ("supportsConfigurationDoneRequest", Json::Bool(true)),
+                    ("supportsEvaluateForHovers", Json::Bool(true)),
+                ]);
+                connection.respond(&request, capabilities)?;
+                connection.event("initialized", Json::object([]))?;
+            }
+            "launch" => {
+                let arguments = arguments(&request);
+                match arguments.get("program").and_then(Json::as_str) {
+                    Some(program) => {
+                        let stop_on_entry = arguments.get("stopOnEntry").and_then(Json::as_bool);
+                        launch = Some((program.to_string(), stop_on_entry.unwrap_or(false)));
+                        connection.respond(&request, Json::Null)?;
+                    }
+                    None => connection.fail(&request, "launch needs a program to debug.")?,
+                }
+            }
+            "setBreakpoints" => {
+                let source = launch
+                    .as_ref()
+                    .and_then(|(program, _)| fs::read_to_string(program).ok());
+                let valid = debugger::statement_lines(&source.unwrap_or_default());
+                let lines = requested_lines(&request);
+                breakpoints = lines
+                    .iter()
+                    .copied()
+                    .filter(|line| valid.contains(line))
+                    .collect();
+                connection.respond(&request, breakpoints_body(&lines, &valid))?;
+            }
+            "threads" => connection.respond(&request, threads_body())?,
+            "configurationDone" => {
+                connection.respond(&request, Json::Null)?;
+                break;
+            }
+            "disconnect" | "terminate" => return connection.respond(&request, Json::Null),
+            name => {
+                connection.fail(&request, &format!("Can't {} before the script runs.", name))?
+            }
+        }
+    }
+
+    let Some((program, stop_on_entry)) = launch else {
+        let mut connection = connection.borrow_mut();
+        connection.event("terminated", Json::object([]))?;
+        return finish(&mut connection);
+    };
+    let exit_code = run(&connection, &program, stop_on_entry, breakpoints)?;
+
+    let mut connection = connection.borrow_mut();
+    if connection.disconnected {
+        return Ok(());
+    }
+    connection.event(
+        "exited",
+        Json::object([("exitCode", Json::Number(exit_code))]),
+    )?;
+    connection.event("terminated", Json::object([]))?;
+    finish(&mut connection)
+}
+
+/// Runs the script under the debugger, returning its exit code.
+fn run(
+    connection: &Rc<RefCell<Connection>>,
+    program: &str,
+    stop_on_entry: bool,
+    breakpoints: Vec<usize>,
+) -> Result<f64, LoxError> {
+    let source = match fs::read_to_string(program) {
+        Ok(source) => source,
+        Err(error) => {
+            let message = format!("Error: {}: {}\n", program, error);
+            connection.borrow_mut().output("stderr", &message)?;
+            return Ok(74.0);
+        }
+    };
+
+    let mut interpreter = Interpreter::with_io(io::empty(), OutputEvents::new(connection));
+    let mut debugger = Debugger::new(Adapter {
+        connection: connection.clone(),
+        program: program.to_string(),
+        lines: debugger::statement_lines(&source),
+    })
+    .with_breakpoints(breakpoints);
+    if stop_on_entry {
+        debugger = debugger.stop_on_entry();
+    }
+    interpreter.set_debugger(debugger);
+    let result = interpreter.eval_file(program, &source);
+
+    let mut connection = connection.borrow_mut();
+    Ok(match result {
+        Ok(_) => 0.0,
+        Err(_) if connection.disconnected => 0.0,
+        Err(error) => {
+            connection.output("stderr", &format!("Error: {}\n", error.report()))?;
+            match error {
+                LoxError::Runtime(_) => 70.0,
+                _ => 65.0,
+            }
+        }
+    })
+}
+
+/// Answers requests after the script has ended until the client disconnects.
+fn finish(connection: &mut Connection) -> Result<(), LoxError> {
+    while let Some(request) = connection.read()? {
+        match command(&request) {
+            "disconnect" | "terminate" => return connection.respond(&request, Json::Null),
+            "threads" => connection.respond(&request, threads_body())?,
+            _ => connection.fail(&request, "The script has ended.")?,
+        }
+    }
+    Ok(())
+}
+
+struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u64,
+    /// Whether the client asked to end the session while the script was paused.
+    disconnected: bool,
+}
+
+impl Connection {
+    fn read(&mut self) -> Result<Option<Json>, LoxError> {
+        protocol::read_message(&mut *self.input)
+    }
+
+    fn send(&mut self, kind: &str, mut fields: Vec<(&str, Json)>) -> Result<(), LoxError> {
+        self.seq += 1;
+        fields.splice(
+            0..0,
+            [
+                ("seq", Json::Number(self.seq as f64)),
+                ("type", Json::String(kind.to_string())),
+            ],
+        );
+        protocol::write_message(&mut *self.output, &Json::object(fields))
+    }
+
+    fn reply(&mut self, request: &Json, fields: Vec<(&str, Json)>) -> Result<(), LoxError> {
+        let mut reply = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("command", Json::String(command(request).to_string())),
+        ];
+        reply.extend(fields);
+        self.send("response", reply)
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> Result<(), LoxError> {
+        let mut fields = vec![("success", Json::Bool(true))];
+        if body != Json::Null {
+            fields.push(("body", body));
+        }
+        self.reply(request, fields)
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> Result<(), LoxError> {
+        self.reply(
+            request,
+            vec![
+                ("success", Json::Bool(false)),
+                ("message", Json::String(message.to_string())),
+            ],
+        )
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), LoxError> {
+        self.send(
+            "event",
+            vec![("event", Json::String(event.to_string())), ("body", body)],
+        )
+    }
+
+    fn output(&mut self, category: &str, text: &str) -> Result<(), LoxError> {
+        let body = Json::object([
+            ("category", Json::String(category.to_string())),
+            ("output", Json::String(text.to_string())),
+        ]);
+        self.event("output", body)
+    }
+}
+
+/// Sends what the script prints as `output` events, a line at a time.
+struct OutputEvents {
+    connection: Rc<RefCell<Connection>>,
+    buffer: Vec<u8>,
+}
+
+impl OutputEvents {
+    fn new(connection: &Rc<RefCell<Connection>>) -> Self {
+        Self {
+            connection: connection.clone(),
+            buffer: vec![],
+        }
+    }
+
+    fn send(&mut self, end: usize) -> io::Result<()> {
+        let text: Vec<u8> = self.buffer.drain(..end).collect();
+        self.connection
+            .borrow_mut()
+            .output("stdout", &String::from_utf8_lossy(&text))
+            .map_err(|e| io::Error::other(e.report()))
+    }
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if let Some(newline) = self.buffer.iter().rposition(|&b| b == b'\n') {
+            self.send(newline + 1)?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send(self.buffer.len())
+    }
+}
+
+/// The debugger frontend: reports pauses as `stopped` events and answers requests until the
+/// client resumes the script.
+struct Adapter {
+    connection: Rc<RefCell<Connection>>,
+    program: String,
+    /// The lines of the program a breakpoint can stop on.
+    lines: BTreeSet<usize>,
+}
+
+impl Adapter {
+    /// Answers one request, returning how to resume if it ends the pause.
+    fn handle(&self, paused: &mut Paused<'_>, request: &Json) -> Result<Option<Resume>, LoxError> {
+        let mut connection = self.connection.borrow_mut();
+        let arguments = arguments(request);
+        let resume = match command(request) {
+            "continue" => Resume::Continue,
+            "next" => Resume::StepOver,
+            "stepIn" => Resume::StepIn,
+            "stepOut" => Resume::StepOut,
+            "disconnect" | "terminate" => {
+                connection.disconnected = true;
+                Resume::Stop
+            }
+            "threads" => {
+                connection.respond(request, threads_body())?;
+                return Ok(None);
+            }
+            "stackTrace" => {
+                let source = Json::object([
+                    ("name", Json::String(self.program.clone())),
+                    ("path", Json::String(self.program.clone())),
+                ]);
+                let frames: Vec<Json> = paused
+                    .stack()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, frame)| {
+                        Json::object([
+                            ("id", Json::Number(index as f64 + 1.0)),
+                            ("name", Json::String(frame.name)),
+                            ("source", source.clone()),
+                            ("line", Json::Number(frame.line as f64)),
+                            ("column", Json::Number(1.0)),
+                        ])
+                    })
+                    .collect();
+                let body = Json::object([
+                    ("totalFrames", Json::Number(frames.len() as f64)),
+                    ("stackFrames", Json::Array(frames)),
+                ]);
+                connection.respond(request, body)?;
+                return Ok(None);
+            }
+            "scopes" => {
+                let frame_id = number(arguments, "frameId").unwrap_or(1.0);
+                let scope = |name: &str, reference: f64, expensive: bool| {
+                    Json::object([
+                        ("name", Json::String(name.to_string())),
+                        ("variablesReference", Json::Number(reference)),
+                        ("expensive", Json::Bool(expensive)),
+                    ])
+                };
+                let scopes = vec![
+                    scope("Locals", frame_id + 1.0, false),
+                    scope("Globals", GLOBALS_REFERENCE, true),
+                ];
+                connection.respond(request, Json::object([("scopes", Json::Array(scopes))]))?;
+                return Ok(None);
+            }
+            "variables" => {
+                let reference = number(arguments, "variablesReference").unwrap_or(0.0);
+                let variables = if reference == GLOBALS_REFERENCE {
+                    paused.globals()
+                } else if reference >= 2.0 {
+                    paused.locals(reference as usize - 2)
+                } else {
+                    vec![]
+                };
+                let variables = variables
+                    .into_iter()
+                    .map(|(name, value)| {
+                        Json::object([
+                            ("name", Json::String(name.to_string())),
+                            ("value", Json::String(value)),
+                            ("variablesReference", Json::Number(0.0)),
+                        ])
+                    })
+                    .collect();
+                let body = Json::object([("variables", Json::Array(variables))]);
+                connection.respond(request, body)?;
+                return Ok(None);
+            }
+            "evaluate" => {
+                let frame = number(arguments, "frameId").map_or(0, |id| id as usize - 1);
+                let expression = arguments
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or_default();
+                // The script's output goes through the connection, which is borrowed here.
+                drop(connection);
+                let result = paused.evaluate(frame, expression);
+                let mut connection = self.connection.borrow_mut();
+                match result {
+                    Ok(value) => {
+                        let body = Json::object([
+                            ("result", Json::String(value.to_string())),
+                            ("variablesReference", Json::Number(0.0)),
+                        ]);
+                        connection.respond(request, body)?;
+                    }
+                    Err(error) => connection.fail(request, &error.report())?,
+                }
+                return Ok(None);
+            }
+            "setBreakpoints" => {
+                let lines = requested_lines(request);
+                let old: Vec<usize> = paused.breakpoints().collect();
+                for line in old {
+                    paused.clear_breakpoint(line);
+                }
+                for &line in lines.iter().filter(|line| self.lines.contains(line)) {
+                    paused.set_breakpoint(line);
+                }
+                connection.respond(request, breakpoints_body(&lines, &self.lines))?;
+                return Ok(None);
+            }
+            name => {
+                connection.fail(request, &format!("Unsupported request '{}'.", name))?;
+                return Ok(None);
+            }
+        };
+
+        let body = match resume {
+            Resume::Continue => Json::object([("allThreadsContinued", Json::Bool(true))]),
+            _ => Json::Null,
+        };
+        connection.respond(request, body)?;
+        Ok(Some(resume))
+    }
+}
+
+impl Frontend for Adapter {
+    fn paused(&mut self, paused: &mut Paused<'_>) -> Resume {
+        let reason = match paused.reason() {
+            PauseReason::Entry => "entry",
+            PauseReason::Breakpoint => "breakpoint",
+            PauseReason::Step => "step",
+        };
+        let stopped = Json::object([
+            ("reason", Json::String(reason.to_string())),
+            ("threadId", Json::Number(THREAD_ID)),
+            ("allThreadsStopped", Json::Bool(true)),
+        ]);
+        if self
+            .connection
+            .borrow_mut()
+            .event("stopped", stopped)
+            .is_err()
+        {
+            return Resume::Stop;
+        }
+
+        loop {
+            let request = self.connection.borrow_mut().read();
+            let resume = match request {
+                Ok(Some(request)) => self.handle(paused, &request),
+                // Without a client there is no one to debug for.
+                Ok(None) | Err(_) => {
+                    self.connection.borrow_mut().disconnected = true;
+                    return Resume::Stop;
+                }
+            };
+            match resume {
+                Ok(Some(resume)) => return resume,
+                Ok(None) => {}
+                Err(_) => {
+                    self.connection.borrow_mut().disconnected = true;
+                    return Resume::Stop;
+                }
+            }
+        }
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request
+        .get("command")
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+}
+
+fn arguments(request: &Json) -> &Json {
+    request.get("arguments").unwrap_or(&Json::Null)
+}
+
+fn number(arguments: &Json, key: &str) -> Option<f64> {
+    arguments.get(key).and_then(Json::as_f64)
+}
+
+fn requested_lines(request: &Json) -> Vec<usize> {
+    let breakpoints = arguments(request).get("breakpoints");
+    breakpoints
+        .and_then(Json::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|breakpoint| number(breakpoint, "line"))
+        .map(|line| line as usize)
+        .collect()
+}
+
+/// Reports which of the requested `lines` have a statement, out of the `valid` ones, for a
+/// breakpoint to stop at.
+fn breakpoints_body(lines: &[usize], valid: &BTreeSet<usize>) -> Json {
+    let breakpoints = lines
+        .iter()
+        .map(|&line| {
+            let verified = valid.contains(&line);
+            let mut fields = vec![
+                ("verified", Json::Bool(verified)),
+                ("line", Json::Number(line as f64)),
+            ];
+            if !verified {
+                let message = "No statement starts on this line.".to_string();
+                fields.push(("message", Json::String(message)));
+            }
+            Json::object(fields)
+        })
+        .collect();
+    Json::object([("breakpoints", Json::Array(breakpoints))])
+}
+
+fn threads_body() -> Json {
+    let thread = Json::object([
+        ("id", Json::Number(THREAD_ID)),
+        ("name", Json::String("main".to_string())),
+    ]);
+    Json::object([("threads", Json::Array(vec![thread]))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use crate::stream::SharedBuffer;
+    use std::io::Cursor;
+
+    /// Frames each request the way a client would and runs a session over them.
+    fn session(requests: &[&str]) -> Vec<Json> {
+        let mut input = String::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let (command, arguments) = request.split_once(' ').unwrap_or((request, "{}"));
+            let message = format!(
+                r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+                seq + 1,
+                command,
+                arguments
+            );
+            input += &format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+        }
+
+        let output = SharedBuffer::new();
+        serve(Cursor::new(input), output.clone()).unwrap();
+        output
+            .contents()
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|message| json::parse(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    /// Summarizes each message as `event name` or `response command`, with its body.
+    fn summary(messages: &[Json]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| {
+                let name = message
+                    .get("event")
+                    .or(message.get("command"))
+                    .and_then(Json::as_str)
+                    .unwrap();
+                let body = match (message.get("success"), message.get("body")) {
+                    (Some(Json::Bool(false)), _) => {
+                        format!(" failed: {}", message.get("message").unwrap().to_compact())
+                    }
+                    (_, Some(body)) => format!(" {}", body.to_compact()),
+                    _ => String::new(),
+                };
+                format!("{}{}", name, body)
+            })
+            .collect()
+    }
+
+    fn program(name: &str, source: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("rlox-dap-{}-{}.lox", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path.to_str().unwrap().replace('\\', "/")
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_inspects_the_program() {
+        let path = program(
+            "breakpoints",
+            "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nvar x = 1;\nprint add(x, 2);\n",
+        );
+        let messages = session(&[
+            "initialize",
+            &format!(r#"launch {{"program":"{}"}}"#, path),
+            r#"setBreakpoints {"source":{"path":"x"},"breakpoints":[{"line":3}]}"#,
+            "configurationDone",
+            "stackTrace",
+            r#"scopes {"frameId":2}"#,
+            r#"variables {"variablesReference":2}"#,
+            r#"variables {"variablesReference":3}"#,
+            r#"evaluate {"expression":"sum * x","frameId":1}"#,
+            r#"evaluate {"expression":"sum","frameId":2}"#,
+            "stepOut",
+            "continue",
+            "disconnect",
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let source = format!(r#"{{"name":"{0}","path":"{0}"}}"#, path);
+        assert_eq!(
+            summary(&messages),
+            [
+                r#"initialize {"supportsConfigurationDoneRequest":true,"supportsEvaluateForHovers":true}"#.to_string(),
+                "initialized {}".to_string(),
+                "launch".to_string(),
+                r#"setBreakpoints {"breakpoints":[{"verified":true,"line":3}]}"#.to_string(),
+                "configurationDone".to_string(),
+                r#"stopped {"reason":"breakpoint","threadId":1,"allThreadsStopped":true}"#.to_string(),
+                format!(
+                    r#"stackTrace {{"totalFrames":2,"stackFrames":[{{"id":1,"name":"add","source":{0},"line":3,"column":1}},{{"id":2,"name":"script","source":{0},"line":6,"column":1}}]}}"#,
+                    source
+                ),
+                r#"scopes {"scopes":[{"name":"Locals","variablesReference":3,"expensive":false},{"name":"Globals","variablesReference":1,"expensive":true}]}"#.to_string(),
+                r#"variables {"variables":[{"name":"a","value":"1","variablesReference":0},{"name":"b","value":"2","variablesReference":0},{"name":"sum","value":"3","variablesReference":0}]}"#.to_string(),
+                r#"variables {"variables":[]}"#.to_string(),
+                r#"evaluate {"result":"3","variablesReference":0}"#.to_string(),
+                r#"evaluate failed: "LINE 1: ('sum') Undefined variable 'sum'.""#.to_string(),
+                "stepOut".to_string(),
+                r#"output {"category":"stdout","output":"3\n"}"#.to_string(),
+                r#"exited {"exitCode":0}"#.to_string(),
+                "terminated {}".to_string(),
+                r#"continue failed: "The script has ended.""#.to_string(),
+                "disconnect".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_from_entry_and_disconnects_while_paused() {
+        let path = program("steps", "var a = 1;\nprint a;\nprint a + 1;\n");
+        let messages = session(&[
+            "initialize",
+            &format!(r#"launch {{"program":"{}","stopOnEntry":true}}"#, path),
+            "configurationDone",
+            "next",
+            "next",
+            "disconnect",
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let summary = summary(&messages);
+        assert_eq!(
+            summary[4..],
+            [
+                r#"stopped {"reason":"entry","threadId":1,"allThreadsStopped":true}"#,
+                "next",
+                r#"stopped {"reason":"step","threadId":1,"allThreadsStopped":true}"#,
+                "next",
+                r#"output {"category":"stdout","output":"1\n"}"#,
+                r#"stopped {"reason":"step","threadId":1,"allThreadsStopped":true}"#,
+                "disconnect",
+            ]
+        );
+    }
+    #[test]
+    fn breakpoints_without_a_statement_are_unverified() {
+        let path = program("unverified", "var a = 1;\n\nprint a;\n");
+        let messages = session(&[
+            "initialize",
+            &format!(r#"launch {{"program":"{}","stopOnEntry":true}}"#, path),
+            r#"setBreakpoints {"breakpoints":[{"line":2},{"line":3},{"line":9}]}"#,
+            "configurationDone",
+            r#"setBreakpoints {"breakpoints":[{"line":1},{"line":4}]}"#,
+            "continue",
+        ]);
+        let _ = fs::remove_file(&path);
+
+        let unverified = |line| {
+            format!(
+                r#"{{"verified":false,"line":{},"message":"No statement starts on this line."}}"#,
+                line
+            )
+        };
+        let summary = summary(&messages);
+        assert_eq!(
+            summary[3..],
+            [
+                format!(
+                    r#"setBreakpoints {{"breakpoints":[{},{{"verified":true,"line":3}},{}]}}"#,
+                    unverified(2),
+                    unverified(9)
+                ),
+                "configurationDone".to_string(),
+                r#"stopped {"reason":"entry","threadId":1,"allThreadsStopped":true}"#.to_string(),
+                format!(
+                    r#"setBreakpoints {{"breakpoints":[{{"verified":true,"line":1}},{}]}}"#,
+                    unverified(4)
+                ),
+                r#"continue {"allThreadsContinued":true}"#.to_string(),
+                r#"output {"category":"stdout","output":"1\n"}"#.to_string(),
+                r#"exited {"exitCode":0}"#.to_string(),
+                "terminated {}".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::chunk::{Chunk, OpCode};
+use crate::gc::Heap;
+use crate::value::{Constant, Function, Value};
+use std::fmt::Write;
+
+/// Renders every instruction of `function`, followed by the listings of any functions
+/// nested in its constant pool.
+pub fn disassemble_function(function: &Function) -> String {
+    let mut listing = disassemble_chunk(&function.chunk, &function.to_string());
+
+    for constant in &function.chunk.constants {
+        if let Constant::Function(nested) = constant {
+            listing.push('\n');
+            listing.push_str(&disassemble_function(nested));
+        }
+    }
+
+    listing
+}
+
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut listing = format!("== {} ==\n", name);
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (line, next) = disassemble_instruction(chunk, offset);
+        listing.push_str(&line);
+        listing.push('\n');
+        offset = next;
+    }
+
+    listing
+}
+
+/// Formats the instruction at `offset` as `offset line name operands` and returns it with
+/// the offset of the following instruction. A `|` in the line column means the line is the
+/// same as the previous instruction's.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let mut text = format!("{:04} ", offset);
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        text.push_str("   | ");
+    } else {
+        let _ = write!(text, "{:4} ", chunk.lines[offset]);
+    }
+
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => {
+            let _ = write!(text, "Unknown opcode {}", chunk.code[offset]);
+            return (text, offset + 1);
+        }
+    };
+
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::Import
+        | OpCode::GetProperty => {
+            let constant = chunk.read_u16(offset + 1);
+            let _ = write!(
+                text,
+                "{:<16} {:4} '{}'",
+                op.name(),
+                constant,
+                chunk.constants[constant as usize]
+            );
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            let _ = write!(text, "{:<16} {:4}", op.name(), chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Try => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let _ = write!(
+                text,
+                "{:<16} {:4} -> {}",
+                op.name(),
+                offset,
+                offset + 3 + jump
+            );
+            offset + 3
+        }
+        OpCode::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let _ = write!(
+                text,
+                "{:<16} {:4} -> {}",
+                op.name(),
+                offset,
+                offset + 3 - jump
+            );
+            offset + 3
+        }
+        OpCode::Closure => {
+            let constant = chunk.read_u16(offset + 1);
+            let value = &chunk.constants[constant as usize];
+            let _ = write!(text, "{:<16} {:4} {}", op.name(), constant, value);
+
+            let mut next = offset + 3;
+            if let Constant::Function(function) = value {
+                for _ in 0..function.upvalue_count {
+                    let kind = if chunk.code[next] == 1 {
+                        "local"
+                    } else {
+                        "upvalue"
+                    };
+                    let _ = write!(
+                        text,
+                        "\n{:04}    |                     {} {}",
+                        next,
+                        kind,
+                        chunk.code[next + 1]
+                    );
+                    next += 2;
+                }
+            }
+            next
+        }
+        _ => {
+            text.push_str(op.name());
+            offset + 1
+        }
+    };
+
+    (text, next)
+}
+
+/// Formats the value stack as `[ a ][ b ]`, bottom first.
+pub fn format_stack(stack: &[Value], heap: &Heap) -> String {
+    let mut text = String::from("          ");
+    for &value in stack {
+        let _ = write!(text, "[ {} ]", heap.display(value));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::optimizer;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn disassembles_with_offsets_lines_and_operands() {
+        let tokens = Scanner::new("var a = 1;\nprint a + 2;")
+            .scan_tokens()
+            .unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let function = compile(&statements).unwrap();
+
+        assert_eq!(
+            disassemble_function(&function),
+            "== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_GET_GLOBAL       1 'a'
+0009    | OP_CONSTANT         2 '2'
+0012    | OP_ADD
+0013    | OP_PRINT
+0014    | OP_NIL
+0015    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn literals_take_the_line_of_their_statement() {
+        let tokens = Scanner::new("var a = 1;\nprint 2 + 3;\nthrow \"x\";")
+            .scan_tokens()
+            .unwrap();
+        let statements = optimizer::optimize(Parser::new(tokens).parse().unwrap());
+        let function = compile(&statements).unwrap();
+
+        assert_eq!(
+            disassemble_function(&function),
+            "== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_CONSTANT         2 '5'
+0009    | OP_PRINT
+0010    3 OP_CONSTANT         3 'x'
+0013    | OP_THROW
+0014    | OP_NIL
+0015    | OP_RETURN
+"
+        );
+    }
+}
@@ -0,0 +1,536 @@
+//! A source-level debugger for the tree-walker.
+//!
+//! A [`Debugger`] attached with [`Interpreter::set_debugger`] is consulted before every
+//! statement that starts on a line. Its breakpoints and the last step command decide whether
+//! to pause there; if so it hands control to a [`Frontend`], which inspects the program
+//! through [`Paused`] and says how to resume. [`Console`] is the command-line frontend behind
+//! `rlox debug`.
+
+pub use crate::error::StackFrame;
+use crate::error::{LoxError, RuntimeError};
+use crate::expr::LiteralValue;
+use crate::interpreter::Interpreter;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::stmt::Stmt;
+use crate::symbol::Symbol;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+const PROMPT: &str = "(debug) ";
+
+const HELP: &str = "\
+break <line>    b   Pause before statements on <line>
+delete <line>   d   Remove the breakpoint on <line>
+continue        c   Run until the next breakpoint
+step            s   Run to the next statement, entering calls
+next            n   Run to the next statement without entering calls
+out             o   Run until the current function returns
+backtrace       bt  Show the call stack
+frame <n>       f   Select frame <n> of the call stack
+locals          l   Show the local variables of the selected frame
+globals         g   Show the global variables
+print <source>  p   Run source in the selected frame and show its value
+list                Show the source around the paused line
+quit            q   Stop the script
+help            h   Show this list
+An empty line repeats the last command.
+";
+
+/// How execution carries on after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until a breakpoint.
+    Continue,
+    /// Pause at the next statement, wherever it is.
+    StepIn,
+    /// Pause at the next statement that isn't in a function called from here.
+    StepOver,
+    /// Pause at the next statement after the current function returns.
+    StepOut,
+    /// Abandon the script with a runtime error.
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// Decides what to do while the program is paused.
+pub trait Frontend {
+    fn paused(&mut self, paused: &mut Paused<'_>) -> Resume;
+}
+
+pub struct Debugger {
+    frontend: Box<dyn Frontend>,
+    breakpoints: BTreeSet<usize>,
+    resume: Resume,
+    /// The stack depth when execution last resumed, which stepping over and out compare to.
+    depth: usize,
+    stop_on_entry: bool,
+}
+
+impl Debugger {
+    pub fn new<F: Frontend + 'static>(frontend: F) -> Self {
+        Self {
+            frontend: Box::new(frontend),
+            breakpoints: BTreeSet::new(),
+            resume: Resume::Continue,
+            depth: 0,
+            stop_on_entry: false,
+        }
+    }
+
+    /// Pauses before the first statement, so that breakpoints can be set before anything runs.
+    pub fn stop_on_entry(mut self) -> Self {
+        self.stop_on_entry = true;
+        self
+    }
+
+    pub fn with_breakpoints<I: IntoIterator<Item = usize>>(mut self, lines: I) -> Self {
+        self.breakpoints.extend(lines);
+        self
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Called by the interpreter before each statement that starts on `line`.
+    pub(crate) fn on_statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: usize,
+    ) -> Result<(), RuntimeError> {
+        let depth = interpreter.stack_depth();
+        let reason = if std::mem::take(&mut self.stop_on_entry) {
+            PauseReason::Entry
+        } else if self.breakpoints.contains(&line) {
+            PauseReason::Breakpoint
+        } else {
+            match self.resume {
+                Resume::StepIn => PauseReason::Step,
+                Resume::StepOver if depth <= self.depth => PauseReason::Step,
+                Resume::StepOut if depth < self.depth => PauseReason::Step,
+                _ => return Ok(()),
+            }
+        };
+
+        let mut paused = Paused {
+            interpreter,
+            line,
+            reason,
+            breakpoints: &mut self.breakpoints,
+        };
+        self.resume = self.frontend.paused(&mut paused);
+        self.depth = depth;
+
+        if self.resume == Resume::Stop {
+            let token = Token::new(TokenType::EOF, "", None, line);
+            return Err(RuntimeError::new("Stopped by the debugger.", line, token).fatal());
+        }
+        Ok(())
+    }
+}
+
+/// A program paused before a statement. Frames are numbered from the innermost, 0.
+pub struct Paused<'a> {
+    interpreter: &'a mut Interpreter,
+    line: usize,
+    reason: PauseReason,
+    breakpoints: &'a mut BTreeSet<usize>,
+}
+
+impl Paused<'_> {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn reason(&self) -> PauseReason {
+        self.reason
+    }
+
+    /// The call stack, innermost frame first.
+    pub fn stack(&self) -> Vec<StackFrame> {
+        self.interpreter.stack()
+    }
+
+    /// The variables in scope in `frame` other than globals, innermost scope first, with their
+    /// values as `print` shows them.
+    pub fn locals(&self, frame: usize) -> Vec<(Symbol, String)> {
+        self.interpreter.locals(frame)
+    }
+
+    pub fn globals(&self) -> Vec<(Symbol, String)> {
+        self.interpreter.globals()
+    }
+
+    /// Runs `source` in the scope of `frame`, returning the value of a trailing expression.
+    /// Breakpoints don't fire while it runs.
+    pub fn evaluate(&mut self, frame: usize, source: &str) -> Result<LiteralValue, LoxError> {
+        self.interpreter.eval_in_frame(frame, source)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Returns false if there already was a breakpoint on `line`.
+    pub fn set_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.insert(line)
+    }
+
+    /// Returns false if there was no breakpoint on `line`.
+    pub fn clear_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.remove(&line)
+    }
+}
+
+/// The lines a breakpoint in `source` can stop on: those where a statement starts, after the
+/// optimizer has run as it does by default. Source that doesn't parse has none.
+pub fn statement_lines(source: &str) -> BTreeSet<usize> {
+    let mut lines = BTreeSet::new();
+    let Ok(tokens) = Scanner::new(source).scan_tokens() else {
+        return lines;
+    };
+    if let Ok(statements) = Parser::new(tokens).parse() {
+        add_statement_lines(&optimizer::optimize(statements), &mut lines);
+    }
+    lines
+}
+
+fn add_statement_lines(statements: &[Stmt], lines: &mut BTreeSet<usize>) {
+    for statement in statements {
+        lines.extend(statement.line());
+        match statement {
+            Stmt::Block { statements } => add_statement_lines(statements, lines),
+            Stmt::For {
+                initializer, body, ..
+            } => {
+                if let Some(initializer) = initializer {
+                    add_statement_lines(std::slice::from_ref(initializer), lines);
+                }
+                add_statement_lines(std::slice::from_ref(body), lines);
+            }
+            Stmt::Function { body, .. } => add_statement_lines(body, lines),
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                add_statement_lines(std::slice::from_ref(then_branch), lines);
+                if let Some(else_branch) = else_branch {
+                    add_statement_lines(std::slice::from_ref(else_branch), lines);
+                }
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                add_statement_lines(body, lines);
+                if let Some((_, catch)) = catch {
+                    add_statement_lines(catch, lines);
+                }
+                if let Some(finally) = finally {
+                    add_statement_lines(finally, lines);
+                }
+            }
+            Stmt::While { body, .. } => add_statement_lines(std::slice::from_ref(body), lines),
+            Stmt::Expression { .. }
+            | Stmt::Print { .. }
+            | Stmt::Return { .. }
+            | Stmt::Import { .. }
+            | Stmt::Throw { .. }
+            | Stmt::Var { .. } => {}
+        }
+    }
+}
+
+/// A line-oriented frontend that reads commands from `input` and reports to `output`; `help`
+/// lists the commands.
+pub struct Console {
+    lines: Vec<String>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    frame: usize,
+    last_command: String,
+}
+
+impl Console {
+    /// `source` is the script being debugged, which is quoted when the program pauses.
+    pub fn new<R, W>(source: &str, input: R, output: W) -> Self
+    where
+        R: BufRead + 'static,
+        W: Write + 'static,
+    {
+        Self {
+            lines: source.lines().map(str::to_string).collect(),
+            input: Box::new(input),
+            output: Box::new(output),
+            frame: 0,
+            last_command: String::new(),
+        }
+    }
+
+    fn say(&mut self, text: &str) {
+        let _ = write!(self.output, "{}", text);
+    }
+
+    /// The source lines from `start` to `end` inclusive, numbered, with the paused line marked.
+    fn listing(&self, start: usize, end: usize, current: usize) -> String {
+        (start.max(1)..=end.min(self.lines.len()))
+            .map(|line| {
+                let marker = if line == current { '>' } else { ' ' };
+                format!("{}{:>4} | {}\n", marker, line, self.lines[line - 1])
+            })
+            .collect()
+    }
+
+    fn variables(variables: Vec<(Symbol, String)>) -> String {
+        if variables.is_empty() {
+            return "No variables.\n".to_string();
+        }
+        variables
+            .into_iter()
+            .map(|(name, value)| format!("{} = {}\n", name, value))
+            .collect()
+    }
+
+    /// Runs one command, returning how to resume if it ends the pause.
+    fn command(&mut self, paused: &mut Paused<'_>, line: &str) -> Option<Resume> {
+        let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        let text = match name {
+            "c" | "continue" => return Some(Resume::Continue),
+            "s" | "step" => return Some(Resume::StepIn),
+            "n" | "next" => return Some(Resume::StepOver),
+            "o" | "out" => return Some(Resume::StepOut),
+            "q" | "quit" => return Some(Resume::Stop),
+            "b" | "break" => match argument.parse() {
+                Ok(line) => {
+                    paused.set_breakpoint(line);
+                    format!("Breakpoint on line {}.\n", line)
+                }
+                Err(_) => "Usage: break <line>\n".to_string(),
+            },
+            "d" | "delete" => match argument.parse() {
+                Ok(line) if paused.clear_breakpoint(line) => {
+                    format!("Removed the breakpoint on line {}.\n", line)
+                }
+                Ok(line) => format!("There is no breakpoint on line {}.\n", line),
+                Err(_) => "Usage: delete <line>\n".to_string(),
+            },
+            "bt" | "backtrace" => paused
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| {
+                    let marker = if index == self.frame { '*' } else { ' ' };
+                    format!(
+                        "{}#{} {} at line {}\n",
+                        marker, index, frame.name, frame.line
+                    )
+                })
+                .collect(),
+            "f" | "frame" => match argument.parse() {
+                Ok(index) if index < paused.stack().len() => {
+                    self.frame = index;
+                    let frame = &paused.stack()[index];
+                    format!("#{} {} at line {}\n", index, frame.name, frame.line)
+                }
+                Ok(index) => format!("There is no frame {}.\n", index),
+                Err(_) => "Usage: frame <n>\n".to_string(),
+            },
+            "l" | "locals" => Self::variables(paused.locals(self.frame)),
+            "g" | "globals" => Self::variables(paused.globals()),
+            "p" | "print" => match paused.evaluate(self.frame, argument) {
+                Ok(value) => format!("{}\n", value),
+                Err(error) => format!("{}\n", error.report()),
+            },
+            "list" => {
+                let line = paused.stack()[self.frame].line;
+                self.listing(line.saturating_sub(5), line + 5, line)
+            }
+            "h" | "help" => HELP.to_string(),
+            _ => format!(
+                "Unknown command '{}'. Type help for a list of commands.\n",
+                name
+            ),
+        };
+        self.say(&text);
+        None
+    }
+}
+
+impl Frontend for Console {
+    fn paused(&mut self, paused: &mut Paused<'_>) -> Resume {
+        self.frame = 0;
+        let reason = match paused.reason() {
+            PauseReason::Entry => "on entry",
+            PauseReason::Breakpoint => "at a breakpoint",
+            PauseReason::Step => "after a step",
+        };
+        let line = paused.line();
+        let text = format!(
+            "Paused {} on line {}.\n{}",
+            reason,
+            line,
+            self.listing(line, line, line)
+        );
+        self.say(&text);
+
+        loop {
+            self.say(PROMPT);
+            let _ = self.output.flush();
+            let mut line = String::new();
+            // Without more commands the script runs to the end.
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Resume::Continue,
+                Ok(_) => {}
+            }
+
+            let mut command = line.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            } else {
+                self.last_command = command.clone();
+            }
+            if command.is_empty() {
+                continue;
+            }
+            if let Some(resume) = self.command(paused, &command) {
+                return resume;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{Limit, Limits};
+    use crate::stream::SharedBuffer;
+    use std::io::{self, Cursor};
+
+    const SCRIPT: &str = "fun add(a, b) {
+  var sum = a + b;
+  return sum;
+}
+var x = 1;
+print add(x, 2);
+print \"done\";
+";
+
+    /// Debugs `SCRIPT` with a console fed `commands`, returning the console's transcript and
+    /// the script's output.
+    fn debug(debugger: fn(Console) -> Debugger, commands: &str) -> (String, String) {
+        let transcript = SharedBuffer::new();
+        let output = SharedBuffer::new();
+        let console = Console::new(
+            SCRIPT,
+            Cursor::new(commands.to_string()),
+            transcript.clone(),
+        );
+        let mut interpreter = Interpreter::with_io(io::empty(), output.clone());
+        interpreter.set_debugger(debugger(console));
+        if let Err(error) = interpreter.eval_str(SCRIPT) {
+            writeln!(output.clone(), "{}", error.report()).unwrap();
+        }
+        (transcript.contents(), output.contents())
+    }
+
+    #[test]
+    fn pauses_at_breakpoints_and_inspects_frames() {
+        let (transcript, output) = debug(
+            |console| Debugger::new(console).with_breakpoints([3]),
+            "bt\nlocals\np a * 10\nframe 1\np x\nlocals\nc\n",
+        );
+
+        assert_eq!(
+            transcript,
+            "Paused at a breakpoint on line 3.\n\
+             >   3 |   return sum;\n\
+             (debug) *#0 add at line 3\n \
+             #1 script at line 6\n\
+             (debug) a = 1\nb = 2\nsum = 3\n\
+             (debug) 10\n\
+             (debug) #1 script at line 6\n\
+             (debug) 1\n\
+             (debug) No variables.\n\
+             (debug) "
+        );
+        assert_eq!(output, "3\ndone\n");
+    }
+
+    #[test]
+    fn steps_in_over_and_out() {
+        let (transcript, output) = debug(
+            |console| Debugger::new(console).stop_on_entry(),
+            "n\nn\ns\n\nout\nn\n",
+        );
+
+        let paused: Vec<&str> = transcript
+            .lines()
+            .filter_map(|line| {
+                line.strip_prefix(PROMPT)
+                    .unwrap_or(line)
+                    .strip_prefix("Paused ")
+            })
+            .collect();
+        assert_eq!(
+            paused,
+            [
+                "on entry on line 1.",
+                "after a step on line 5.",
+                "after a step on line 6.",
+                "after a step on line 2.",
+                "after a step on line 3.",
+                "after a step on line 7.",
+            ]
+        );
+        assert_eq!(output, "3\ndone\n");
+    }
+
+    #[test]
+    fn evaluating_while_paused_leaves_the_step_budget_alone() {
+        struct Evaluate(usize);
+        impl Frontend for Evaluate {
+            fn paused(&mut self, paused: &mut Paused<'_>) -> Resume {
+                paused.evaluate(0, "1 + 1").unwrap();
+                self.0 += 1;
+                if self.0 > 1000 {
+                    Resume::Stop
+                } else {
+                    Resume::StepIn
+                }
+            }
+        }
+
+        let mut interpreter = Interpreter::with_io(io::empty(), SharedBuffer::new());
+        interpreter.set_limits(Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        });
+        interpreter.set_debugger(Debugger::new(Evaluate(0)).stop_on_entry());
+        match interpreter.eval_str("var i = 0;\nwhile (true)\n  i = i + 1;") {
+            Err(LoxError::Runtime(error)) => assert_eq!(error.limit, Some(Limit::Steps)),
+            result => panic!("expected the step limit, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn quitting_stops_the_script() {
+        let (_, output) = debug(
+            |console| Debugger::new(console).stop_on_entry(),
+            "b 7\nc\nq\n",
+        );
+
+        assert_eq!(output, "3\nLINE 7: Stopped by the debugger.\n");
+    }
+}
@@ -0,0 +1,392 @@
+//! Machine-readable dumps of the token stream and the AST, for `--dump-tokens` and
+//! `--dump-ast`.
+//!
+//! The JSON AST is built from the concrete syntax tree, which lowers to exactly the AST the
+//! parser produces but also knows where every node starts and ends. Node kinds and field names
+//! follow the `Stmt` and `Expr` variants.
+
+use crate::cst::{self, SyntaxKind, SyntaxNode};
+use crate::error::LoxError;
+use crate::expr::{Expr, LiteralValue};
+use crate::json::Json;
+use crate::limits::Limits;
+use crate::parser::Parser;
+use crate::scanner::{LiteralValue as ScannerLiteralValue, Scanner, TokenType};
+use crate::stmt::Stmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One token per line, or the AST as S-expressions.
+    Text,
+    Json,
+}
+
+impl DumpFormat {
+    /// Parses the value of a `--dump-tokens=` or `--dump-ast=` flag.
+    pub fn from_flag(value: &str) -> Option<Self> {
+        match value {
+            "text" | "sexpr" => Some(DumpFormat::Text),
+            "json" => Some(DumpFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn dump_tokens(source: &str, format: DumpFormat) -> Result<String, LoxError> {
+    let tokens = Scanner::new(source)
+        .with_trivia()
+        .scan_tokens()
+        .map_err(LoxError::Scan)?;
+
+    let mut offset = 0;
+    let mut dumped = vec![];
+    for token in tokens {
+        let length = token.lexeme.as_str().chars().count();
+        if !matches!(token.token_type, TokenType::Whitespace | TokenType::Comment) {
+            dumped.push((token, offset..offset + length));
+        }
+        offset += length;
+    }
+
+    let output = match format {
+        DumpFormat::Text => dumped
+            .iter()
+            .map(|(token, _)| format!("{:>4} {}\n", token.line_number, token))
+            .collect(),
+        DumpFormat::Json => {
+            let tokens = dumped
+                .into_iter()
+                .map(|(token, range)| {
+                    let literal = match token.literal {
+                        Some(ScannerLiteralValue::FloatValue(n)) => Json::Number(n),
+                        Some(ScannerLiteralValue::StringValue(s)) => Json::String(s.to_string()),
+                        _ => Json::Null,
+                    };
+                    Json::object([
+                        ("type", Json::String(token.token_type.to_string())),
+                        ("lexeme", Json::String(token.lexeme.to_string())),
+                        ("literal", literal),
+                        ("span", span(range, token.line_number)),
+                    ])
+                })
+                .collect();
+            format!("{}\n", Json::Array(tokens))
+        }
+    };
+    Ok(output)
+}
+
+pub fn dump_ast(source: &str, format: DumpFormat) -> Result<String, LoxError> {
+    match format {
+        DumpFormat::Text => {
+            let tokens = Scanner::new(source).scan_tokens().map_err(LoxError::Scan)?;
+            let statements = Parser::new(tokens).parse().map_err(LoxError::Parse)?;
+            Ok(statements
+                .iter()
+                .map(|stmt| format!("{}\n", stmt))
+                .collect())
+        }
+        DumpFormat::Json => Ok(format!(
+            "{}\n",
+            node_to_json(&cst::parse(source, Limits::default().max_nesting)?)
+        )),
+    }
+}
+
+fn span(range: Range<usize>, line: usize) -> Json {
+    Json::object([
+        ("start", Json::Number(range.start as f64)),
+        ("end", Json::Number(range.end as f64)),
+        ("line", Json::Number(line as f64)),
+    ])
+}
+
+fn node_to_json(node: &SyntaxNode) -> Json {
+    let nodes: Vec<&SyntaxNode> = node.child_nodes().collect();
+    let child = |i: usize| nodes.get(i).map_or(Json::Null, |node| node_to_json(node));
+    let all =
+        |nodes: &[&SyntaxNode]| Json::Array(nodes.iter().map(|node| node_to_json(node)).collect());
+    let token = |n: usize| {
+        let token = node.child_tokens().nth(n).expect("malformed syntax tree");
+        Json::String(token.lexeme.to_string())
+    };
+
+    let (kind, fields) = match node.kind() {
+        SyntaxKind::Program => ("Program", vec![("body", all(&nodes))]),
+        SyntaxKind::Block => ("Block", vec![("statements", all(&nodes))]),
+        SyntaxKind::VarDecl => {
+            let initializer = node.initializer().map_or(Json::Null, node_to_json);
+            (
+                "Var",
+                vec![("name", token(1)), ("initializer", initializer)],
+            )
+        }
+        SyntaxKind::FunDecl => {
+            let params = nodes[0]
+                .child_tokens()
+                .filter(|token| token.token_type == TokenType::Identifier)
+                .map(|token| Json::String(token.lexeme.to_string()))
+                .collect();
+            let body: Vec<&SyntaxNode> = node.body().child_nodes().collect();
+            (
+                "Function",
+                vec![
+                    ("name", token(1)),
+                    ("params", Json::Array(params)),
+                    ("body", all(&body)),
+                ],
+            )
+        }
+        SyntaxKind::Import => {
+            let path = match node.to_stmt() {
+                Some(Stmt::Import { path, .. }) => Json::String(path.to_string()),
+                _ => Json::Null,
+            };
+            ("Import", vec![("path", path), ("name", token(3))])
+        }
+        SyntaxKind::ExprStmt => ("Expression", vec![("expression", child(0))]),
+        SyntaxKind::For => {
+            let [initializer, condition, increment, body] = node
+                .for_clauses()
+                .map(|clause| clause.map_or(Json::Null, node_to_json));
+            (
+                "For",
+                vec![
+                    ("initializer", initializer),
+                    ("condition", condition),
+                    ("increment", increment),
+                    ("body", body),
+                ],
+            )
+        }
+        SyntaxKind::If => (
+            "If",
+            vec![
+                ("condition", child(0)),
+                ("then_branch", child(1)),
+                ("else_branch", child(2)),
+            ],
+        ),
+        SyntaxKind::Print => ("Print", vec![("expression", child(0))]),
+        SyntaxKind::Return => ("Return", vec![("value", child(0))]),
+        SyntaxKind::Throw => ("Throw", vec![("value", child(0))]),
+        SyntaxKind::Try => {
+            let clause = |kind| {
+                nodes
+                    .iter()
+                    .find(|node| node.kind() == kind)
+                    .map_or(Json::Null, |node| node_to_json(node))
+            };
+            (
+                "Try",
+                vec![
+                    ("body", child(0)),
+                    ("catch", clause(SyntaxKind::Catch)),
+                    ("finally", clause(SyntaxKind::Finally)),
+                ],
+            )
+        }
+        SyntaxKind::Catch => ("Catch", vec![("name", token(2)), ("body", child(0))]),
+        SyntaxKind::Finally => ("Finally", vec![("body", child(0))]),
+        SyntaxKind::While => ("While", vec![("condition", child(0)), ("body", child(1))]),
+        SyntaxKind::Assign => {
+            let name = nodes[0]
+                .child_tokens()
+                .next()
+                .expect("malformed syntax tree");
+            (
+                "Assign",
+                vec![
+                    ("name", Json::String(name.lexeme.to_string())),
+                    ("value", child(1)),
+                ],
+            )
+        }
+        SyntaxKind::Binary => (
+            "Binary",
+            vec![
+                ("operator", token(0)),
+                ("left", child(0)),
+                ("right", child(1)),
+            ],
+        ),
+        SyntaxKind::Logical => (
+            "Logical",
+            vec![
+                ("operator", token(0)),
+                ("left", child(0)),
+                ("right", child(1)),
+            ],
+        ),
+        SyntaxKind::Call => {
+            let arguments: Vec<&SyntaxNode> = nodes[1].child_nodes().collect();
+            (
+                "Call",
+                vec![("callee", child(0)), ("arguments", all(&arguments))],
+            )
+        }
+        SyntaxKind::Get => ("Get", vec![("object", child(0)), ("name", token(1))]),
+        SyntaxKind::Grouping => ("Grouping", vec![("expression", child(0))]),
+        SyntaxKind::Literal => {
+            let value = match node.to_expr() {
+                Some(Expr::Literal { value }) => literal_to_json(&value),
+                _ => Json::Null,
+            };
+            ("Literal", vec![("value", value)])
+        }
+        SyntaxKind::Unary => ("Unary", vec![("operator", token(0)), ("right", child(0))]),
+        SyntaxKind::Variable => ("Variable", vec![("name", token(0))]),
+        SyntaxKind::ParamList | SyntaxKind::ArgList | SyntaxKind::TypeAnnotation => {
+            unreachable!("lists and annotations are folded into their parent")
+        }
+    };
+
+    let line = node
+        .tokens()
+        .first()
+        .map_or(1, |token| token.token().line_number);
+    let mut object = vec![("kind", Json::String(kind.to_string()))];
+    object.extend(fields);
+    object.push(("span", span(node.range(), line)));
+    Json::object(object)
+}
+
+fn literal_to_json(value: &LiteralValue) -> Json {
+    match value {
+        LiteralValue::Number(n) => Json::Number(*n),
+        LiteralValue::StringValue(s) => Json::String(s.to_string()),
+        LiteralValue::True => Json::Bool(true),
+        LiteralValue::False => Json::Bool(false),
+        _ => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_tokens_as_text_and_json() {
+        let text = dump_tokens("var a = \"hi\";", DumpFormat::Text).unwrap();
+        assert_eq!(
+            text,
+            "   1 Var var None\n   1 Identifier a None\n   1 Equal = None\n   1 String \"hi\" Some(StringValue(\"hi\"))\n   1 Semicolon ; None\n   1 EOF  None\n"
+        );
+
+        let json = dump_tokens("// c\n12", DumpFormat::Json).unwrap();
+        assert_eq!(
+            json,
+            r#"[
+  {
+    "type": "Number",
+    "lexeme": "12",
+    "literal": 12,
+    "span": {
+      "start": 5,
+      "end": 7,
+      "line": 2
+    }
+  },
+  {
+    "type": "EOF",
+    "lexeme": "",
+    "literal": null,
+    "span": {
+      "start": 7,
+      "end": 7,
+      "line": 2
+    }
+  }
+]
+"#
+        );
+    }
+
+    #[test]
+    fn dumps_the_ast_as_s_expressions() {
+        let source = "var a = 1; fun f(x, y) { return x * -y; } for (;a < 3;) a = f(a, 2); if (a) print \"big\"; else {}";
+        assert_eq!(
+            dump_ast(source, DumpFormat::Text).unwrap(),
+            "(var a 1)\n(fun f (x y) (return (* x (- y))))\n(for nil (< a 3) nil (; (= a (call f a 2))))\n(if a (print big) (block))\n"
+        );
+    }
+
+    #[test]
+    fn dumps_the_ast_as_json_with_spans() {
+        let json = dump_ast("print (1 + x) or \"a\\\";", DumpFormat::Json).unwrap();
+        let expected = r#"{
+  "kind": "Program",
+  "body": [
+    {
+      "kind": "Print",
+      "expression": {
+        "kind": "Logical",
+        "operator": "or",
+        "left": {
+          "kind": "Grouping",
+          "expression": {
+            "kind": "Binary",
+            "operator": "+",
+            "left": {
+              "kind": "Literal",
+              "value": 1,
+              "span": {
+                "start": 7,
+                "end": 8,
+                "line": 1
+              }
+            },
+            "right": {
+              "kind": "Variable",
+              "name": "x",
+              "span": {
+                "start": 11,
+                "end": 12,
+                "line": 1
+              }
+            },
+            "span": {
+              "start": 7,
+              "end": 12,
+              "line": 1
+            }
+          },
+          "span": {
+            "start": 6,
+            "end": 13,
+            "line": 1
+          }
+        },
+        "right": {
+          "kind": "Literal",
+          "value": "a\\",
+          "span": {
+            "start": 17,
+            "end": 21,
+            "line": 1
+          }
+        },
+        "span": {
+          "start": 6,
+          "end": 21,
+          "line": 1
+        }
+      },
+      "span": {
+        "start": 0,
+        "end": 22,
+        "line": 1
+      }
+    }
+  ],
+  "span": {
+    "start": 0,
+    "end": 22,
+    "line": 1
+  }
+}
+"#;
+        assert_eq!(json, expected);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::callable::LoxFunction;
+use crate::error::RuntimeError;
+use crate::expr::LiteralValue;
+use crate::scanner::Token;
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<Symbol, LiteralValue>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: Symbol, value: LiteralValue) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get(&self, name: &Token) -> Result<LiteralValue, RuntimeError> {
+        if let Some(value) = self.values.get(&name.lexeme.symbol()) {
+            return Ok(value.clone());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(RuntimeError::new(
+                &format!("Undefined variable '{}'.", name.lexeme),
+                name.line_number,
+                name.clone(),
+            )),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: LiteralValue) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme.symbol()) {
+            *slot = value;
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(RuntimeError::new(
+                &format!("Undefined variable '{}'.", name.lexeme),
+                name.line_number,
+                name.clone(),
+            )),
+        }
+    }
+
+    /// The bindings made in this scope itself, in no particular order.
+    pub fn bindings(&self) -> impl Iterator<Item = (Symbol, &LiteralValue)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.clone(), value))
+    }
+
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    /// Looks up a binding in this scope only, without walking enclosing scopes.
+    pub fn get_local(&self, name: Symbol) -> Option<LiteralValue> {
+        self.values.get(&name).cloned()
+    }
+
+    fn functions(&self) -> impl Iterator<Item = &Rc<LoxFunction>> {
+        self.values.values().filter_map(|value| match value {
+            LiteralValue::Function(function) => Some(function),
+            _ => None,
+        })
+    }
+}
+
+/// The scopes the tree-walker creates for blocks and calls. A closure stored in the scope it
+/// closes over keeps that scope alive through a reference cycle, so every scope is tracked
+/// here and [`Scopes::collect`] empties the ones nothing outside such cycles still reaches.
+///
+/// Collection needs no roots: a scope or function is in use from outside the tracked scopes
+/// if it has more strong references than the tracked scopes account for. Everything those
+/// reach is kept, so references held anywhere else, like the Rust stack, are always safe.
+#[derive(Debug)]
+pub struct Scopes {
+    scopes: Vec<Weak<RefCell<Environment>>>,
+    /// How many scopes may be tracked before the next collection.
+    threshold: usize,
+}
+
+/// The fewest scopes tracked before a collection runs.
+const MIN_THRESHOLD: usize = 1024;
+
+impl Default for Scopes {
+    fn default() -> Self {
+        Self {
+            scopes: vec![],
+            threshold: MIN_THRESHOLD,
+        }
+    }
+}
+
+impl Scopes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `environment` a shared scope and tracks it, collecting first once enough scopes
+    /// have been tracked since the last collection.
+    pub fn track(&mut self, environment: Environment) -> Rc<RefCell<Environment>> {
+        if self.scopes.len() >= self.threshold {
+            self.collect();
+        }
+        let scope = Rc::new(RefCell::new(environment));
+        self.scopes.push(Rc::downgrade(&scope));
+        scope
+    }
+
+    /// How many tracked scopes are still alive.
+    pub fn live(&self) -> usize {
+        self.scopes
+            .iter()
+            .filter(|scope| scope.strong_count() > 0)
+            .count()
+    }
+
+    /// Empties every tracked scope that is only reachable through other tracked scopes and
+    /// the functions they hold, which breaks the cycles keeping them alive.
+    pub fn collect(&mut self) {
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        let scopes: Vec<Rc<RefCell<Environment>>> =
+            self.scopes.iter().filter_map(Weak::upgrade).collect();
+        let index: HashMap<*const RefCell<Environment>, usize> = scopes
+            .iter()
+            .enumerate()
+            .map(|(i, scope)| (Rc::as_ptr(scope), i))
+            .collect();
+        let tracked = |scope: &Rc<RefCell<Environment>>| index.get(&Rc::as_ptr(scope)).copied();
+
+        // Count the references each scope and function gets from tracked scopes. A scope
+        // that is borrowed right now is in use, and its references aren't counted.
+        let mut internal = vec![0; scopes.len()];
+        let mut in_use = vec![false; scopes.len()];
+        let mut functions: HashMap<*const LoxFunction, (Rc<LoxFunction>, usize)> = HashMap::new();
+        for (i, scope) in scopes.iter().enumerate() {
+            let Ok(scope) = scope.try_borrow() else {
+                in_use[i] = true;
+                continue;
+            };
+            if let Some(enclosing) = scope.enclosing.as_ref().and_then(tracked) {
+                internal[enclosing] += 1;
+            }
+            for function in scope.functions() {
+                functions
+                    .entry(Rc::as_ptr(function))
+                    .or_insert_with(|| (function.clone(), 0))
+                    .1 += 1;
+            }
+        }
+        // A function only tracked scopes hold passes its reference to its closure on to
+        // them; one held anywhere else keeps its closure in use.
+        for (function, references) in functions.values() {
+            let Some(closure) = tracked(&function.closure) else {
+                continue;
+            };
+            // One of the strong references is the clone in `functions`.
+            if *references == Rc::strong_count(function) - 1 {
+                internal[closure] += 1;
+            } else {
+                in_use[closure] = true;
+            }
+        }
+
+        // Keep everything reachable from a scope in use, and empty the rest.
+        let mut pending: Vec<usize> = (0..scopes.len())
+            .filter(|&i| in_use[i] || Rc::strong_count(&scopes[i]) - 1 > internal[i])
+            .collect();
+        let mut live = vec![false; scopes.len()];
+        while let Some(i) = pending.pop() {
+            if mem::replace(&mut live[i], true) {
+                continue;
+            }
+            let Ok(scope) = scopes[i].try_borrow() else {
+                continue;
+            };
+            pending.extend(scope.enclosing.as_ref().and_then(tracked));
+            pending.extend(
+                scope
+                    .functions()
+                    .filter_map(|function| tracked(&function.closure)),
+            );
+        }
+
+        // The contents are dropped only once no scope is borrowed, since dropping them can
+        // drop other scopes.
+        let garbage: Vec<Environment> = scopes
+            .iter()
+            .zip(&live)
+            .filter(|(_, &live)| !live)
+            .map(|(scope, _)| mem::take(&mut *scope.borrow_mut()))
+            .collect();
+        drop(garbage);
+        drop(scopes);
+
+        self.scopes.retain(|scope| scope.strong_count() > 0);
+        self.threshold = (self.scopes.len() * 2).max(MIN_THRESHOLD);
+    }
+}
@@ -1,11 +1,44 @@
+use crate::expr::LiteralValue;
+use crate::limits::Limit;
 use crate::scanner::{Token, TokenType};
+use crate::typecheck::TypeError;
 use std::fmt;
 
-#[derive(Debug)]
+/// A function call in progress, as backtraces and the debugger show it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// The function's name, or `script` for top-level code.
+    pub name: String,
+    /// The file the function was defined in, if it came from one.
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{} ({}:{})", self.name, file, self.line),
+            None => write!(f, "{} (line {})", self.name, self.line),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RuntimeError {
     pub message: String,
     pub line: usize,
-    pub token: Token,
+    /// Boxed, since errors are passed back through every level of the parser, compiler and
+    /// tree-walker.
+    pub token: Box<Token>,
+    /// The calls in progress when the error was raised, innermost first. Empty for errors
+    /// that were never raised by running code, such as parse errors.
+    pub backtrace: Box<[StackFrame]>,
+    /// The value a `throw` statement raised, or `None` for errors raised by the runtime.
+    pub value: Option<Box<LiteralValue>>,
+    /// Fatal errors stop the program even inside a `try` block.
+    pub fatal: bool,
+    /// The limit the error reports exceeding, if it was raised for one.
+    pub limit: Option<Limit>,
 }
 
 impl RuntimeError {
@@ -13,19 +46,50 @@ impl RuntimeError {
         Self {
             message: message.to_string(),
             line,
-            token,
+            token: Box::new(token),
+            backtrace: Box::default(),
+            value: None,
+            fatal: false,
+            limit: None,
         }
     }
 
+    /// The error raised when running code goes over `limit` on `line`.
+    pub fn exceeded(limit: Limit, line: usize) -> Self {
+        let token = Token::new(TokenType::EOF, "", None, line);
+        Self {
+            limit: Some(limit),
+            ..Self::new(&limit.to_string(), line, token)
+        }
+    }
+
+    /// Marks the error as one `catch` clauses can't intercept.
+    pub fn fatal(mut self) -> Self {
+        self.fatal = true;
+        self
+    }
+
+    /// The error on one line, followed by the backtrace if it was raised inside a call.
     pub fn report(&self) -> String {
-        if self.token.token_type == TokenType::EOF {
+        let mut report = if self.token.token_type == TokenType::EOF {
             format!("LINE {}: {}", self.line, self.message)
         } else {
             format!(
                 "LINE {}: ('{}') {}",
                 self.line, self.token.lexeme, self.message
             )
+        };
+        // A lone top-level frame says nothing the first line doesn't.
+        if self.backtrace.len() > 1 {
+            // Deep recursion repeats one frame many times, so runs of it are shown once.
+            for frames in self.backtrace.chunk_by(|a, b| a == b) {
+                report += &format!("\n  at {}", frames[0]);
+                if frames.len() > 1 {
+                    report += &format!(" [repeated {} times]", frames.len());
+                }
+            }
         }
+        report
     }
 }
 
@@ -42,3 +106,67 @@ impl fmt::Display for RuntimeError {
         }
     }
 }
+
+impl std::error::Error for RuntimeError {}
+
+/// Any failure surfaced by the embedding API, tagged with the phase that produced it.
+#[derive(Debug)]
+pub enum LoxError {
+    Io(String),
+    Load(String),
+    Scan(String),
+    Parse(RuntimeError),
+    Compile(RuntimeError),
+    /// Every type error `rlox check` found, in source order.
+    Type(Vec<TypeError>),
+    Runtime(RuntimeError),
+}
+
+impl LoxError {
+    pub fn report(&self) -> String {
+        match self {
+            LoxError::Io(message) | LoxError::Load(message) | LoxError::Scan(message) => {
+                message.clone()
+            }
+            LoxError::Parse(error) | LoxError::Compile(error) | LoxError::Runtime(error) => {
+                error.report()
+            }
+            LoxError::Type(errors) => {
+                let errors: Vec<String> = errors.iter().map(TypeError::to_string).collect();
+                errors.join("\n")
+            }
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.report())
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+/// Returned when a Lox value cannot be converted into the requested Rust type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl ConversionError {
+    pub fn new(expected: &'static str, value: &LiteralValue) -> Self {
+        Self {
+            expected,
+            found: value.type_name(),
+        }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected {}, but found {}.", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError {}
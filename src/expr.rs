@@ -1,67 +1,403 @@
+use crate::callable::{LoxFunction, NativeFunction};
+use crate::error::{ConversionError, RuntimeError};
+use crate::module::{self, Module};
 use crate::scanner::Token;
+use std::fmt;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum Expr {
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    /// `object.name`, reading a binding of a module.
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
     Grouping {
         expression: Box<Expr>,
     },
     Literal {
         value: LiteralValue,
     },
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
+    Variable {
+        name: Token,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum LiteralValue {
     Number(f64),
-    StringValue(String),
+    StringValue(Rc<str>),
     True,
     False,
     Nil,
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    /// A runtime error caught by a `catch` clause.
+    Error(Rc<RuntimeError>),
+    Module(Rc<Module>),
+}
+
+/// A read-only pass over an expression that produces a value per node.
+///
+/// `visit_expr` dispatches to the method for the node's variant via `walk_expr`; override it
+/// to run code around every node. Implementations recurse by calling `visit_expr` on the
+/// children they care about.
+pub trait Visitor {
+    type Output;
+
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        walk_expr(self, expr)
+    }
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> Self::Output;
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output;
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Self::Output;
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output;
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output;
+    fn visit_literal(&mut self, value: &LiteralValue) -> Self::Output;
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output;
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output;
+    fn visit_variable(&mut self, name: &Token) -> Self::Output;
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) -> V::Output {
+    match expr {
+        Expr::Assign { name, value } => visitor.visit_assign(name, value),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => visitor.visit_binary(left, operator, right),
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => visitor.visit_call(callee, paren, arguments),
+        Expr::Get { object, name } => visitor.visit_get(object, name),
+        Expr::Grouping { expression } => visitor.visit_grouping(expression),
+        Expr::Literal { value } => visitor.visit_literal(value),
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => visitor.visit_logical(left, operator, right),
+        Expr::Unary { operator, right } => visitor.visit_unary(operator, right),
+        Expr::Variable { name } => visitor.visit_variable(name),
+    }
+}
+
+/// A pass that rebuilds an expression. The default `fold_expr` rebuilds the node from its
+/// folded children; override it and call `fold_children` to transform bottom-up.
+pub trait Folder {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_children(self, expr)
+    }
+}
+
+pub fn fold_children<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    let mut fold = |expr: Box<Expr>| Box::new(folder.fold_expr(*expr));
+
+    match expr {
+        Expr::Assign { name, value } => Expr::Assign {
+            name,
+            value: fold(value),
+        },
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => Expr::Binary {
+            left: fold(left),
+            operator,
+            right: fold(right),
+        },
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => Expr::Call {
+            callee: fold(callee),
+            paren,
+            arguments: arguments
+                .into_iter()
+                .map(|argument| *fold(Box::new(argument)))
+                .collect(),
+        },
+        Expr::Get { object, name } => Expr::Get {
+            object: fold(object),
+            name,
+        },
+        Expr::Grouping { expression } => Expr::Grouping {
+            expression: fold(expression),
+        },
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => Expr::Logical {
+            left: fold(left),
+            operator,
+            right: fold(right),
+        },
+        Expr::Unary { operator, right } => Expr::Unary {
+            operator,
+            right: fold(right),
+        },
+        Expr::Literal { .. } | Expr::Variable { .. } => expr,
+    }
+}
+
+/// Renders an expression as the S-expression used by `Display`.
+struct Printer<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Visitor for Printer<'_, '_> {
+    type Output = fmt::Result;
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> fmt::Result {
+        write!(self.0, "(= {} ", name.lexeme)?;
+        self.visit_expr(value)?;
+        write!(self.0, ")")
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> fmt::Result {
+        write!(self.0, "({} ", operator.lexeme)?;
+        self.visit_expr(left)?;
+        write!(self.0, " ")?;
+        self.visit_expr(right)?;
+        write!(self.0, ")")
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> fmt::Result {
+        write!(self.0, "(call ")?;
+        self.visit_expr(callee)?;
+        for argument in arguments {
+            write!(self.0, " ")?;
+            self.visit_expr(argument)?;
+        }
+        write!(self.0, ")")
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> fmt::Result {
+        write!(self.0, "(. ")?;
+        self.visit_expr(object)?;
+        write!(self.0, " {})", name.lexeme)
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> fmt::Result {
+        write!(self.0, "(group ")?;
+        self.visit_expr(expression)?;
+        write!(self.0, ")")
+    }
+
+    fn visit_literal(&mut self, value: &LiteralValue) -> fmt::Result {
+        write!(self.0, "{}", value)
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> fmt::Result {
+        self.visit_binary(left, operator, right)
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> fmt::Result {
+        write!(self.0, "({} ", operator.lexeme)?;
+        self.visit_expr(right)?;
+        write!(self.0, ")")
+    }
+
+    fn visit_variable(&mut self, name: &Token) -> fmt::Result {
+        write!(self.0, "{}", name.lexeme)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer(f).visit_expr(self)
+    }
 }
 
 impl Expr {
-    pub fn to_string(&self) -> String {
+    pub fn print(&self) {
+        println!("{}", self);
+    }
+
+    /// The line of the expression's first token. Literals carry no token, so an expression
+    /// made only of literals has no line.
+    pub fn line(&self) -> Option<usize> {
         match self {
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => format!(
-                "({} {} {})",
-                operator.lexeme,
-                left.to_string(),
-                right.to_string()
-            ),
-            Expr::Grouping { expression } => format!("(group {})", expression.to_string()),
-            Expr::Literal { value } => format!("{}", value.to_string()),
-            Expr::Unary { operator, right } => {
-                format!("({} {})", operator.lexeme, right.to_string())
+            Expr::Assign { name, .. } | Expr::Variable { name } => Some(name.line_number),
+            Expr::Binary { left, operator, .. } | Expr::Logical { left, operator, .. } => {
+                left.line().or(Some(operator.line_number))
             }
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line_number)),
+            Expr::Get { object, name } => object.line().or(Some(name.line_number)),
+            Expr::Grouping { expression } => expression.line(),
+            Expr::Literal { .. } => None,
+            Expr::Unary { operator, .. } => Some(operator.line_number),
         }
     }
+}
 
-    pub fn print(&self) {
-        println!("{}", self.to_string());
+impl LiteralValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LiteralValue::Number(_) => "number",
+            LiteralValue::StringValue(_) => "string",
+            LiteralValue::True | LiteralValue::False => "boolean",
+            LiteralValue::Nil => "nil",
+            LiteralValue::Function(_) | LiteralValue::Native(_) => "function",
+            LiteralValue::Error(_) => "error",
+            LiteralValue::Module(_) => "module",
+        }
     }
 }
 
-impl LiteralValue {
-    fn to_string(&self) -> String {
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            LiteralValue::Number(val) => format!("{}", val),
-            LiteralValue::StringValue(val) => val.clone(),
-            LiteralValue::True => "true".to_string(),
-            LiteralValue::False => "false".to_string(),
-            LiteralValue::Nil => "nil".to_string(),
+            LiteralValue::Number(val) => write!(f, "{}", val),
+            LiteralValue::StringValue(val) => write!(f, "{}", val),
+            LiteralValue::True => write!(f, "true"),
+            LiteralValue::False => write!(f, "false"),
+            LiteralValue::Nil => write!(f, "nil"),
+            LiteralValue::Function(fun) => write!(f, "<fn {}>", fun.name()),
+            LiteralValue::Native(_) => write!(f, "<native fn>"),
+            LiteralValue::Error(error) => write!(f, "{}", error.message),
+            LiteralValue::Module(module) => write!(f, "<module {}>", module::name(&module.file)),
+        }
+    }
+}
+
+impl PartialEq for LiteralValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LiteralValue::Number(l), LiteralValue::Number(r)) => l == r,
+            (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => {
+                Rc::ptr_eq(l, r) || l == r
+            }
+            (LiteralValue::True, LiteralValue::True) => true,
+            (LiteralValue::False, LiteralValue::False) => true,
+            (LiteralValue::Nil, LiteralValue::Nil) => true,
+            (LiteralValue::Function(l), LiteralValue::Function(r)) => Rc::ptr_eq(l, r),
+            (LiteralValue::Native(l), LiteralValue::Native(r)) => Rc::ptr_eq(l, r),
+            (LiteralValue::Error(l), LiteralValue::Error(r)) => Rc::ptr_eq(l, r),
+            (LiteralValue::Module(l), LiteralValue::Module(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl From<f64> for LiteralValue {
+    fn from(value: f64) -> Self {
+        LiteralValue::Number(value)
+    }
+}
+
+impl From<i32> for LiteralValue {
+    fn from(value: i32) -> Self {
+        LiteralValue::Number(value as f64)
+    }
+}
+
+impl From<bool> for LiteralValue {
+    fn from(value: bool) -> Self {
+        if value {
+            LiteralValue::True
+        } else {
+            LiteralValue::False
+        }
+    }
+}
+
+impl From<String> for LiteralValue {
+    fn from(value: String) -> Self {
+        LiteralValue::StringValue(value.into())
+    }
+}
+
+impl From<&str> for LiteralValue {
+    fn from(value: &str) -> Self {
+        LiteralValue::StringValue(value.into())
+    }
+}
+
+impl From<()> for LiteralValue {
+    fn from(_: ()) -> Self {
+        LiteralValue::Nil
+    }
+}
+
+impl<T: Into<LiteralValue>> From<Option<T>> for LiteralValue {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => LiteralValue::Nil,
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        match value {
+            LiteralValue::Number(n) => Ok(n),
+            other => Err(ConversionError::new("number", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        match value {
+            LiteralValue::True => Ok(true),
+            LiteralValue::False => Ok(false),
+            other => Err(ConversionError::new("boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        match value {
+            LiteralValue::StringValue(s) => Ok(s.to_string()),
+            other => Err(ConversionError::new("string", &other)),
+        }
+    }
+}
+
+impl TryFrom<LiteralValue> for () {
+    type Error = ConversionError;
+
+    fn try_from(value: LiteralValue) -> Result<Self, Self::Error> {
+        match value {
+            LiteralValue::Nil => Ok(()),
+            other => Err(ConversionError::new("nil", &other)),
         }
     }
 }
@@ -73,8 +409,8 @@ mod tests {
 
     #[test]
     fn print_pretty_ast() {
-        let minus_token = Token::new(Minus, "-".to_string(), None, 1);
-        let star_token = Token::new(Star, "*".to_string(), None, 1);
+        let minus_token = Token::new(Minus, "-", None, 1);
+        let star_token = Token::new(Star, "*", None, 1);
 
         let number = Expr::Literal {
             value: LiteralValue::Number(123.0),
@@ -96,5 +432,62 @@ mod tests {
         };
 
         ast.print();
+        assert_eq!(ast.to_string(), "(* (- 123) (group 45.67))");
+    }
+
+    #[test]
+    fn convert_between_rust_and_lox_values() {
+        assert_eq!(LiteralValue::from(1.5), LiteralValue::Number(1.5));
+        assert_eq!(LiteralValue::from(true), LiteralValue::True);
+        assert_eq!(
+            LiteralValue::from("hi"),
+            LiteralValue::StringValue("hi".into())
+        );
+        assert_eq!(LiteralValue::from(None::<f64>), LiteralValue::Nil);
+
+        assert_eq!(f64::try_from(LiteralValue::Number(2.0)), Ok(2.0));
+        assert_eq!(bool::try_from(LiteralValue::False), Ok(false));
+        assert_eq!(
+            std::string::String::try_from(LiteralValue::Number(2.0)),
+            Err(ConversionError {
+                expected: "string",
+                found: "number",
+            })
+        );
+    }
+
+    fn parse_expr(source: &str) -> Expr {
+        let tokens = crate::scanner::Scanner::new(source).scan_tokens().unwrap();
+        match crate::parser::Parser::new(tokens)
+            .parse()
+            .unwrap()
+            .remove(0)
+        {
+            crate::stmt::Stmt::Expression { expression } => expression,
+            other => panic!("expected an expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn folder_rebuilds_bottom_up() {
+        struct Identity;
+        impl Folder for Identity {}
+
+        struct Ungroup;
+        impl Folder for Ungroup {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                match fold_children(self, expr) {
+                    Expr::Grouping { expression } => *expression,
+                    other => other,
+                }
+            }
+        }
+
+        let expr = parse_expr("((1)) * (2 + (x))");
+        assert_eq!(
+            Identity.fold_expr(expr.clone()).to_string(),
+            expr.to_string()
+        );
+        assert_eq!(Ungroup.fold_expr(expr).to_string(), "(* 1 (+ 2 x))");
     }
 }
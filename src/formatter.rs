@@ -0,0 +1,484 @@
+//! Pretty-printer for Lox source, used by `rlox fmt`.
+//!
+//! The formatter works on the token stream rather than the AST so that comments survive. It
+//! ignores the original layout apart from comments and blank lines between statements, which
+//! makes the output a function of the tokens alone and therefore idempotent. Source that does
+//! not parse is rejected rather than formatted.
+
+use crate::error::LoxError;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub indent_width: usize,
+    pub max_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            max_width: 80,
+        }
+    }
+}
+
+pub fn format_source(source: &str) -> Result<String, LoxError> {
+    format_source_with(source, &FormatOptions::default())
+}
+
+pub fn format_source_with(source: &str, options: &FormatOptions) -> Result<String, LoxError> {
+    let tokens = Scanner::new(source).scan_tokens().map_err(LoxError::Scan)?;
+    Parser::new(tokens).parse().map_err(LoxError::Parse)?;
+
+    let tokens = Scanner::new(source)
+        .with_comments()
+        .scan_tokens()
+        .map_err(LoxError::Scan)?;
+
+    let mut formatter = Formatter::new(*options);
+    formatter.format(&tokens);
+    Ok(formatter.finish())
+}
+
+struct Piece {
+    text: String,
+    kind: TokenType,
+    space_before: bool,
+    unary: bool,
+}
+
+struct Formatter {
+    options: FormatOptions,
+    output: Vec<String>,
+    current: Vec<Piece>,
+    indent: usize,
+    paren_depth: usize,
+    // Set when a comment splits a statement, so the rest of it is indented one level deeper.
+    continuation: bool,
+}
+
+impl Formatter {
+    fn new(options: FormatOptions) -> Self {
+        Self {
+            options,
+            output: vec![],
+            current: vec![],
+            indent: 0,
+            paren_depth: 0,
+            continuation: false,
+        }
+    }
+
+    fn format(&mut self, tokens: &[Token]) {
+        let mut previous: Option<&Token> = None;
+        let mut index = 0;
+
+        while index < tokens.len() {
+            let token = &tokens[index];
+            if token.token_type == TokenType::EOF {
+                break;
+            }
+
+            let blank_before =
+                previous.is_some_and(|prev| start_line(token) > prev.line_number + 1);
+            let same_line = previous.is_some_and(|prev| start_line(token) == prev.line_number);
+
+            match token.token_type {
+                TokenType::Comment => {
+                    let text = token.lexeme.as_str().trim_end().to_string();
+                    if same_line && !self.current.is_empty() {
+                        self.push(token, text, true);
+                        self.flush();
+                    } else if same_line && !self.output.is_empty() {
+                        // The line was already ended by `;`, `{` or `}`.
+                        let line = self.output.last_mut().unwrap();
+                        line.push(' ');
+                        line.push_str(&text);
+                    } else {
+                        self.flush();
+                        self.blank_line_if(blank_before);
+                        self.push(token, text, false);
+                        self.flush();
+                    }
+                }
+                TokenType::LeftBrace => {
+                    self.blank_line_if(blank_before && self.current.is_empty());
+                    let space = !self.current.is_empty();
+                    if tokens.get(index + 1).map(|t| t.token_type) == Some(TokenType::RightBrace) {
+                        self.push(token, "{}".to_string(), space);
+                        index += 1;
+                        self.after_close_brace(tokens.get(index + 1));
+                    } else {
+                        self.push(token, "{".to_string(), space);
+                        self.flush();
+                        self.indent += 1;
+                    }
+                }
+                TokenType::RightBrace => {
+                    self.flush();
+                    self.indent = self.indent.saturating_sub(1);
+                    self.push(token, "}".to_string(), false);
+                    self.after_close_brace(tokens.get(index + 1));
+                }
+                TokenType::Semicolon => {
+                    self.push(token, ";".to_string(), false);
+                    if self.paren_depth == 0 {
+                        self.flush();
+                    }
+                }
+                _ => {
+                    if self.current.is_empty() {
+                        self.blank_line_if(blank_before);
+                    }
+                    match token.token_type {
+                        TokenType::LeftParen => self.paren_depth += 1,
+                        TokenType::RightParen => {
+                            self.paren_depth = self.paren_depth.saturating_sub(1)
+                        }
+                        _ => {}
+                    }
+                    let space = self.space_before(token.token_type);
+                    self.push(token, token.lexeme.to_string(), space);
+                }
+            }
+
+            // An empty block consumed its `}` too, so that's the token the next one follows.
+            previous = Some(&tokens[index]);
+            index += 1;
+        }
+
+        self.flush();
+    }
+
+    /// `} else` stays on one line; anything else after a closing brace starts a new one.
+    fn after_close_brace(&mut self, next: Option<&Token>) {
+        let continues = matches!(
+            next.map(|t| t.token_type),
+            Some(TokenType::Else | TokenType::Catch | TokenType::Finally)
+        );
+        if !continues {
+            self.flush();
+        }
+    }
+
+    fn space_before(&self, kind: TokenType) -> bool {
+        let Some(prev) = self.current.last() else {
+            return false;
+        };
+        if prev.unary || matches!(prev.kind, TokenType::LeftParen | TokenType::Dot) {
+            return false;
+        }
+
+        match kind {
+            TokenType::RightParen
+            | TokenType::Comma
+            | TokenType::Colon
+            | TokenType::Semicolon
+            | TokenType::Dot => false,
+            // A call: `f(x)`, `f(x)(y)`.
+            TokenType::LeftParen => {
+                !matches!(prev.kind, TokenType::Identifier | TokenType::RightParen)
+            }
+            _ => true,
+        }
+    }
+
+    fn push(&mut self, token: &Token, text: String, space_before: bool) {
+        let unary = match token.token_type {
+            TokenType::Bang => true,
+            TokenType::Minus => !self
+                .current
+                .last()
+                .is_some_and(|prev| ends_operand(prev.kind)),
+            _ => false,
+        };
+
+        self.current.push(Piece {
+            text,
+            kind: token.token_type,
+            space_before: space_before && !self.current.is_empty(),
+            unary,
+        });
+    }
+
+    fn blank_line_if(&mut self, blank: bool) {
+        let after_open_brace = self.output.last().is_none_or(|line| line.ends_with('{'));
+        if blank && !after_open_brace && self.current.is_empty() {
+            self.output.push(String::new());
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+
+        let pieces = std::mem::take(&mut self.current);
+        let ends_statement = match pieces.last().map(|piece| piece.kind) {
+            Some(TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace) => {
+                self.paren_depth == 0
+            }
+            Some(TokenType::Comment) => {
+                pieces.len() == 1 && !self.continuation
+                    || pieces.len() > 1
+                        && matches!(
+                            pieces[pieces.len() - 2].kind,
+                            TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace
+                        )
+            }
+            _ => false,
+        };
+        // `{}` is one piece but still ends the statement.
+        let ends_statement = ends_statement || pieces.last().is_some_and(|p| p.text == "{}");
+
+        let indent = self.indent + self.continuation as usize;
+        for (indent, line) in self.wrap(pieces, indent) {
+            let mut text = " ".repeat(indent * self.options.indent_width);
+            for (i, piece) in line.iter().enumerate() {
+                if i > 0 && piece.space_before {
+                    text.push(' ');
+                }
+                text.push_str(&piece.text);
+            }
+            self.output.push(text);
+        }
+
+        self.continuation = !ends_statement;
+    }
+
+    fn width(&self, pieces: &[Piece], indent: usize) -> usize {
+        let text: usize = pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| piece.text.len() + (i > 0 && piece.space_before) as usize)
+            .sum();
+        indent * self.options.indent_width + text
+    }
+
+    fn wrap(&self, pieces: Vec<Piece>, indent: usize) -> Vec<(usize, Vec<Piece>)> {
+        if pieces.len() < 2 || self.width(&pieces, indent) <= self.options.max_width {
+            return vec![(indent, pieces)];
+        }
+
+        if let Some(splits) = operator_splits(&pieces) {
+            return self.wrap_segments(split_at(pieces, &splits), indent, indent + 1);
+        }
+
+        if let Some((open, close)) = first_group(&pieces) {
+            let mut rest = pieces;
+            let tail = rest.split_off(close);
+            let inner = rest.split_off(open + 1);
+            let head = rest;
+
+            let commas: Vec<usize> = depth_zero(&inner)
+                .filter(|&(i, kind)| kind == TokenType::Comma && i + 1 < inner.len())
+                .map(|(i, _)| i + 1)
+                .collect();
+
+            let mut lines = self.wrap(head, indent);
+            for segment in split_at(inner, &commas) {
+                lines.extend(self.wrap(segment, indent + 1));
+            }
+            lines.extend(self.wrap(tail, indent));
+            return lines;
+        }
+
+        vec![(indent, pieces)]
+    }
+
+    fn wrap_segments(
+        &self,
+        segments: Vec<Vec<Piece>>,
+        first: usize,
+        rest: usize,
+    ) -> Vec<(usize, Vec<Piece>)> {
+        let mut lines = vec![];
+        for (i, segment) in segments.into_iter().enumerate() {
+            lines.extend(self.wrap(segment, if i == 0 { first } else { rest }));
+        }
+        lines
+    }
+
+    fn finish(mut self) -> String {
+        while self.output.last().is_some_and(|line| line.is_empty()) {
+            self.output.pop();
+        }
+        if self.output.is_empty() {
+            return String::new();
+        }
+        self.output.join("\n") + "\n"
+    }
+}
+
+/// The line a token starts on. Tokens record the line they end on, which differs for strings
+/// that span lines.
+fn start_line(token: &Token) -> usize {
+    token.line_number - token.lexeme.as_str().matches('\n').count()
+}
+
+fn ends_operand(kind: TokenType) -> bool {
+    matches!(
+        kind,
+        TokenType::Identifier
+            | TokenType::Number
+            | TokenType::String
+            | TokenType::RightParen
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::Super
+    )
+}
+
+/// Pieces outside any parentheses, with their index.
+fn depth_zero(pieces: &[Piece]) -> impl Iterator<Item = (usize, TokenType)> + '_ {
+    let mut depth = 0usize;
+    pieces.iter().enumerate().filter_map(move |(i, piece)| {
+        match piece.kind {
+            TokenType::LeftParen => depth += 1,
+            TokenType::RightParen => depth = depth.saturating_sub(1),
+            _ if depth == 0 => return Some((i, piece.kind)),
+            _ => {}
+        }
+        None
+    })
+}
+
+fn precedence(piece: &Piece) -> Option<u8> {
+    match piece.kind {
+        TokenType::Or => Some(0),
+        TokenType::And => Some(1),
+        TokenType::EqualEqual | TokenType::BangEqual => Some(2),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            Some(3)
+        }
+        TokenType::Plus => Some(4),
+        TokenType::Minus if !piece.unary => Some(4),
+        TokenType::Star | TokenType::Slash => Some(5),
+        _ => None,
+    }
+}
+
+/// Where to break a line before its loosest-binding binary operators outside parentheses.
+fn operator_splits(pieces: &[Piece]) -> Option<Vec<usize>> {
+    let operators: Vec<(usize, u8)> = depth_zero(pieces)
+        .filter(|&(i, _)| i > 0)
+        .filter_map(|(i, _)| precedence(&pieces[i]).map(|level| (i, level)))
+        .collect();
+    let loosest = operators.iter().map(|&(_, level)| level).min()?;
+
+    Some(
+        operators
+            .into_iter()
+            .filter(|&(_, level)| level == loosest)
+            .map(|(i, _)| i)
+            .collect(),
+    )
+}
+
+/// The first non-empty parenthesized group at depth zero, as the indices of its parens.
+fn first_group(pieces: &[Piece]) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut open = None;
+    for (i, piece) in pieces.iter().enumerate() {
+        match piece.kind {
+            TokenType::LeftParen => {
+                if depth == 0 {
+                    open = Some(i);
+                }
+                depth += 1;
+            }
+            TokenType::RightParen => {
+                depth -= 1;
+                if depth == 0 {
+                    let open = open?;
+                    if i > open + 1 {
+                        return Some((open, i));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_at(mut pieces: Vec<Piece>, indices: &[usize]) -> Vec<Vec<Piece>> {
+    let mut segments = vec![];
+    for &index in indices.iter().rev() {
+        segments.push(pieces.split_off(index));
+    }
+    segments.push(pieces);
+    segments.reverse();
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_source(&formatted).unwrap(),
+            formatted,
+            "not idempotent"
+        );
+    }
+
+    #[test]
+    fn normalizes_spacing_and_indentation() {
+        assert_formats(
+            "fun  add(a,b){return a+b;}\nvar x=-add(1,2)*(3);if(!x)print x;else{print \"no\";}\nfun empty(){}",
+            "fun add(a, b) {\n    return a + b;\n}\nvar x = -add(1, 2) * (3);\nif (!x) print x;\nelse {\n    print \"no\";\n}\nfun empty() {}\n",
+        );
+        assert_formats(
+            "for(var i=0;i<3;i=i+1){print i - -1;}",
+            "for (var i = 0; i < 3; i = i + 1) {\n    print i - -1;\n}\n",
+        );
+        assert_formats(
+            "var n:Number=1;fun f(a :String,b):Boolean{return true;}",
+            "var n: Number = 1;\nfun f(a: String, b): Boolean {\n    return true;\n}\n",
+        );
+        assert_formats(
+            "try{throw 1;}catch(e){print e;}finally{}",
+            "try {\n    throw 1;\n} catch (e) {\n    print e;\n} finally {}\n",
+        );
+        assert_formats("if (x) {\n}\nprint 1;", "if (x) {}\nprint 1;\n");
+    }
+
+    #[test]
+    fn preserves_comments_and_blank_lines() {
+        assert_formats(
+            "// header\n\n\n\nvar a = 1; // trailing\n{\n\n  // inside\n  print a;\n\n\n  print a;\n}\n\n",
+            "// header\n\nvar a = 1; // trailing\n{\n    // inside\n    print a;\n\n    print a;\n}\n",
+        );
+        assert_formats("print 1 + // why\n  2;", "print 1 + // why\n    2;\n");
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let options = FormatOptions {
+            max_width: 30,
+            ..FormatOptions::default()
+        };
+        let source = "print compute(first, second, third);\nprint aaaaaaaaaa + bbbbbbbbbb * cccccccccc + dddddddddd;";
+        let expected = "print compute(\n    first,\n    second,\n    third\n);\nprint aaaaaaaaaa\n    + bbbbbbbbbb * cccccccccc\n    + dddddddddd;\n";
+
+        let formatted = format_source_with(source, &options).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source_with(&formatted, &options).unwrap(), formatted);
+    }
+
+    #[test]
+    fn rejects_invalid_source() {
+        assert!(matches!(
+            format_source("print (1;"),
+            Err(LoxError::Parse(_))
+        ));
+    }
+}
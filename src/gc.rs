@@ -0,0 +1,467 @@
+use crate::callable::NativeFunction;
+use crate::error::RuntimeError;
+use crate::expr::LiteralValue;
+use crate::module;
+use crate::symbol::Symbol;
+use crate::value::{Function, Value, ValueKind};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+/// A handle to an object on the `Heap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GcRef(u32);
+
+impl GcRef {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    #[cfg(any(feature = "nan-boxing", test))]
+    pub(crate) fn from_index(index: u32) -> Self {
+        GcRef(index)
+    }
+}
+
+#[derive(Debug)]
+pub enum Object {
+    String(Rc<str>),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Native(Rc<NativeFunction>),
+    /// A runtime error caught by a `catch` clause.
+    Error(Rc<RuntimeError>),
+    Module(ObjModule),
+}
+
+/// A compiled function loaded into the heap, with its constant pool materialized as values.
+#[derive(Debug)]
+pub struct ObjFunction {
+    pub function: Rc<Function>,
+    pub constants: Vec<Value>,
+}
+
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: GcRef,
+    pub upvalues: Vec<GcRef>,
+    /// The module whose globals the function uses, or `None` for the main script's.
+    pub module: Option<GcRef>,
+}
+
+/// An imported module: the globals its top-level code defined, which its functions keep
+/// using after the import.
+#[derive(Debug)]
+pub struct ObjModule {
+    pub file: Rc<str>,
+    pub globals: HashMap<Symbol, Value>,
+}
+
+/// A captured variable: it points at a stack slot while the enclosing call is active and
+/// holds the value itself once that slot goes away.
+#[derive(Debug)]
+pub enum ObjUpvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// Tuning for the collector. A collection runs once the bytes allocated exceed the current
+/// threshold, which is then reset to `growth_factor` times the surviving bytes, but never
+/// below `initial_threshold`. In stress mode every allocation collects first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    pub initial_threshold: usize,
+    pub growth_factor: f64,
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: 1024 * 1024,
+            growth_factor: 2.0,
+            stress: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub live_objects: usize,
+    pub bytes_allocated: usize,
+    pub collections: usize,
+    pub objects_freed: usize,
+}
+
+struct Entry {
+    object: Object,
+    size: usize,
+    marked: bool,
+}
+
+/// A mark-and-sweep heap. The owner supplies the roots to `collect`; everything reachable
+/// from them survives and everything else is freed, cycles included.
+///
+/// Strings are interned: there is at most one live string object per distinct text, so two
+/// string values are equal exactly when their handles are.
+pub struct Heap {
+    entries: Vec<Option<Entry>>,
+    strings: HashMap<Rc<str>, GcRef>,
+    free: Vec<u32>,
+    gray: Vec<GcRef>,
+    next_gc: usize,
+    config: GcConfig,
+    stats: HeapStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Self {
+            entries: vec![],
+            strings: HashMap::new(),
+            free: vec![],
+            gray: vec![],
+            next_gc: config.initial_threshold,
+            config,
+            stats: HeapStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> GcConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_gc = self.next_gc.max(config.initial_threshold);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.stats.bytes_allocated > self.next_gc
+    }
+
+    /// Stores an object without collecting. Callers that can supply roots should check
+    /// `should_collect` first. Strings must go through `alloc_string` so they are interned.
+    pub fn alloc(&mut self, object: Object) -> GcRef {
+        let size = object_size(&object);
+        self.stats.bytes_allocated += size;
+        self.stats.live_objects += 1;
+
+        let entry = Some(Entry {
+            object,
+            size,
+            marked: false,
+        });
+
+        match self.free.pop() {
+            Some(index) => {
+                self.entries[index as usize] = entry;
+                GcRef(index)
+            }
+            None => {
+                self.entries.push(entry);
+                GcRef((self.entries.len() - 1) as u32)
+            }
+        }
+    }
+
+    /// Returns the existing string object with this text, or allocates one.
+    pub fn alloc_string(&mut self, value: &str) -> GcRef {
+        if let Some(reference) = self.find_string(value) {
+            return reference;
+        }
+
+        let value: Rc<str> = value.into();
+        let reference = self.alloc(Object::String(value.clone()));
+        self.strings.insert(value, reference);
+        reference
+    }
+
+    pub fn find_string(&self, value: &str) -> Option<GcRef> {
+        self.strings.get(value).copied()
+    }
+
+    pub fn get(&self, reference: GcRef) -> &Object {
+        match &self.entries[reference.index()] {
+            Some(entry) => &entry.object,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    pub fn get_mut(&mut self, reference: GcRef) -> &mut Object {
+        match &mut self.entries[reference.index()] {
+            Some(entry) => &mut entry.object,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    pub fn as_str(&self, reference: GcRef) -> Option<&str> {
+        match self.get(reference) {
+            Object::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn function(&self, reference: GcRef) -> &ObjFunction {
+        match self.get(reference) {
+            Object::Function(function) => function,
+            other => panic!("expected a function, found {:?}", other),
+        }
+    }
+
+    pub fn closure(&self, reference: GcRef) -> &ObjClosure {
+        match self.get(reference) {
+            Object::Closure(closure) => closure,
+            other => panic!("expected a closure, found {:?}", other),
+        }
+    }
+
+    pub fn module(&self, reference: GcRef) -> &ObjModule {
+        match self.get(reference) {
+            Object::Module(module) => module,
+            other => panic!("expected a module, found {:?}", other),
+        }
+    }
+
+    pub fn module_mut(&mut self, reference: GcRef) -> &mut ObjModule {
+        match self.get_mut(reference) {
+            Object::Module(module) => module,
+            other => panic!("expected a module, found {:?}", other),
+        }
+    }
+
+    pub fn upvalue(&self, reference: GcRef) -> &ObjUpvalue {
+        match self.get(reference) {
+            Object::Upvalue(upvalue) => upvalue,
+            other => panic!("expected an upvalue, found {:?}", other),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, reference: GcRef) -> &mut ObjUpvalue {
+        match self.get_mut(reference) {
+            Object::Upvalue(upvalue) => upvalue,
+            other => panic!("expected an upvalue, found {:?}", other),
+        }
+    }
+
+    /// Marks everything reachable from `roots`, then frees every unmarked object.
+    pub fn collect<I: IntoIterator<Item = Value>>(&mut self, roots: I) {
+        for root in roots {
+            self.mark_value(root);
+        }
+
+        while let Some(reference) = self.gray.pop() {
+            self.blacken(reference);
+        }
+
+        self.sweep();
+        self.stats.collections += 1;
+        self.next_gc = ((self.stats.bytes_allocated as f64 * self.config.growth_factor) as usize)
+            .max(self.config.initial_threshold);
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Some(reference) = value.as_obj() {
+            self.mark_object(reference);
+        }
+    }
+
+    pub fn mark_object(&mut self, reference: GcRef) {
+        if let Some(entry) = &mut self.entries[reference.index()] {
+            if !entry.marked {
+                entry.marked = true;
+                self.gray.push(reference);
+            }
+        }
+    }
+
+    fn blacken(&mut self, reference: GcRef) {
+        let mut children = vec![];
+        match self.get(reference) {
+            Object::String(_) | Object::Native(_) | Object::Error(_) => {}
+            Object::Function(function) => children.extend(function.constants.iter().copied()),
+            Object::Closure(closure) => {
+                children.push(Value::obj(closure.function));
+                children.extend(closure.upvalues.iter().map(|&upvalue| Value::obj(upvalue)));
+                children.extend(closure.module.map(Value::obj));
+            }
+            Object::Module(module) => children.extend(module.globals.values().copied()),
+            Object::Upvalue(ObjUpvalue::Closed(value)) => children.push(*value),
+            Object::Upvalue(ObjUpvalue::Open(_)) => {}
+        }
+
+        for child in children {
+            self.mark_value(child);
+        }
+    }
+
+    fn sweep(&mut self) {
+        for (index, slot) in self.entries.iter_mut().enumerate() {
+            let Some(entry) = slot else { continue };
+
+            if entry.marked {
+                entry.marked = false;
+                continue;
+            }
+
+            if let Object::String(s) = &entry.object {
+                self.strings.remove(s);
+            }
+            self.stats.bytes_allocated -= entry.size;
+            self.stats.live_objects -= 1;
+            self.stats.objects_freed += 1;
+            *slot = None;
+            self.free.push(index as u32);
+        }
+    }
+
+    pub fn display(&self, value: Value) -> ValueDisplay<'_> {
+        ValueDisplay { heap: self, value }
+    }
+
+    /// Converts into the value type used by the embedding API. Compiled functions and
+    /// modules have no tree-walker equivalent and yield `None`.
+    pub fn to_literal(&self, value: Value) -> Option<LiteralValue> {
+        match value.kind() {
+            ValueKind::Nil => Some(LiteralValue::Nil),
+            ValueKind::Bool(b) => Some(LiteralValue::from(b)),
+            ValueKind::Number(n) => Some(LiteralValue::Number(n)),
+            ValueKind::Obj(reference) => match self.get(reference) {
+                Object::String(s) => Some(LiteralValue::StringValue(s.clone())),
+                Object::Native(native) => Some(LiteralValue::Native(native.clone())),
+                Object::Error(error) => Some(LiteralValue::Error(error.clone())),
+                Object::Function(_) | Object::Closure(_) | Object::Upvalue(_) => None,
+                Object::Module(_) => None,
+            },
+        }
+    }
+
+    /// Converts from the value type used by the embedding API, allocating strings and natives
+    /// on the heap. Tree-walker functions and modules cannot run on the VM and yield `None`.
+    pub fn from_literal(&mut self, value: &LiteralValue) -> Option<Value> {
+        match value {
+            LiteralValue::Number(n) => Some(Value::number(*n)),
+            LiteralValue::StringValue(s) => Some(Value::obj(self.alloc_string(s))),
+            LiteralValue::True => Some(Value::bool(true)),
+            LiteralValue::False => Some(Value::bool(false)),
+            LiteralValue::Nil => Some(Value::NIL),
+            LiteralValue::Native(native) => {
+                Some(Value::obj(self.alloc(Object::Native(native.clone()))))
+            }
+            LiteralValue::Error(error) => {
+                Some(Value::obj(self.alloc(Object::Error(error.clone()))))
+            }
+            LiteralValue::Function(_) | LiteralValue::Module(_) => None,
+        }
+    }
+}
+
+pub struct ValueDisplay<'a> {
+    heap: &'a Heap,
+    value: Value,
+}
+
+impl fmt::Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value.kind() {
+            ValueKind::Nil => write!(f, "nil"),
+            ValueKind::Bool(b) => write!(f, "{}", b),
+            ValueKind::Number(n) => write!(f, "{}", n),
+            ValueKind::Obj(reference) => match self.heap.get(reference) {
+                Object::String(s) => write!(f, "{}", s),
+                Object::Function(function) => write!(f, "{}", function.function),
+                Object::Closure(closure) => {
+                    write!(f, "{}", self.heap.function(closure.function).function)
+                }
+                Object::Upvalue(_) => write!(f, "upvalue"),
+                Object::Native(_) => write!(f, "<native fn>"),
+                Object::Error(error) => write!(f, "{}", error.message),
+                Object::Module(module) => write!(f, "<module {}>", module::name(&module.file)),
+            },
+        }
+    }
+}
+
+fn object_size(object: &Object) -> usize {
+    mem::size_of::<Entry>()
+        + match object {
+            Object::String(s) => s.len(),
+            Object::Function(function) => function.constants.len() * mem::size_of::<Value>(),
+            Object::Closure(closure) => closure.upvalues.len() * mem::size_of::<GcRef>(),
+            Object::Upvalue(_) | Object::Native(_) => 0,
+            Object::Error(error) => error.message.len(),
+            Object::Module(module) => {
+                module.globals.len() * (mem::size_of::<Symbol>() + mem::size_of::<Value>())
+            }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let mut heap = Heap::default();
+
+        let function = heap.alloc(Object::Function(ObjFunction {
+            function: Rc::new(Function::default()),
+            constants: vec![],
+        }));
+        let upvalue = heap.alloc(Object::Upvalue(ObjUpvalue::Open(0)));
+        let closure = heap.alloc(Object::Closure(ObjClosure {
+            function,
+            upvalues: vec![upvalue],
+            module: None,
+        }));
+        // The closure captures a variable that holds the closure itself.
+        *heap.upvalue_mut(upvalue) = ObjUpvalue::Closed(Value::obj(closure));
+
+        heap.collect([Value::obj(closure)]);
+        assert_eq!(heap.stats().live_objects, 3);
+
+        heap.collect([]);
+        assert_eq!(heap.stats().live_objects, 0);
+        assert_eq!(heap.stats().bytes_allocated, 0);
+        assert_eq!(heap.stats().objects_freed, 3);
+    }
+
+    #[test]
+    fn threshold_grows_with_surviving_bytes() {
+        let mut heap = Heap::new(GcConfig {
+            initial_threshold: 0,
+            growth_factor: 2.0,
+            stress: false,
+        });
+        let kept = heap.alloc_string("kept");
+        assert!(heap.should_collect());
+
+        heap.collect([Value::obj(kept)]);
+        assert!(!heap.should_collect());
+        assert_eq!(heap.as_str(kept), Some("kept"));
+    }
+
+    #[test]
+    fn strings_are_interned_until_collected() {
+        let mut heap = Heap::default();
+        let a = heap.alloc_string("text");
+        assert_eq!(heap.alloc_string("text"), a);
+        assert_ne!(heap.alloc_string("other"), a);
+
+        heap.collect([]);
+        assert_eq!(heap.find_string("text"), None);
+        assert_eq!(heap.stats().live_objects, 0);
+    }
+}
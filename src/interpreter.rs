@@ -1,84 +1,958 @@
-use crate::{error::RuntimeError, expr::*, TokenType};
-
-fn evaluate(expr: &Expr) -> Result<LiteralValue, RuntimeError> {
-    match expr {
-        Expr::Literal { value } => Ok(value.clone()),
-        Expr::Grouping { expression } => evaluate(expression),
-        Expr::Unary { operator, right } => {
-            let right_val = evaluate(right)?;
-            match operator.token_type {
-                TokenType::Bang => Ok(match !is_truthy(right_val) {
-                    true => LiteralValue::True,
-                    false => LiteralValue::False,
-                }),
-                TokenType::Minus => match right_val {
-                    LiteralValue::Number(n) => Ok(LiteralValue::Number(-n)),
-                    _ => Err(RuntimeError::new(
-                        "Operand must be a number.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                _ => Err(RuntimeError::new(
-                    "Invalid unary operator.",
-                    operator.line_number,
-                    operator.clone(),
-                )),
+use crate::bytecode;
+use crate::callable::{LoxFunction, NativeFunction};
+use crate::compiler;
+use crate::cst::{self, SyntaxNode};
+use crate::debug;
+use crate::debugger::Debugger;
+use crate::environment::{Environment, Scopes};
+use crate::error::{LoxError, RuntimeError, StackFrame};
+use crate::expr::*;
+use crate::gc::GcConfig;
+use crate::limits::{self, Interrupt, Limit, Limits, StackGuard};
+use crate::module::{self, Module};
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::repl::Repl;
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::stmt::Stmt;
+use crate::symbol::{intern, Symbol};
+use crate::typecheck;
+use crate::value::Function;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Ways statement execution can leave the normal flow of control.
+enum Unwind {
+    Error(RuntimeError),
+    Return(LiteralValue),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Unwind {
+    fn into_error(self) -> RuntimeError {
+        match self {
+            Unwind::Error(error) => error,
+            Unwind::Return(_) => unreachable!("the parser rejects top-level returns"),
+        }
+    }
+}
+
+/// A call in progress on the tree-walker, innermost last.
+struct Frame {
+    /// `None` for the top-level script.
+    function: Option<Rc<LoxFunction>>,
+    /// The line of the statement running in this frame, or of the call it is waiting on.
+    line: usize,
+    /// The scope this frame was in when it made the call it is waiting on. The innermost
+    /// frame's scope is the interpreter's current environment.
+    environment: Option<Rc<RefCell<Environment>>>,
+    /// The module a top-level frame is running. `None` for the main script, which is
+    /// running `Interpreter::file`.
+    module: Option<Rc<str>>,
+}
+
+/// Which engine runs scripts: the tree-walker evaluates the AST directly, the virtual machine
+/// compiles it to bytecode first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
+
+/// A tree-walking interpreter that keeps its globals alive across calls, so a host can
+/// evaluate several snippets against the same session.
+///
+/// `print` writes to the output sink and `readLine()` reads from the input stream; both
+/// default to stdio and can be swapped out to capture, redirect or discard script I/O.
+pub struct Interpreter {
+    backend: Backend,
+    vm: Vm,
+    globals: Rc<RefCell<Environment>>,
+    /// The block and call scopes, which are collected when only cycles keep them alive.
+    scopes: Scopes,
+    /// Globals set through [`Interpreter::set_global`], which survive a [`Interpreter::reset`].
+    host_globals: HashMap<Symbol, LiteralValue>,
+    environment: Rc<RefCell<Environment>>,
+    output: Box<dyn Write>,
+    input: Rc<RefCell<Box<dyn BufRead>>>,
+    trace: Option<Box<dyn Write>>,
+    trace_depth: usize,
+    bytecode_cache: bool,
+    optimize: bool,
+    frames: Vec<Frame>,
+    /// The file being run, which backtraces and the functions it declares refer to.
+    file: Option<Rc<str>>,
+    /// Modules that have been imported, by canonical path.
+    modules: HashMap<PathBuf, Rc<Module>>,
+    /// Modules whose top-level code is running, outermost first.
+    loading: Vec<PathBuf>,
+    debugger: Option<Debugger>,
+    limits: Limits,
+    /// Statements the tree-walker has executed since `interpret` was called.
+    steps: u64,
+    /// Bytes the tree-walker has allocated since it last measured what scripts can reach.
+    allocated: usize,
+    /// Shared with the VM, so one handle stops either backend.
+    interrupt: Interrupt,
+    /// When the script the tree-walker is running times out.
+    deadline: Option<Instant>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_io(BufReader::new(io::stdin()), io::stdout())
+    }
+
+    pub fn with_io<R, W>(input: R, output: W) -> Self
+    where
+        R: BufRead + 'static,
+        W: Write + 'static,
+    {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let input: Rc<RefCell<Box<dyn BufRead>>> = Rc::new(RefCell::new(Box::new(input)));
+        let mut interpreter = Self {
+            backend: Backend::default(),
+            vm: Vm::new(),
+            environment: globals.clone(),
+            globals,
+            scopes: Scopes::new(),
+            host_globals: HashMap::new(),
+            output: Box::new(output),
+            input: input.clone(),
+            trace: None,
+            trace_depth: 0,
+            bytecode_cache: false,
+            optimize: true,
+            frames: vec![Frame {
+                function: None,
+                line: 0,
+                environment: None,
+                module: None,
+            }],
+            file: None,
+            modules: HashMap::new(),
+            loading: vec![],
+            debugger: None,
+            limits: Limits::default(),
+            steps: 0,
+            allocated: 0,
+            interrupt: Interrupt::new(),
+            deadline: None,
+        };
+
+        interpreter.vm.set_optimize(interpreter.optimize);
+        interpreter.vm.set_interrupt(interpreter.interrupt.clone());
+
+        interpreter.define_native("clock", 0, |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?;
+            Ok(LiteralValue::Number(now.as_secs_f64()))
+        });
+
+        interpreter.define_native("readLine", 0, move |_| {
+            let mut line = String::new();
+            match input.borrow_mut().read_line(&mut line) {
+                Ok(0) => Ok(LiteralValue::Nil),
+                Ok(_) => {
+                    let trimmed = line.trim_end_matches(['\n', '\r']);
+                    Ok(LiteralValue::StringValue(trimmed.into()))
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        });
+
+        interpreter.define_native("errorMessage", 1, |args| match &args[0] {
+            LiteralValue::Error(error) => {
+                Ok(LiteralValue::StringValue(error.message.as_str().into()))
             }
+            _ => Err("errorMessage expects an error.".to_string()),
+        });
+
+        interpreter.define_native("errorLine", 1, |args| match &args[0] {
+            LiteralValue::Error(error) => Ok(LiteralValue::Number(error.line as f64)),
+            _ => Err("errorLine expects an error.".to_string()),
+        });
+
+        interpreter
+    }
+
+    /// Selects the engine used by later calls. Globals set by the host and natives are
+    /// shared, but globals defined by scripts stay with the backend that ran them.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Attaches a debugger that can pause the tree-walker before each statement. The VM
+    /// runs on regardless.
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn take_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+
+    /// Logs execution to `trace`: every bytecode instruction with the value stack on the VM,
+    /// every executed statement and evaluated expression on the tree-walker.
+    pub fn set_trace<W: Write + 'static>(&mut self, trace: W) {
+        self.trace = Some(Box::new(trace));
+    }
+
+    pub fn clear_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// Compiles `source` for the VM and returns the disassembled bytecode.
+    pub fn disassemble(&self, source: &str) -> Result<String, LoxError> {
+        let function = self.compile_source(source)?;
+        Ok(debug::disassemble_function(&function))
+    }
+
+    /// Reports the scan, parse or compile error `source` would fail with, or else the type
+    /// errors in it, without running it.
+    pub fn check(&self, source: &str) -> Result<(), LoxError> {
+        let tree = self.parse_tree(source)?;
+        self.compile(&self.optimized(tree.to_stmts()))?;
+        let errors = typecheck::check(&tree);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(LoxError::Type(errors))
         }
-        Expr::Binary {
-            left,
-            operator,
-            right,
-        } => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
+    }
 
-            match operator.token_type {
-                TokenType::Minus => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => {
-                        Ok(LiteralValue::Number(l - r))
+    /// Compiles `source` into the on-disk format described in `bytecode`.
+    pub fn compile_to_bytes(&self, source: &str) -> Result<Vec<u8>, LoxError> {
+        let function = self.compile_source(source)?;
+        bytecode::write_script(&function, bytecode::hash_source(source)).map_err(LoxError::Load)
+    }
+
+    /// Runs a script produced by `compile_to_bytes` on the VM, whatever the selected backend.
+    pub fn run_compiled(&mut self, bytes: &[u8]) -> Result<LiteralValue, LoxError> {
+        let script = bytecode::read_script(bytes).map_err(LoxError::Load)?;
+        self.run_function(script.function)
+    }
+
+    /// When enabled, `run_file` on the VM backend keeps compiled scripts in a `.rlox-cache`
+    /// directory next to the source and reuses them while the source hash is unchanged.
+    pub fn set_bytecode_cache(&mut self, enabled: bool) {
+        self.bytecode_cache = enabled;
+    }
+
+    /// Tunes the VM's garbage collector. Has no effect on the tree-walker.
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.vm.set_gc_config(config);
+    }
+
+    /// Caps the steps, call depth, memory and running time of the scripts run from now on,
+    /// on both backends. See [`Limits`].
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.vm.set_limits(limits);
+    }
+
+    /// A handle another thread can use to stop whatever script this interpreter is running,
+    /// on either backend. The script fails with an `Interrupted.` error.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    pub fn set_input<R: BufRead + 'static>(&mut self, input: R) {
+        *self.input.borrow_mut() = Box::new(input);
+    }
+
+    /// Enables or disables constant folding before code runs or is compiled. On by default.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+        self.vm.set_optimize(optimize);
+    }
+
+    /// Runs `source` and returns the value of its trailing expression statement, or nil.
+    pub fn eval_str(&mut self, source: &str) -> Result<LiteralValue, LoxError> {
+        let statements = self.parse_source(source)?;
+        match self.backend {
+            Backend::TreeWalker => {
+                let result = self.interpret(&statements).map_err(LoxError::Runtime);
+                self.output
+                    .flush()
+                    .map_err(|e| LoxError::Io(e.to_string()))?;
+                result
+            }
+            Backend::Vm => {
+                let function = self.compile(&statements)?;
+                self.run_function(function)
+            }
+        }
+    }
+
+    /// Runs a source file, or a compiled script if the file starts with the bytecode magic.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoxError> {
+        let path = path.as_ref();
+        let bytes =
+            fs::read(path).map_err(|e| LoxError::Io(format!("{}: {}", path.display(), e)))?;
+        let previous = self.file.replace(path.display().to_string().into());
+        let result = self.run_bytes(path, bytes);
+        self.file = previous;
+        result
+    }
+
+    fn run_bytes(&mut self, path: &Path, bytes: Vec<u8>) -> Result<(), LoxError> {
+        if bytecode::is_compiled(&bytes) {
+            return self.run_compiled(&bytes).map(|_| ());
+        }
+
+        let source = String::from_utf8(bytes).map_err(|e| LoxError::Io(e.to_string()))?;
+        // Cached bytecode is always optimized, so it is bypassed when optimization is off.
+        if self.backend == Backend::Vm && self.bytecode_cache && self.optimize {
+            return self.run_cached(path, &source).map(|_| ());
+        }
+        self.eval_str(&source).map(|_| ())
+    }
+
+    /// Runs `source` like [`Interpreter::eval_str`], naming `file` as where it came from in
+    /// backtraces.
+    pub fn eval_file(&mut self, file: &str, source: &str) -> Result<LiteralValue, LoxError> {
+        let previous = self.file.replace(file.into());
+        let result = self.eval_str(source);
+        self.file = previous;
+        result
+    }
+
+    fn run_cached(&mut self, path: &Path, source: &str) -> Result<LiteralValue, LoxError> {
+        let cache = cache_path(path);
+        let hash = bytecode::hash_source(source);
+
+        if let Ok(bytes) = fs::read(&cache) {
+            if bytecode::read_source_hash(&bytes) == Some(hash) {
+                if let Ok(script) = bytecode::read_script(&bytes) {
+                    return self.run_function(script.function);
+                }
+            }
+        }
+
+        let function = self.compile_source(source)?;
+        // The cache is best-effort: a read-only directory just means we compile every time.
+        if let Ok(bytes) = bytecode::write_script(&function, hash) {
+            if let Some(dir) = cache.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(&cache, bytes);
+        }
+        self.run_function(function)
+    }
+
+    /// Parses `source` into a concrete syntax tree, within the configured limits.
+    pub fn parse_tree(&self, source: &str) -> Result<SyntaxNode, LoxError> {
+        let _stack = StackGuard::new(self.limits.max_stack_bytes);
+        cst::parse(source, self.limits.max_nesting)
+    }
+
+    fn parse_source(&self, source: &str) -> Result<Vec<Stmt>, LoxError> {
+        let _stack = StackGuard::new(self.limits.max_stack_bytes);
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().map_err(LoxError::Scan)?;
+        let statements = Parser::new(tokens)
+            .max_nesting(self.limits.max_nesting)
+            .parse()
+            .map_err(LoxError::Parse)?;
+        Ok(self.optimized(statements))
+    }
+
+    fn optimized(&self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        if self.optimize {
+            optimizer::optimize(statements)
+        } else {
+            statements
+        }
+    }
+
+    fn compile_source(&self, source: &str) -> Result<Function, LoxError> {
+        let statements = self.parse_source(source)?;
+        self.compile(&statements)
+    }
+
+    fn compile(&self, statements: &[Stmt]) -> Result<Function, LoxError> {
+        let _stack = StackGuard::new(self.limits.max_stack_bytes);
+        compiler::compile(statements).map_err(LoxError::Compile)
+    }
+
+    fn run_function(&mut self, function: Function) -> Result<LiteralValue, LoxError> {
+        let trace = self.trace.as_deref_mut().map(|t| t as &mut dyn Write);
+        let file = self.file.as_deref().map(str::to_string);
+        self.vm.set_file(self.file.clone());
+        let result = self
+            .vm
+            .interpret(function, &mut *self.output, trace)
+            .map_err(|mut error| {
+                // The VM knows which module each frame came from, but not the main script.
+                for frame in error.backtrace.iter_mut() {
+                    if frame.file.is_none() {
+                        frame.file = file.clone();
                     }
-                    
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::Slash => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => {
-                        Ok(LiteralValue::Number(l / r))
+                }
+                LoxError::Runtime(error)
+            });
+        self.output
+            .flush()
+            .map_err(|e| LoxError::Io(e.to_string()))?;
+        result
+    }
+
+    /// Runs a REPL that reads from the input stream and echoes results, errors and prompts to
+    /// the output sink, until the input ends. See `Repl` for line editing and history.
+    pub fn run_prompt(&mut self) -> Result<(), LoxError> {
+        Repl::new(self).run()
+    }
+
+    /// Reads one line from the input stream, without its line ending, or `None` at the end.
+    pub(crate) fn read_input_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.input.borrow_mut().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(Some(line))
+    }
+
+    pub(crate) fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        match self.backend {
+            Backend::TreeWalker => self.globals.borrow().get_local(intern(name)),
+            Backend::Vm => self.vm.get_global(name),
+        }
+    }
+
+    pub fn set_global<V: Into<LiteralValue>>(&mut self, name: &str, value: V) {
+        let value = value.into();
+        self.vm.set_global(name, &value);
+        self.globals
+            .borrow_mut()
+            .define(intern(name), value.clone());
+        self.host_globals.insert(intern(name), value);
+    }
+
+    /// The globals visible to the current backend with their values as `print` shows them,
+    /// sorted by name.
+    pub fn globals(&self) -> Vec<(Symbol, String)> {
+        let mut globals = match self.backend {
+            Backend::TreeWalker => self
+                .globals
+                .borrow()
+                .bindings()
+                .map(|(name, value)| (name, value.to_string()))
+                .collect(),
+            Backend::Vm => self.vm.globals(),
+        };
+        globals.sort_by(|(l, _), (r, _)| l.cmp(r));
+        globals
+    }
+
+    /// Forgets every global defined by scripts on both backends, keeping natives and the
+    /// globals set by the host. Settings such as the backend and I/O sinks are left alone.
+    pub fn reset(&mut self) {
+        let gc_config = self.vm.gc_config();
+        self.vm = Vm::new();
+        self.vm.set_gc_config(gc_config);
+        self.vm.set_optimize(self.optimize);
+        self.vm.set_limits(self.limits);
+        self.vm.set_interrupt(self.interrupt.clone());
+        self.globals = Rc::new(RefCell::new(Environment::new()));
+        self.environment = self.globals.clone();
+        self.modules.clear();
+        for (name, value) in &self.host_globals {
+            self.vm.set_global(name.as_str(), value);
+            self.globals
+                .borrow_mut()
+                .define(name.clone(), value.clone());
+        }
+    }
+
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, fun: F)
+    where
+        F: Fn(&[LiteralValue]) -> Result<LiteralValue, String> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, fun);
+        self.set_global(name, LiteralValue::Native(Rc::new(native)));
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<LiteralValue, RuntimeError> {
+        let _stack = StackGuard::new(self.limits.max_stack_bytes);
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.interpret_statements(statements).map_err(|mut error| {
+            self.attach_backtrace(&mut error);
+            error
+        })
+    }
+
+    fn interpret_statements(&mut self, statements: &[Stmt]) -> Result<LiteralValue, RuntimeError> {
+        let mut result = LiteralValue::Nil;
+
+        for statement in statements {
+            result = match statement {
+                Stmt::Expression { expression } => {
+                    self.enter_statement(statement)?;
+                    self.evaluate(expression)?
+                }
+                _ => {
+                    self.execute(statement).map_err(Unwind::into_error)?;
+                    LiteralValue::Nil
+                }
+            };
+        }
+
+        Ok(result)
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        self.check_stack()?;
+        self.execute_statement(stmt)
+    }
+
+    fn execute_statement(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        if self.trace.is_some() {
+            self.trace_line(&format!("exec {}", stmt_label(stmt)));
+        }
+        self.enter_statement(stmt)?;
+
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.evaluate(expression)?;
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                let value = self.evaluate(expression)?;
+                writeln!(self.output, "{}", value).map_err(|e| {
+                    RuntimeError::new(
+                        &format!("Could not write output: {}.", e),
+                        keyword.line_number,
+                        keyword.clone(),
+                    )
+                })?;
+            }
+            Stmt::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => LiteralValue::Nil,
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.symbol(), value);
+            }
+            Stmt::Block { statements } => {
+                let environment = Environment::new_enclosed(self.environment.clone());
+                self.execute_block(statements, environment)?;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if is_truthy(self.evaluate(condition)?) {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while is_truthy(self.evaluate(condition)?) {
+                    self.check_interrupt()?;
+                    self.execute(body)?;
+                }
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                let environment = Environment::new_enclosed(self.environment.clone());
+                let environment = self.scopes.track(environment);
+                let previous = std::mem::replace(&mut self.environment, environment);
+                let result = self.execute_for(initializer.as_deref(), condition, increment, body);
+                self.environment = previous;
+                result?;
+            }
+            Stmt::Function { name, params, body } => {
+                self.allocate(mem::size_of::<LoxFunction>(), name.line_number)?;
+                let function = LoxFunction {
+                    declaration: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    closure: self.environment.clone(),
+                    file: self.current_file(),
+                };
+                self.environment.borrow_mut().define(
+                    name.lexeme.symbol(),
+                    LiteralValue::Function(Rc::new(function)),
+                );
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => LiteralValue::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+            } => {
+                let module = self.import(keyword, path)?;
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.symbol(), LiteralValue::Module(module));
+            }
+            Stmt::Throw { keyword, value } => {
+                let value = self.evaluate(value)?;
+                return Err(Unwind::Error(thrown_error(value, keyword)));
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let environment = Environment::new_enclosed(self.environment.clone());
+                let result = match (self.execute_block(body, environment), catch) {
+                    (Err(Unwind::Error(error)), Some((name, body))) if !error.fatal => {
+                        self.execute_catch(error, name, body)
                     }
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::Star => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => {
-                        Ok(LiteralValue::Number(l * r))
+                    (result, _) => result,
+                };
+
+                if let Some(body) = finally {
+                    // A fatal error skips cleanup just as it skips handlers.
+                    if !matches!(&result, Err(Unwind::Error(error)) if error.fatal) {
+                        let environment = Environment::new_enclosed(self.environment.clone());
+                        self.execute_block(body, environment)?;
                     }
+                }
+                result?;
+            }
+        }
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::Plus => match (left_val, right_val) {
+        Ok(())
+    }
+
+    fn execute_for(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: &Option<Expr>,
+        increment: &Option<Expr>,
+        body: &Stmt,
+    ) -> Result<(), Unwind> {
+        if let Some(initializer) = initializer {
+            self.execute(initializer)?;
+        }
+
+        loop {
+            if let Some(condition) = condition {
+                if !is_truthy(self.evaluate(condition)?) {
+                    return Ok(());
+                }
+            }
+
+            self.check_interrupt()?;
+            self.execute(body)?;
+
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+    }
+
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), Unwind> {
+        let environment = self.scopes.track(environment);
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement));
+        self.environment = previous;
+        result
+    }
+
+    /// Runs a catch clause with its variable bound to the thrown value, or to an error
+    /// value for errors raised by the runtime.
+    fn execute_catch(
+        &mut self,
+        mut error: RuntimeError,
+        name: &Token,
+        body: &[Stmt],
+    ) -> Result<(), Unwind> {
+        self.attach_backtrace(&mut error);
+        let value = match error.value.take() {
+            Some(value) => *value,
+            None => LiteralValue::Error(Rc::new(error)),
+        };
+        let mut environment = Environment::new_enclosed(self.environment.clone());
+        environment.define(name.lexeme.symbol(), value);
+        self.execute_block(body, environment)
+    }
+
+    fn call(
+        &mut self,
+        callee: LiteralValue,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        self.check_interrupt()?;
+        let arity = match &callee {
+            LiteralValue::Function(function) => function.arity(),
+            LiteralValue::Native(native) => native.arity,
+            _ => {
+                return Err(RuntimeError::new(
+                    "Can only call functions.",
+                    paren.line_number,
+                    paren.clone(),
+                ))
+            }
+        };
+
+        if arguments.len() != arity {
+            return Err(RuntimeError::new(
+                &format!("Expected {} arguments but got {}.", arity, arguments.len()),
+                paren.line_number,
+                paren.clone(),
+            ));
+        }
+
+        match callee {
+            LiteralValue::Function(function) => {
+                self.check_depth(paren.line_number)?;
+                let scope = mem::size_of::<Environment>() + arity * BINDING_SIZE;
+                self.allocate(scope, paren.line_number)?;
+                let mut environment = Environment::new_enclosed(function.closure.clone());
+                for (param, argument) in function.params.iter().zip(arguments) {
+                    environment.define(param.lexeme.symbol(), argument);
+                }
+
+                if let Some(caller) = self.frames.last_mut() {
+                    caller.line = paren.line_number;
+                    caller.environment = Some(self.environment.clone());
+                }
+                self.frames.push(Frame {
+                    function: Some(function.clone()),
+                    line: function.declaration.line_number,
+                    environment: None,
+                    module: None,
+                });
+                let mut result = self.execute_block(&function.body, environment);
+                if let Err(Unwind::Error(error)) = &mut result {
+                    self.attach_backtrace(error);
+                }
+                self.frames.pop();
+                if let Some(caller) = self.frames.last_mut() {
+                    caller.environment = None;
+                }
+
+                match result {
+                    Ok(()) => Ok(LiteralValue::Nil),
+                    Err(Unwind::Return(value)) => Ok(value),
+                    Err(Unwind::Error(error)) => Err(error),
+                }
+            }
+            LiteralValue::Native(native) => (native.fun)(&arguments)
+                .map_err(|message| RuntimeError::new(&message, paren.line_number, paren.clone())),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Runs the module `path` names the first time it is imported, in a scope of its own
+    /// that starts out with the natives and host globals.
+    fn import(&mut self, keyword: &Token, path: &str) -> Result<Rc<Module>, RuntimeError> {
+        let error =
+            |message: String| RuntimeError::new(&message, keyword.line_number, keyword.clone());
+        let file = module::resolve(self.current_file().as_deref(), path).map_err(error)?;
+        if let Some(module) = self.modules.get(&file) {
+            return Ok(module.clone());
+        }
+        module::check_cycle(self.file.as_deref(), &self.loading, &file).map_err(error)?;
+        self.check_depth(keyword.line_number)?;
+        let statements =
+            module::parse(&file, self.optimize, self.limits.max_nesting).map_err(error)?;
+
+        let mut environment = Environment::new();
+        for (name, value) in &self.host_globals {
+            environment.define(name.clone(), value.clone());
+        }
+        let module = Rc::new(Module {
+            file: file.display().to_string().into(),
+            environment: Rc::new(RefCell::new(environment)),
+        });
+
+        if let Some(caller) = self.frames.last_mut() {
+            caller.environment = Some(self.environment.clone());
+        }
+        self.frames.push(Frame {
+            function: None,
+            line: 1,
+            environment: None,
+            module: Some(module.file.clone()),
+        });
+        self.loading.push(file.clone());
+        let previous = std::mem::replace(&mut self.environment, module.environment.clone());
+        let mut result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement))
+            .map_err(Unwind::into_error);
+        self.environment = previous;
+        self.loading.pop();
+        if let Err(error) = &mut result {
+            self.attach_backtrace(error);
+        }
+        self.frames.pop();
+        if let Some(caller) = self.frames.last_mut() {
+            caller.environment = None;
+        }
+
+        result?;
+        self.modules.insert(file, module.clone());
+        Ok(module)
+    }
+
+    /// The file the innermost frame's code came from.
+    fn current_file(&self) -> Option<Rc<str>> {
+        match self.frames.last() {
+            Some(Frame {
+                function: Some(function),
+                ..
+            }) => function.file.clone(),
+            Some(Frame {
+                module: Some(module),
+                ..
+            }) => Some(module.clone()),
+            _ => self.file.clone(),
+        }
+    }
+
+    /// Fails with an error `catch` can't stop if the host has interrupted the script or its
+    /// time is up.
+    fn check_interrupt(&self) -> Result<(), RuntimeError> {
+        if self.interrupt.poll(self.deadline) {
+            let line = self.frames.last().map_or(0, |frame| frame.line);
+            return Err(RuntimeError::exceeded(Limit::Interrupt, line).fatal());
+        }
+        Ok(())
+    }
+
+    /// Fails if another call would go over the depth limit.
+    fn check_depth(&self, line: usize) -> Result<(), RuntimeError> {
+        if self.frames.len() > self.limits.max_depth {
+            return Err(RuntimeError::exceeded(Limit::Depth, line));
+        }
+        Ok(())
+    }
+
+    /// Fails like going over the depth limit if the native stack is used up, whether by
+    /// deep recursion, deeply nested code or both. The tree-walker recurses on the native
+    /// stack, and how much each call takes depends on the build, so the depth limit alone
+    /// can't keep it from overflowing.
+    fn check_stack(&self) -> Result<(), RuntimeError> {
+        if limits::stack_exhausted() {
+            let line = self.frames.last().map_or(0, |frame| frame.line);
+            return Err(RuntimeError::exceeded(Limit::Depth, line));
+        }
+        Ok(())
+    }
+
+    /// Applies a binary operator. Kept out of `visit_binary`, so that the frames a long chain
+    /// of operators recurses through stay small.
+    fn binary(
+        &mut self,
+        operator: &Token,
+        left_val: LiteralValue,
+        right_val: LiteralValue,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match operator.token_type {
+            TokenType::Minus => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => {
+                    Ok(LiteralValue::Number(l - r))
+                }
+
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::Slash => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => {
+                    Ok(LiteralValue::Number(l / r))
+                }
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::Star => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => {
+                    Ok(LiteralValue::Number(l * r))
+                }
+
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::Plus => {
+                let sum = match (left_val, right_val) {
                     (LiteralValue::Number(l), LiteralValue::Number(r)) => {
                         Ok(LiteralValue::Number(l + r))
                     }
                     (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => {
-                        Ok(LiteralValue::StringValue(format!("{}{}", l, r)))
+                        Ok(LiteralValue::StringValue(format!("{}{}", l, r).into()))
                     }
                     (LiteralValue::Number(l), LiteralValue::StringValue(r)) => {
-                        Ok(LiteralValue::StringValue(format!("{}{}", l, r)))
+                        Ok(LiteralValue::StringValue(format!("{}{}", l, r).into()))
                     }
                     (LiteralValue::StringValue(l), LiteralValue::Number(r)) => {
-                        Ok(LiteralValue::StringValue(format!("{}{}", l, r)))
+                        Ok(LiteralValue::StringValue(format!("{}{}", l, r).into()))
                     }
 
                     _ => Err(RuntimeError::new(
@@ -86,167 +960,1118 @@ fn evaluate(expr: &Expr) -> Result<LiteralValue, RuntimeError> {
                         operator.line_number,
                         operator.clone(),
                     )),
-                },
-                TokenType::Greater => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l > r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l > r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
+                }?;
+                // Only strings take up memory beyond the value itself.
+                if let LiteralValue::StringValue(string) = &sum {
+                    self.allocate(string.len(), operator.line_number)?;
+                }
+                Ok(sum)
+            }
+            TokenType::Greater => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l > r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l > r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers or strings.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::GreaterEqual => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l >= r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l >= r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers or strings.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::GreaterEqual => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l >= r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l >= r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers or strings.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::Less => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l < r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l < r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers or strings.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::Less => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l < r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l < r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers or strings.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::LessEqual => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l <= r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l <= r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers or strings.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::LessEqual => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l <= r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l <= r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers or strings.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::BangEqual => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l != r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l != r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::True, LiteralValue::False) => Ok(LiteralValue::True),
-                    (LiteralValue::False, LiteralValue::True) => Ok(LiteralValue::True),
-                    (LiteralValue::True, LiteralValue::True) => Ok(LiteralValue::False),
-                    (LiteralValue::False, LiteralValue::False) => Ok(LiteralValue::False),
-
-                    (LiteralValue::True, LiteralValue::Nil) => Ok(LiteralValue::True),
-                    (LiteralValue::False, LiteralValue::Nil) => Ok(LiteralValue::True),
-                    (LiteralValue::Nil, LiteralValue::True) => Ok(LiteralValue::True),
-                    (LiteralValue::Nil, LiteralValue::False) => Ok(LiteralValue::True),
-
-                    (LiteralValue::Nil, LiteralValue::Nil) => Ok(LiteralValue::False),
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers or strings.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::BangEqual => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l != r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l != r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::True, LiteralValue::False) => Ok(LiteralValue::True),
+                (LiteralValue::False, LiteralValue::True) => Ok(LiteralValue::True),
+                (LiteralValue::True, LiteralValue::True) => Ok(LiteralValue::False),
+                (LiteralValue::False, LiteralValue::False) => Ok(LiteralValue::False),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers, strings, or booleans.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
-                },
-                TokenType::EqualEqual => match (left_val, right_val) {
-                    (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l == r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-                    (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l == r {
-                        LiteralValue::True
-                    } else {
-                        LiteralValue::False
-                    }),
-
-                    (LiteralValue::True, LiteralValue::True) => Ok(LiteralValue::True),
-                    (LiteralValue::False, LiteralValue::False) => Ok(LiteralValue::True),
-                    (LiteralValue::True, LiteralValue::False) => Ok(LiteralValue::False),
-                    (LiteralValue::False, LiteralValue::True) => Ok(LiteralValue::False),
-
-                    (LiteralValue::True, LiteralValue::Nil) => Ok(LiteralValue::False),
-                    (LiteralValue::False, LiteralValue::Nil) => Ok(LiteralValue::False),
-                    (LiteralValue::Nil, LiteralValue::True) => Ok(LiteralValue::False),
-                    (LiteralValue::Nil, LiteralValue::False) => Ok(LiteralValue::False),
-
-                    (LiteralValue::Nil, LiteralValue::Nil) => Ok(LiteralValue::True),
+                (LiteralValue::True, LiteralValue::Nil) => Ok(LiteralValue::True),
+                (LiteralValue::False, LiteralValue::Nil) => Ok(LiteralValue::True),
+                (LiteralValue::Nil, LiteralValue::True) => Ok(LiteralValue::True),
+                (LiteralValue::Nil, LiteralValue::False) => Ok(LiteralValue::True),
 
-                    _ => Err(RuntimeError::new(
-                        "Operands must be numbers, strings, or booleans.",
-                        operator.line_number,
-                        operator.clone(),
-                    )),
+                (LiteralValue::Nil, LiteralValue::Nil) => Ok(LiteralValue::False),
+
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers, strings, or booleans.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            TokenType::EqualEqual => match (left_val, right_val) {
+                (LiteralValue::Number(l), LiteralValue::Number(r)) => Ok(if l == r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+                (LiteralValue::StringValue(l), LiteralValue::StringValue(r)) => Ok(if l == r {
+                    LiteralValue::True
+                } else {
+                    LiteralValue::False
+                }),
+
+                (LiteralValue::True, LiteralValue::True) => Ok(LiteralValue::True),
+                (LiteralValue::False, LiteralValue::False) => Ok(LiteralValue::True),
+                (LiteralValue::True, LiteralValue::False) => Ok(LiteralValue::False),
+                (LiteralValue::False, LiteralValue::True) => Ok(LiteralValue::False),
+
+                (LiteralValue::True, LiteralValue::Nil) => Ok(LiteralValue::False),
+                (LiteralValue::False, LiteralValue::Nil) => Ok(LiteralValue::False),
+                (LiteralValue::Nil, LiteralValue::True) => Ok(LiteralValue::False),
+                (LiteralValue::Nil, LiteralValue::False) => Ok(LiteralValue::False),
+
+                (LiteralValue::Nil, LiteralValue::Nil) => Ok(LiteralValue::True),
+
+                _ => Err(RuntimeError::new(
+                    "Operands must be numbers, strings, or booleans.",
+                    operator.line_number,
+                    operator.clone(),
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                "Invalid binary operator.",
+                operator.line_number,
+                operator.clone(),
+            )),
+        }
+    }
+
+    /// Counts `bytes` the script is about to keep. Once the count passes the heap limit,
+    /// it is reset to what the script can still reach, and only if that is over the limit
+    /// too does the allocation fail.
+    fn allocate(&mut self, bytes: usize, line: usize) -> Result<(), RuntimeError> {
+        let Some(max) = self.limits.max_heap_bytes else {
+            return Ok(());
+        };
+        self.allocated += bytes;
+        if self.allocated > max {
+            self.allocated = self.live_bytes() + bytes;
+            if self.allocated > max {
+                return Err(RuntimeError::exceeded(Limit::Heap, line));
+            }
+        }
+        Ok(())
+    }
+
+    /// Roughly how many bytes of scopes, strings and functions scripts can still reach.
+    fn live_bytes(&self) -> usize {
+        let mut scopes = vec![self.globals.clone(), self.environment.clone()];
+        scopes.extend(
+            self.frames
+                .iter()
+                .filter_map(|frame| frame.environment.clone()),
+        );
+        scopes.extend(
+            self.modules
+                .values()
+                .map(|module| module.environment.clone()),
+        );
+
+        // Scopes and the values in them are shared, so each is counted once.
+        let mut seen = HashSet::new();
+        let mut bytes = 0;
+        while let Some(scope) = scopes.pop() {
+            if !seen.insert(Rc::as_ptr(&scope) as *const ()) {
+                continue;
+            }
+            let scope = scope.borrow();
+            bytes += mem::size_of::<Environment>();
+            for (_, value) in scope.bindings() {
+                bytes += BINDING_SIZE;
+                match value {
+                    LiteralValue::StringValue(string)
+                        if seen.insert(string.as_ptr() as *const ()) =>
+                    {
+                        bytes += string.len();
+                    }
+                    LiteralValue::Function(function)
+                        if seen.insert(Rc::as_ptr(function) as *const ()) =>
+                    {
+                        bytes += mem::size_of::<LoxFunction>();
+                        scopes.push(function.closure.clone());
+                    }
+                    LiteralValue::Error(error) if seen.insert(Rc::as_ptr(error) as *const ()) => {
+                        bytes += mem::size_of::<RuntimeError>() + error.message.len();
+                    }
+                    LiteralValue::Module(module) => scopes.push(module.environment.clone()),
+                    _ => {}
+                }
+            }
+            scopes.extend(scope.enclosing());
+        }
+        bytes
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<LiteralValue, RuntimeError> {
+        self.visit_expr(expr)
+    }
+
+    /// Counts `stmt` against the step limit, records the line it starts on and lets an
+    /// attached debugger pause there.
+    fn enter_statement(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            let line = stmt.line().or_else(|| Some(self.frames.last()?.line));
+            return Err(RuntimeError::exceeded(Limit::Steps, line.unwrap_or(0)));
+        }
+        let Some(line) = stmt.line() else {
+            return Ok(());
+        };
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = line;
+        }
+
+        // The debugger is detached while it runs, so that evaluating expressions for it
+        // can't pause again.
+        let Some(mut debugger) = self.debugger.take() else {
+            return Ok(());
+        };
+        let result = debugger.on_statement(self, line);
+        self.debugger = Some(debugger);
+        result
+    }
+
+    /// Records the call stack on an error raised in the innermost frame, unless an inner
+    /// call already has.
+    fn attach_backtrace(&self, error: &mut RuntimeError) {
+        if error.backtrace.is_empty() {
+            error.backtrace = self.stack().into();
+            if let Some(innermost) = error.backtrace.first_mut() {
+                innermost.line = error.line;
+            }
+        }
+    }
+
+    pub(crate) fn stack_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The tree-walker's call stack, innermost frame first.
+    pub(crate) fn stack(&self) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| StackFrame {
+                name: frame
+                    .function
+                    .as_ref()
+                    .map_or("script".to_string(), |function| function.name().to_string()),
+                file: match &frame.function {
+                    Some(function) => function.file.as_deref().map(str::to_string),
+                    None => frame
+                        .module
+                        .as_ref()
+                        .or(self.file.as_ref())
+                        .map(|file| file.to_string()),
                 },
+                line: frame.line,
+            })
+            .collect()
+    }
+
+    /// The scope frame `index` (innermost first) is running in.
+    fn frame_environment(&self, index: usize) -> Option<Rc<RefCell<Environment>>> {
+        let frame = self.frames.iter().rev().nth(index)?;
+        Some(match &frame.environment {
+            Some(environment) => environment.clone(),
+            None => self.environment.clone(),
+        })
+    }
+
+    /// The variables visible in frame `index` other than globals, innermost scope first and
+    /// sorted by name within each scope, with their values as `print` shows them.
+    pub(crate) fn locals(&self, index: usize) -> Vec<(Symbol, String)> {
+        let mut locals = vec![];
+        let mut scope = self.frame_environment(index);
+        while let Some(environment) = scope {
+            let environment = environment.borrow();
+            // The outermost scope holds the globals of the script or module.
+            if environment.enclosing().is_none() {
+                break;
+            }
+            let mut bindings: Vec<(Symbol, String)> = environment
+                .bindings()
+                .map(|(name, value)| (name, value.to_string()))
+                .collect();
+            bindings.sort_by(|(l, _), (r, _)| l.cmp(r));
+            locals.extend(bindings);
+            scope = environment.enclosing();
+        }
+        locals
+    }
+
+    /// Runs `source` in the scope of frame `index`, returning the value of a trailing
+    /// expression like [`Interpreter::eval_str`].
+    pub(crate) fn eval_in_frame(
+        &mut self,
+        index: usize,
+        source: &str,
+    ) -> Result<LiteralValue, LoxError> {
+        let statements = self.parse_source(source)?;
+        let Some(environment) = self.frame_environment(index) else {
+            return Err(LoxError::Load(format!("There is no frame {}.", index)));
+        };
+        // The statements run as part of the innermost frame, which mustn't look like it moved.
+        let line = self.frames.last().map(|frame| frame.line);
+        let previous = std::mem::replace(&mut self.environment, environment);
+        // The statements get a budget of their own, and the paused run keeps what it had left.
+        let budget = (self.steps, self.deadline);
+        // What went wrong in the frame's code says nothing about how the program got there.
+        let result = self.interpret(&statements).map_err(|mut error| {
+            error.backtrace = Box::default();
+            LoxError::Runtime(error)
+        });
+        (self.steps, self.deadline) = budget;
+        self.environment = previous;
+        if let (Some(frame), Some(line)) = (self.frames.last_mut(), line) {
+            frame.line = line;
+        }
+        result
+    }
+
+    fn trace_line(&mut self, line: &str) {
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "{}{}", "  ".repeat(self.trace_depth), line);
+        }
+    }
+}
+
+impl Visitor for Interpreter {
+    type Output = Result<LiteralValue, RuntimeError>;
+
+    /// Evaluates `expr`, logging each node and its value post-order when tracing is on.
+    fn visit_expr(&mut self, expr: &Expr) -> Self::Output {
+        self.check_stack()?;
+        if self.trace.is_none() {
+            return walk_expr(self, expr);
+        }
+
+        self.trace_depth += 1;
+        let result = walk_expr(self, expr);
+        self.trace_depth -= 1;
+
+        if let Ok(value) = &result {
+            self.trace_line(&format!("{} => {}", expr, value));
+        }
+        result
+    }
+
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> Self::Output {
+        let value = self.evaluate(value)?;
+        self.environment.borrow_mut().assign(name, value.clone())?;
+        Ok(value)
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        let left_val = self.evaluate(left)?;
+        let right_val = self.evaluate(right)?;
+        self.binary(operator, left_val, right_val)
+    }
+
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Self::Output {
+        let callee = self.evaluate(callee)?;
+
+        let mut argument_values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            argument_values.push(self.evaluate(argument)?);
+        }
+
+        self.call(callee, argument_values, paren)
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Self::Output {
+        self.evaluate(expression)
+    }
+
+    fn visit_literal(&mut self, value: &LiteralValue) -> Self::Output {
+        Ok(value.clone())
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Self::Output {
+        let left_val = self.evaluate(left)?;
+
+        if operator.token_type == TokenType::Or {
+            if is_truthy(left_val.clone()) {
+                return Ok(left_val);
+            }
+        } else if !is_truthy(left_val.clone()) {
+            return Ok(left_val);
+        }
+
+        self.evaluate(right)
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> Self::Output {
+        let right_val = self.evaluate(right)?;
+        match operator.token_type {
+            TokenType::Bang => Ok(match !is_truthy(right_val) {
+                true => LiteralValue::True,
+                false => LiteralValue::False,
+            }),
+            TokenType::Minus => match right_val {
+                LiteralValue::Number(n) => Ok(LiteralValue::Number(-n)),
                 _ => Err(RuntimeError::new(
-                    "Invalid binary operator.",
+                    "Operand must be a number.",
                     operator.line_number,
                     operator.clone(),
                 )),
+            },
+            _ => Err(RuntimeError::new(
+                "Invalid unary operator.",
+                operator.line_number,
+                operator.clone(),
+            )),
+        }
+    }
+
+    fn visit_variable(&mut self, name: &Token) -> Self::Output {
+        self.environment.borrow().get(name)
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> Self::Output {
+        let error = |message: String| RuntimeError::new(&message, name.line_number, name.clone());
+        match self.evaluate(object)? {
+            LiteralValue::Module(module) => {
+                let value = module.environment.borrow().get_local(name.lexeme.symbol());
+                value.ok_or_else(|| error(format!("Undefined property '{}'.", name.lexeme)))
             }
+            _ => Err(error("Only modules have properties.".to_string())),
         }
     }
 }
 
-fn is_truthy(value: LiteralValue) -> bool {
-    match value {
-        LiteralValue::False => false,
-        LiteralValue::Nil => false,
-        _ => true,
+/// What one binding in a scope takes up, not counting what its value points to.
+const BINDING_SIZE: usize = mem::size_of::<(Symbol, LiteralValue)>();
+
+/// `dir/script.lox` is cached as `dir/.rlox-cache/script.loxc`.
+pub fn cache_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push("c");
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    dir.join(".rlox-cache").join(file_name)
+}
+
+fn stmt_label(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::Block { .. } => "block".to_string(),
+        Stmt::Expression { expression } => format!("expression {}", expression),
+        Stmt::For { .. } => "for".to_string(),
+        Stmt::Function { name, .. } => format!("fun {} (line {})", name.lexeme, name.line_number),
+        Stmt::If { condition, .. } => format!("if {}", condition),
+        Stmt::Print {
+            keyword,
+            expression,
+        } => format!("print {} (line {})", expression, keyword.line_number),
+        Stmt::Return { keyword, .. } => format!("return (line {})", keyword.line_number),
+        Stmt::Import { path, name, .. } => {
+            format!(
+                "import \"{}\" as {} (line {})",
+                path, name.lexeme, name.line_number
+            )
+        }
+        Stmt::Throw { keyword, value } => {
+            format!("throw {} (line {})", value, keyword.line_number)
+        }
+        Stmt::Try { keyword, .. } => format!("try (line {})", keyword.line_number),
+        Stmt::Var { name, .. } => format!("var {} (line {})", name.lexeme, name.line_number),
+        Stmt::While { condition, .. } => format!("while {}", condition),
     }
 }
 
-pub fn interpret(expr: &Expr) -> Result<String, RuntimeError> {
-    let result = evaluate(expr)?;
-    Ok(stringify(result))
+/// The error a `throw` statement raises. Rethrowing a caught error keeps its original
+/// message, line and backtrace.
+fn thrown_error(value: LiteralValue, keyword: &Token) -> RuntimeError {
+    let mut error = match &value {
+        LiteralValue::Error(error) => RuntimeError::clone(error),
+        _ => RuntimeError::new(&value.to_string(), keyword.line_number, keyword.clone()),
+    };
+    error.value = Some(Box::new(value));
+    error
+}
+
+fn is_truthy(value: LiteralValue) -> bool {
+    !matches!(value, LiteralValue::False | LiteralValue::Nil)
 }
 
-fn stringify(value: LiteralValue) -> String {
-    match value {
-        LiteralValue::Number(n) => n.to_string().replace(".0", ""),
-        LiteralValue::StringValue(s) => s,
-        LiteralValue::True => "true".to_string(),
-        LiteralValue::False => "false".to_string(),
-        LiteralValue::Nil => "nil".to_string(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::SharedBuffer;
+    use std::io::Cursor;
+
+    #[test]
+    fn evaluates_trailing_expression() {
+        let mut interpreter = Interpreter::new();
+        let value = interpreter.eval_str("var a = 2; (a + 1) * 3").unwrap();
+        assert_eq!(value, LiteralValue::Number(9.0));
+    }
+
+    #[test]
+    fn globals_persist_and_are_visible_to_the_host() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("greeting", "hello");
+        interpreter
+            .eval_str("var message = greeting + \" world\";")
+            .unwrap();
+
+        let message = String::try_from(interpreter.get_global("message").unwrap()).unwrap();
+        assert_eq!(message, "hello world");
+    }
+
+    #[test]
+    fn reset_keeps_host_globals_on_both_backends() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("greeting", "hello");
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            interpreter.set_backend(backend);
+            interpreter
+                .eval_str("var greeting = \"bye\"; fun f() {}")
+                .unwrap();
+            interpreter.reset();
+
+            let names: Vec<String> = interpreter
+                .globals()
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            assert_eq!(
+                names,
+                [
+                    "clock=<native fn>",
+                    "errorLine=<native fn>",
+                    "errorMessage=<native fn>",
+                    "greeting=hello",
+                    "readLine=<native fn>"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn closures_and_natives() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_native("double", 1, |args| match args[0] {
+            LiteralValue::Number(n) => Ok(LiteralValue::Number(n * 2.0)),
+            _ => Err("double expects a number.".to_string()),
+        });
+
+        let source = "fun counter() { var i = 0; fun next() { i = i + 1; return i; } return next; }
+                      var next = counter(); next(); double(next())";
+        assert_eq!(
+            interpreter.eval_str(source).unwrap(),
+            LiteralValue::Number(4.0)
+        );
+    }
+
+    #[test]
+    fn control_flow_and_block_scopes() {
+        let mut interpreter = Interpreter::new();
+        let source = "var total = 0;
+                      for (var i = 0; i < 5; i = i + 1) {
+                        if (i == 2) total = total + 10; else total = total + i;
+                      }
+                      var n = 0;
+                      while (n < 3 and total > 0) n = n + 1;
+                      var a = 1;
+                      { var a = 2; total = total + a; }
+                      total + n + a";
+        assert_eq!(
+            interpreter.eval_str(source).unwrap(),
+            LiteralValue::Number(24.0)
+        );
+    }
+
+    #[test]
+    fn scopes_kept_alive_by_cycles_are_collected() {
+        let mut interpreter = Interpreter::new();
+        // Each call leaves behind a scope holding a function that closes over it.
+        interpreter
+            .eval_str(
+                "fun outer() { fun inner() { return inner; } return 1; }
+                 fun counter() { var i = 0; fun next() { i = i + 1; return i; } return next; }
+                 var next = counter();
+                 for (var i = 0; i < 20000; i = i + 1) { outer(); next(); }",
+            )
+            .unwrap();
+        assert!(interpreter.scopes.live() < 4096);
+
+        // Closures still in use survive collection with their state.
+        interpreter.scopes.collect();
+        assert!(interpreter.scopes.live() < 4);
+        assert_eq!(
+            interpreter.eval_str("next()").unwrap(),
+            LiteralValue::Number(20001.0)
+        );
+    }
+
+    #[test]
+    fn runtime_errors_carry_the_offending_token() {
+        let mut interpreter = Interpreter::new();
+        match interpreter.eval_str("1 + nil") {
+            Err(LoxError::Runtime(error)) => {
+                assert_eq!(error.token.lexeme, "+");
+                assert_eq!(
+                    error.message,
+                    "Operands must be two numbers or two strings."
+                );
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn runtime_errors_report_a_backtrace_on_both_backends() {
+        let source = "fun inner(x) {\n  return x + nil;\n}\nfun outer() {\n  return inner(1);\n}\n\nouter();";
+        let mut interpreter = Interpreter::new();
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            interpreter.set_backend(backend);
+            let error = interpreter.eval_file("main.lox", source).unwrap_err();
+            assert_eq!(
+                error.report(),
+                "LINE 2: ('+') Operands must be two numbers or two strings.\n  at inner (main.lox:2)\n  at outer (main.lox:5)\n  at script (main.lox:8)",
+                "{:?}",
+                backend
+            );
+
+            // Recursive calls from the same line are collapsed into one.
+            let source =
+                "fun down(n) {\n  if (n == 0) return n + nil;\n  return down(n - 1);\n}\ndown(5);";
+            let error = interpreter.eval_file("main.lox", source).unwrap_err();
+            assert_eq!(
+                error.report(),
+                "LINE 2: ('+') Operands must be two numbers or two strings.\n  at down (main.lox:2)\n  at down (main.lox:3) [repeated 5 times]\n  at script (main.lox:5)",
+                "{:?}",
+                backend
+            );
+
+            // Errors outside any call, including in natives called from the top level,
+            // report just the one line.
+            let error = interpreter.eval_str("clock(1);").unwrap_err();
+            assert_eq!(
+                error.report(),
+                "LINE 1: (')') Expected 0 arguments but got 1."
+            );
+        }
+    }
+
+    #[test]
+    fn thrown_values_and_runtime_errors_are_caught_on_both_backends() {
+        let source = "var log = \"\";
+            try { throw 1; log = log + \"unreachable\"; } catch (e) { log = log + e; }
+            try { log = log + 2; } finally { log = log + 3; }
+            fun f() { try { return 4; } finally { log = log + \"!\"; } }
+            var r = f();
+            log = log + r;
+            var e = \"outer\";
+            try { nil(); } catch (e) { log = log + \" \" + errorMessage(e) + errorLine(e); }
+            log + \" \" + e";
+        let mut interpreter = Interpreter::new();
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            interpreter.set_backend(backend);
+            assert_eq!(
+                interpreter.eval_str(source).unwrap(),
+                LiteralValue::from("123!4 Can only call functions.8 outer"),
+                "{:?}",
+                backend
+            );
+
+            // Uncaught, a thrown value is reported like any other runtime error.
+            let error = interpreter
+                .eval_file("main.lox", "fun f() {\n  throw \"oops\";\n}\nf();")
+                .unwrap_err();
+            let LoxError::Runtime(runtime) = &error else {
+                panic!("expected a runtime error, got {:?}", error);
+            };
+            assert_eq!(runtime.value.as_deref(), Some(&LiteralValue::from("oops")));
+            assert_eq!(
+                error.report(),
+                "LINE 2: ('throw') oops\n  at f (main.lox:2)\n  at script (main.lox:4)"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_raise_distinct_catchable_errors_on_both_backends() {
+        let limit = |interpreter: &mut Interpreter, source: &str| match interpreter
+            .eval_str(source)
+            .unwrap_err()
+        {
+            LoxError::Runtime(error) => (error.limit, error.message),
+            error => panic!("expected a runtime error, got {:?}", error),
+        };
+
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+            interpreter.set_backend(backend);
+            interpreter.set_limits(Limits {
+                max_steps: Some(100_000),
+                max_depth: 16,
+                max_heap_bytes: Some(100_000),
+                ..Limits::default()
+            });
+
+            assert_eq!(
+                limit(&mut interpreter, "fun f(n) { return f(n + 1); } f(0);"),
+                (Some(Limit::Depth), "Stack overflow.".to_string()),
+                "{:?}",
+                backend
+            );
+            // Deep code inside each call can't get around the depth limit either.
+            let deep = format!(
+                "fun g() {{ return {}g(){}; }} g();",
+                "-(".repeat(5),
+                ")".repeat(5)
+            );
+            assert_eq!(limit(&mut interpreter, &deep).0, Some(Limit::Depth));
+            assert_eq!(
+                limit(&mut interpreter, "var s = \"ab\"; while (true) s = s + s;"),
+                (Some(Limit::Heap), "Out of memory.".to_string()),
+                "{:?}",
+                backend
+            );
+            assert_eq!(
+                limit(&mut interpreter, "s = nil; while (true) {}"),
+                (Some(Limit::Steps), "Step limit exceeded.".to_string()),
+                "{:?}",
+                backend
+            );
+
+            // Each run gets the full step budget, and scripts can catch what they exceed.
+            interpreter
+                .eval_str(
+                    "fun f() { f(); }
+                     try { f(); } catch (e) { print errorMessage(e); }
+                     var s = \"ab\";
+                     try { while (true) s = s + s; } catch (e) { print errorMessage(e); }
+                     s = nil;
+                     print \"done\";",
+                )
+                .unwrap();
+            assert_eq!(
+                output.contents(),
+                "Stack overflow.\nOut of memory.\ndone\n",
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn deep_code_and_recursion_stop_before_the_native_stack_runs_out() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::with_io(Cursor::new(""), SharedBuffer::new());
+            interpreter.set_backend(backend);
+            interpreter.set_limits(Limits {
+                max_depth: 1_000_000,
+                max_nesting: 1_000_000,
+                ..Limits::default()
+            });
+
+            // Long chains of operators nest one level per operator and must still run.
+            let chain = format!("{}1", "1 + ".repeat(299));
+            assert_eq!(
+                interpreter.eval_str(&chain).unwrap(),
+                LiteralValue::Number(300.0),
+                "{:?}",
+                backend
+            );
+
+            let parens = format!("{}1{};", "(".repeat(100_000), ")".repeat(100_000));
+            match interpreter.eval_str(&parens).unwrap_err() {
+                LoxError::Parse(_) | LoxError::Runtime(_) => {}
+                error => panic!("expected a nesting error, got {:?}", error),
+            }
+
+            let recurse = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } f(100000);";
+            match backend {
+                // The tree-walker recurses on the native stack, so it runs out of depth.
+                Backend::TreeWalker => match interpreter.eval_str(recurse).unwrap_err() {
+                    LoxError::Runtime(error) => assert_eq!(error.limit, Some(Limit::Depth)),
+                    error => panic!("expected a runtime error, got {:?}", error),
+                },
+                // The VM keeps its frames on the heap, so only max_depth bounds it.
+                Backend::Vm => assert_eq!(
+                    interpreter.eval_str(recurse).unwrap(),
+                    LiteralValue::Number(100000.0)
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn default_limits_are_reached_before_the_stack_on_a_thread_made_for_them() {
+        let limits = Limits::default();
+        let reached = limits.with_stack(move || {
+            for optimize in [true, false] {
+                for backend in [Backend::TreeWalker, Backend::Vm] {
+                    let mut interpreter =
+                        Interpreter::with_io(Cursor::new(""), SharedBuffer::new());
+                    interpreter.set_backend(backend);
+                    interpreter.set_optimize(optimize);
+                    let context = format!("{:?}, optimize: {}", backend, optimize);
+
+                    // The statement and its expression take the first two levels.
+                    let parens = |depth| format!("{}1{};", "(".repeat(depth), ")".repeat(depth));
+                    assert_eq!(
+                        interpreter
+                            .eval_str(&parens(limits.max_nesting - 2))
+                            .unwrap(),
+                        LiteralValue::Number(1.0),
+                        "{}",
+                        context
+                    );
+                    match interpreter
+                        .eval_str(&parens(limits.max_nesting - 1))
+                        .unwrap_err()
+                    {
+                        LoxError::Parse(error) => {
+                            assert_eq!(error.message, "Code is nested too deeply.", "{}", context)
+                        }
+                        error => panic!("expected a parse error, got {:?}", error),
+                    }
+
+                    let recurse = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); }";
+                    interpreter.eval_str(recurse).unwrap();
+                    assert_eq!(
+                        interpreter.eval_str("f(200);").unwrap(),
+                        LiteralValue::Number(200.0),
+                        "{}",
+                        context
+                    );
+                    match interpreter.eval_str("f(300);").unwrap_err() {
+                        LoxError::Runtime(error) => {
+                            assert_eq!(error.limit, Some(Limit::Depth), "{}", context)
+                        }
+                        error => panic!("expected a runtime error, got {:?}", error),
+                    }
+                }
+            }
+        });
+        reached.unwrap();
+    }
+
+    #[test]
+    fn interrupts_and_timeouts_stop_scripts_past_catch_blocks() {
+        let interrupted = |result: Result<LiteralValue, LoxError>| match result.unwrap_err() {
+            LoxError::Runtime(error) => error.limit == Some(Limit::Interrupt) && error.fatal,
+            error => panic!("expected a runtime error, got {:?}", error),
+        };
+
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+            interpreter.set_backend(backend);
+
+            let handle = interpreter.interrupt_handle();
+            let host = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                handle.interrupt();
+            });
+            let result = interpreter.eval_str(
+                "fun spin() { while (true) {} }
+                 try { spin(); } catch (e) { print \"caught\"; } finally { print \"finally\"; }",
+            );
+            host.join().unwrap();
+            assert!(interrupted(result), "{:?}", backend);
+
+            // The interrupt is used up, so the next script runs normally.
+            interpreter
+                .eval_str("for (var i = 0; i < 3; i = i + 1) print i;")
+                .unwrap();
+
+            interpreter.set_limits(Limits {
+                timeout: Some(std::time::Duration::from_millis(20)),
+                ..Limits::default()
+            });
+            let recurse = "fun f(n) { if (n > 0) f(n - 1); } while (true) f(10);";
+            assert!(interrupted(interpreter.eval_str(recurse)), "{:?}", backend);
+            assert_eq!(output.contents(), "0\n1\n2\n", "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn backends_produce_identical_output() {
+        let scripts = [
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+             for (var i = 0; i < 10; i = i + 1) print fib(i);",
+            "fun makeCounter() {
+               var count = 0;
+               fun increment() { count = count + 1; return count; }
+               return increment;
+             }
+             var a = makeCounter(); var b = makeCounter();
+             print a(); print a(); print b();",
+            "var x = \"outer\"; { var x = x + \" inner\"; print x; } print x;
+             print nil or \"default\"; print 1 and 2; print !nil;
+             print \"ab\" < \"b\"; print 3 + \"px\"; print 10 / 4;",
+            "var i = 0; while (i < 3) { var j = i; fun show() { print j; } show(); i = i + 1; }
+             print clock() > 0;",
+            "print 1; print 1 + nil;",
+            "print 1 == \"1\";",
+            "fun f(a, b) {} f(1);",
+            "var s = \"str\"; s();",
+            "print undefined;",
+            "-\"a\";",
+            "fun f() { return f; } print f() == f;",
+            "fun check(n) { if (n > 2) throw \"big \" + n; return n; }
+             for (var i = 1; i < 5; i = i + 1) {
+               try { print check(i); } catch (e) { print \"caught \" + e; } finally { print i; }
+             }",
+            "fun f() { try { return \"body\"; } finally { print \"cleanup\"; } } print f();
+             try { try { 1 + nil; } finally { print \"inner\"; } } catch (e) { print e; }
+             fun g() { var c = 1; try { throw g; } catch (e) { fun h() { return c; } return h; } }
+             print g()();",
+            "fun f() { throw \"deep\"; } fun g() { f(); } try { g(); } finally { print 1; }",
+        ];
+
+        for script in scripts {
+            let mut results = vec![];
+            // The VM runs twice: once normally and once collecting before every allocation.
+            for (backend, stress) in [
+                (Backend::TreeWalker, false),
+                (Backend::Vm, false),
+                (Backend::Vm, true),
+            ] {
+                let output = SharedBuffer::new();
+                let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+                interpreter.set_backend(backend);
+                interpreter.set_gc_config(GcConfig {
+                    stress,
+                    ..GcConfig::default()
+                });
+                let error = interpreter.eval_str(script).err().map(|e| e.report());
+                results.push((output.contents(), error));
+            }
+            assert_eq!(results[0], results[1], "backends disagree on {}", script);
+            assert_eq!(results[1], results[2], "GC stress changes {}", script);
+        }
+    }
+
+    #[test]
+    fn optimization_does_not_change_behavior() {
+        let scripts = [
+            "print (1 + 2) * 3; print \"a\" + (1 < 2 == true); print nil or -(-4);",
+            "var x = 2; print x * 1 - 0; print !!(x > 1);",
+            "print 1 + 2; print -\"a\";",
+            "print 2 * 3; print 1 == \"1\";",
+        ];
+
+        for script in scripts {
+            let mut results = vec![];
+            for optimize in [false, true] {
+                let output = SharedBuffer::new();
+                let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+                interpreter.set_optimize(optimize);
+                let error = interpreter.eval_str(script).err().map(|e| e.report());
+                results.push((output.contents(), error));
+            }
+            assert_eq!(results[0], results[1], "optimizer changes {}", script);
+        }
+    }
+
+    #[test]
+    fn trace_logs_execution_on_both_backends() {
+        let trace = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_io(Cursor::new(""), io::sink());
+        interpreter.set_trace(trace.clone());
+        // Keep `1 + 2` from being folded so its evaluation shows up in the trace.
+        interpreter.set_optimize(false);
+
+        interpreter.eval_str("print 1 + 2;").unwrap();
+        assert_eq!(
+            trace.contents(),
+            "exec print (+ 1 2) (line 1)\n  1 => 1\n  2 => 2\n(+ 1 2) => 3\n"
+        );
+
+        trace.clear();
+        interpreter.set_backend(Backend::Vm);
+        interpreter.eval_str("print 1 + 2;").unwrap();
+        assert!(trace
+            .contents()
+            .contains("          [ <script> ][ 1 ][ 2 ]\n0006    | OP_ADD\n"));
+    }
+
+    #[test]
+    fn compiled_scripts_run_and_are_cached() {
+        let dir = std::env::temp_dir().join(format!("rlox-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("hello.lox");
+        fs::write(&script, "print \"hello\";").unwrap();
+
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+        let bytes = interpreter.compile_to_bytes("print \"compiled\";").unwrap();
+        interpreter.run_compiled(&bytes).unwrap();
+
+        interpreter.set_backend(Backend::Vm);
+        interpreter.set_bytecode_cache(true);
+        interpreter.run_file(&script).unwrap();
+        let cached = fs::read(cache_path(&script)).unwrap();
+        assert_eq!(
+            bytecode::read_source_hash(&cached),
+            Some(bytecode::hash_source("print \"hello\";"))
+        );
+
+        // A stale cache entry is replaced rather than run.
+        fs::write(&script, "print \"changed\";").unwrap();
+        interpreter.run_file(&script).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(output.contents(), "compiled\nhello\nchanged\n");
+    }
+
+    #[test]
+    fn modules_run_once_and_are_shared_on_both_backends() {
+        let dir = std::env::temp_dir().join(format!("rlox-module-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let files = [
+            (
+                "lib/counter.lox",
+                "print \"loading\"; var count = 0; fun bump() { count = count + 1; return count; }",
+            ),
+            (
+                "lib/util.lox",
+                "import \"counter.lox\" as counter; fun twice() { counter.bump(); return counter.bump(); }",
+            ),
+            (
+                "main.lox",
+                "import \"lib/util.lox\" as util; import \"lib/counter.lox\" as counter;
+                 print util.twice(); print counter.count; print counter;
+                 try { counter.missing; } catch (e) { print errorMessage(e); }",
+            ),
+            ("a.lox", "import \"b.lox\" as b;"),
+            ("b.lox", "import \"a.lox\" as a;"),
+        ];
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let root = dir.canonicalize().unwrap();
+
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_io(Cursor::new(""), output.clone());
+            interpreter.set_backend(backend);
+
+            interpreter.run_file(dir.join("main.lox")).unwrap();
+            assert_eq!(
+                output.contents(),
+                "loading\n2\n2\n<module counter>\nUndefined property 'missing'.\n",
+                "{:?}",
+                backend
+            );
+
+            let error = interpreter.run_file(dir.join("a.lox")).unwrap_err();
+            let (a, b) = (root.join("a.lox"), root.join("b.lox"));
+            assert!(
+                error.to_string().contains(&format!(
+                    "Import cycle: {} -> {} -> {}.",
+                    a.display(),
+                    b.display(),
+                    a.display()
+                )),
+                "{:?}: {}",
+                backend,
+                error
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn script_io_uses_the_supplied_streams() {
+        let output = SharedBuffer::new();
+        let input = Cursor::new("Ada\n");
+        let mut interpreter = Interpreter::with_io(input, output.clone());
+
+        interpreter
+            .eval_str("print \"Hello, \" + readLine() + \"!\"; print readLine();")
+            .unwrap();
+        assert_eq!(output.contents(), "Hello, Ada!\nnil\n");
+    }
+
+    #[test]
+    fn prompt_keeps_state_between_lines() {
+        let output = SharedBuffer::new();
+        let input = Cursor::new("var a = 2;\na * 21\nb\n\n");
+        let mut interpreter = Interpreter::with_io(input, output.clone());
+
+        interpreter.run_prompt().unwrap();
+        assert_eq!(
+            output.contents(),
+            ">>> >>> 42\n>>> LINE 1: ('b') Undefined variable 'b'.\n>>> >>> "
+        );
     }
 }
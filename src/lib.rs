@@ -0,0 +1,35 @@
+pub mod bytecode;
+pub mod callable;
+pub mod chunk;
+pub mod compiler;
+pub mod cst;
+pub mod dap;
+pub mod debug;
+pub mod debugger;
+pub mod dump;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub mod formatter;
+pub mod gc;
+pub mod interpreter;
+pub mod json;
+pub mod limits;
+pub mod lsp;
+pub mod module;
+pub mod optimizer;
+pub mod parser;
+mod protocol;
+pub mod repl;
+pub mod resolver;
+pub mod scanner;
+pub mod stmt;
+pub mod stream;
+pub mod symbol;
+pub mod typecheck;
+pub mod value;
+pub mod vm;
+
+pub use error::LoxError;
+pub use expr::LiteralValue;
+pub use interpreter::{Backend, Interpreter};
@@ -0,0 +1,171 @@
+//! Caps on the work a script may do, for hosts that run code they don't trust. Exceeding
+//! one raises a runtime error that scripts can catch, though a script out of steps fails
+//! again at its next step. Running out of time, or being stopped through an `Interrupt`,
+//! raises one they can't.
+
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The limits a script runs under. The depth, nesting and stack limits are always on,
+/// because deep enough recursion would overflow the native stack; the others are off
+/// unless set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Statements the tree-walker executes, or instructions the VM executes, in one run.
+    pub max_steps: Option<u64>,
+    /// Calls in progress at once.
+    pub max_depth: usize,
+    /// How deeply statements and expressions may nest in the source. Every pass over the
+    /// syntax tree recurses through it, so the parser rejects code nested deeper.
+    pub max_nesting: usize,
+    /// Bytes of native stack that parsing, compiling or running one script may use. What a
+    /// call or a level of nesting takes depends on the build and the platform, so this is
+    /// checked on top of the depth and nesting limits, and going over it fails the same way.
+    /// The default leaves room for the default depth and nesting even in debug builds.
+    ///
+    /// Only threads started by [`Limits::with_stack`] are known to have that much stack. On
+    /// any other thread no more than 1 MiB is used, which suits the 2 MiB stacks Rust gives
+    /// new threads, so deep code may fail well before the depth and nesting limits there.
+    pub max_stack_bytes: usize,
+    /// Bytes of strings, functions and scopes a script may keep alive. The tree-walker's
+    /// count is an estimate.
+    pub max_heap_bytes: Option<usize>,
+    /// Wall-clock time one run may take before it is interrupted.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_depth: 256,
+            max_nesting: 512,
+            max_stack_bytes: 16 << 20,
+            max_heap_bytes: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Runs `f` on a new thread with twice `max_stack_bytes` of native stack, so that scripts
+    /// it runs under these limits can use all of `max_stack_bytes`, and returns its result.
+    /// A panic in `f` is passed on to the caller.
+    pub fn with_stack<T: Send>(&self, f: impl FnOnce() -> T + Send) -> io::Result<T> {
+        let max_stack_bytes = self.max_stack_bytes;
+        thread::scope(|scope| {
+            let thread = thread::Builder::new()
+                .stack_size(max_stack_bytes.saturating_mul(2))
+                .spawn_scoped(scope, move || {
+                    STACK_BUDGET.with(|budget| budget.set(max_stack_bytes));
+                    f()
+                })?;
+            Ok(thread
+                .join()
+                .unwrap_or_else(|panic| panic::resume_unwind(panic)))
+        })
+    }
+}
+
+/// Which limit a runtime error reports exceeding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Depth,
+    Heap,
+    Interrupt,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Steps => "Step limit exceeded.",
+            Limit::Depth => "Stack overflow.",
+            Limit::Heap => "Out of memory.",
+            Limit::Interrupt => "Interrupted.",
+        })
+    }
+}
+
+/// A handle for stopping a running script from another thread. Clones share one flag, which
+/// the script checks at every loop iteration and call.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the running script to stop. If no script is running, the next one stops as soon
+    /// as it checks.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the script should stop, because it was interrupted or has run past
+    /// `deadline`. A pending interrupt is cleared once it has been seen.
+    pub(crate) fn poll(&self, deadline: Option<Instant>) -> bool {
+        (self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed))
+            || deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+thread_local! {
+    /// Where the native stack was when the outermost guarded pass on this thread began, and
+    /// how many bytes it may grow past that.
+    static STACK: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    /// The most native stack a guarded pass on this thread may use, whatever its limits ask
+    /// for. Threads that didn't come from `Limits::with_stack` may only have 2 MiB.
+    static STACK_BUDGET: Cell<usize> = const { Cell::new(1 << 20) };
+}
+
+/// Puts the work done while it lives under `max_stack_bytes`, or under what the thread can
+/// spare if that is less. A guard made while another
+/// is alive, such as the one for parsing an imported module, leaves the outer one in
+/// charge, so the whole stack a script takes up counts against one limit.
+pub(crate) struct StackGuard {
+    outermost: bool,
+}
+
+impl StackGuard {
+    pub(crate) fn new(max_stack_bytes: usize) -> Self {
+        let outermost = STACK.with(|stack| {
+            let outermost = stack.get().is_none();
+            if outermost {
+                let budget = STACK_BUDGET.with(Cell::get);
+                stack.set(Some((stack_address(), max_stack_bytes.min(budget))));
+            }
+            outermost
+        });
+        Self { outermost }
+    }
+}
+
+impl Drop for StackGuard {
+    fn drop(&mut self) {
+        if self.outermost {
+            STACK.with(|stack| stack.set(None));
+        }
+    }
+}
+
+/// Whether the stack has grown past what the current guard allows.
+pub(crate) fn stack_exhausted() -> bool {
+    STACK.with(|stack| {
+        stack
+            .get()
+            .is_some_and(|(base, max)| base.abs_diff(stack_address()) > max)
+    })
+}
+
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
@@ -0,0 +1,893 @@
+//! A Language Server Protocol server behind `rlox lsp`.
+//!
+//! Documents are synced in full on every change. Each version is checked like `rlox check`
+//! for diagnostics, and its names are bound by the [resolver](crate::resolver) for hover,
+//! go-to-definition, references and document symbols. Semantic tokens come from the scanner,
+//! with identifiers classified by what they resolve to. Positions are exchanged in UTF-16 code
+//! units, as the protocol requires. A message that can't be parsed is answered with a parse
+//! error and skipped.
+
+use crate::error::{LoxError, RuntimeError};
+use crate::interpreter::Interpreter;
+use crate::json::Json;
+use crate::protocol;
+use crate::resolver::{self, DeclarationKind, Resolution};
+use crate::scanner::{Scanner, Token, TokenType};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+const PARSE_ERROR: f64 = -32700.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+/// The semantic token types this server reports, in the order of their indexes.
+const TOKEN_TYPES: [&str; 8] = [
+    "keyword",
+    "function",
+    "parameter",
+    "variable",
+    "string",
+    "number",
+    "operator",
+    "comment",
+];
+const DECLARATION_MODIFIER: u32 = 1;
+
+/// Serves one session on `input` and `output`, returning after the client's `exit`
+/// notification or at the end of the input.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> Result<(), LoxError> {
+    let mut server = Server {
+        output: &mut output,
+        documents: HashMap::new(),
+        checker: Interpreter::with_io(io::empty(), io::sink()),
+    };
+    loop {
+        let message = match protocol::read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            // The message was skipped, and without it there is no id to answer.
+            Err(LoxError::Load(message)) => {
+                let reply = error_reply(Json::Null, PARSE_ERROR, message);
+                protocol::write_message(server.output, &reply)?;
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        let method = message
+            .get("method")
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let result = server.handle(method, params)?;
+        // Notifications have no id and get no reply.
+        let Some(id) = message.get("id") else {
+            continue;
+        };
+        let reply = match result {
+            Some(result) => Json::object([
+                ("jsonrpc", Json::String("2.0".to_string())),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            None => error_reply(
+                id.clone(),
+                METHOD_NOT_FOUND,
+                format!("Unhandled method {}.", method),
+            ),
+        };
+        protocol::write_message(server.output, &reply)?;
+    }
+    Ok(())
+}
+
+fn error_reply(id: Json, code: f64, message: String) -> Json {
+    let error = Json::object([
+        ("code", Json::Number(code)),
+        ("message", Json::String(message)),
+    ]);
+    Json::object([
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("id", id),
+        ("error", error),
+    ])
+}
+
+struct Server<'a> {
+    output: &'a mut dyn Write,
+    documents: HashMap<String, Document>,
+    /// Checks documents for diagnostics; it never runs them.
+    checker: Interpreter,
+}
+
+impl Server<'_> {
+    /// Handles a message, returning its result, or `None` for methods the server doesn't know.
+    fn handle(&mut self, method: &str, params: &Json) -> Result<Option<Json>, LoxError> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let document = self.documents.get(&uri);
+        let offset = document.and_then(|document| {
+            let position = params.get("position")?;
+            let line = position.get("line")?.as_f64()? as usize;
+            let character = position.get("character")?.as_f64()? as usize;
+            Some(document.lines.offset(line, character))
+        });
+
+        let result = match method {
+            "initialize" => capabilities(),
+            "initialized" | "shutdown" => Json::Null,
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => {
+                        params.get("textDocument").and_then(|d| d.get("text"))
+                    }
+                    _ => params
+                        .get("contentChanges")
+                        .and_then(Json::as_array)
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change.get("text")),
+                };
+                let text = text.and_then(Json::as_str).unwrap_or_default();
+                let document = Document::new(text, &self.checker);
+                let diagnostics = document.diagnostics.clone();
+                self.documents.insert(uri.clone(), document);
+                self.publish(&uri, diagnostics)?;
+                Json::Null
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish(&uri, vec![])?;
+                Json::Null
+            }
+            "textDocument/hover" => match (document, offset) {
+                (Some(document), Some(offset)) => document.hover(offset),
+                _ => Json::Null,
+            },
+            "textDocument/definition" => match (document, offset) {
+                (Some(document), Some(offset)) => document.definition(&uri, offset),
+                _ => Json::Null,
+            },
+            "textDocument/references" => match (document, offset) {
+                (Some(document), Some(offset)) => {
+                    let include_declaration = params
+                        .get("context")
+                        .and_then(|context| context.get("includeDeclaration"))
+                        .and_then(Json::as_bool)
+                        .unwrap_or(false);
+                    document.references(&uri, offset, include_declaration)
+                }
+                _ => Json::Null,
+            },
+            "textDocument/documentSymbol" => match document {
+                Some(document) => document.symbols(),
+                None => Json::Null,
+            },
+            "textDocument/semanticTokens/full" => match document {
+                Some(document) => document.semantic_tokens(),
+                None => Json::Null,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> Result<(), LoxError> {
+        let params = Json::object([
+            ("uri", Json::String(uri.to_string())),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]);
+        let notification = Json::object([
+            ("jsonrpc", Json::String("2.0".to_string())),
+            (
+                "method",
+                Json::String("textDocument/publishDiagnostics".to_string()),
+            ),
+            ("params", params),
+        ]);
+        protocol::write_message(self.output, &notification)
+    }
+}
+
+fn capabilities() -> Json {
+    let legend = Json::object([
+        (
+            "tokenTypes",
+            Json::Array(
+                TOKEN_TYPES
+                    .iter()
+                    .map(|name| Json::String(name.to_string()))
+                    .collect(),
+            ),
+        ),
+        (
+            "tokenModifiers",
+            Json::Array(vec![Json::String("declaration".to_string())]),
+        ),
+    ]);
+    let capabilities = Json::object([
+        // Full sync: every change sends the whole document.
+        ("textDocumentSync", Json::Number(1.0)),
+        ("hoverProvider", Json::Bool(true)),
+        ("definitionProvider", Json::Bool(true)),
+        ("referencesProvider", Json::Bool(true)),
+        ("documentSymbolProvider", Json::Bool(true)),
+        (
+            "semanticTokensProvider",
+            Json::object([("legend", legend), ("full", Json::Bool(true))]),
+        ),
+    ]);
+    Json::object([
+        ("capabilities", capabilities),
+        (
+            "serverInfo",
+            Json::object([("name", Json::String("rlox".to_string()))]),
+        ),
+    ])
+}
+
+/// An open document and everything worked out about it.
+struct Document {
+    lines: LineIndex,
+    /// Every token other than whitespace, with its character offsets. Empty if the document
+    /// doesn't scan.
+    tokens: Vec<(Token, Range<usize>)>,
+    /// Name bindings, if the document parses.
+    resolution: Option<Resolution>,
+    diagnostics: Vec<Json>,
+}
+
+impl Document {
+    fn new(text: &str, checker: &Interpreter) -> Self {
+        let mut document = Document {
+            lines: LineIndex::new(text),
+            tokens: scan(text),
+            resolution: checker
+                .parse_tree(text)
+                .ok()
+                .map(|tree| resolver::resolve(&tree)),
+            diagnostics: vec![],
+        };
+        if let Err(error) = checker.check(text) {
+            document.diagnostics = document.diagnose(&error);
+        }
+        document
+    }
+
+    fn diagnose(&self, error: &LoxError) -> Vec<Json> {
+        let diagnostic = |range: Json, message: &str| {
+            Json::object([
+                ("range", range),
+                ("severity", Json::Number(1.0)),
+                ("source", Json::String("rlox".to_string())),
+                ("message", Json::String(message.to_string())),
+            ])
+        };
+        match error {
+            // Scan errors only know their line: "LINE 3: Unexpected character."
+            LoxError::Scan(errors) => errors
+                .lines()
+                .map(|error| {
+                    let (line, message) = error
+                        .strip_prefix("LINE ")
+                        .and_then(|error| error.split_once(": "))
+                        .and_then(|(line, message)| Some((line.parse().ok()?, message)))
+                        .unwrap_or((1, error));
+                    diagnostic(self.lines.line_range(line), message)
+                })
+                .collect(),
+            LoxError::Parse(error) | LoxError::Compile(error) | LoxError::Runtime(error) => {
+                vec![diagnostic(self.error_range(error), &error.message)]
+            }
+            LoxError::Type(errors) => errors
+                .iter()
+                .map(|error| diagnostic(self.lines.range(error.range.clone()), &error.message))
+                .collect(),
+            LoxError::Io(message) | LoxError::Load(message) => {
+                vec![diagnostic(self.lines.line_range(1), message)]
+            }
+        }
+    }
+
+    /// The range of the token an error was reported at, or its whole line.
+    fn error_range(&self, error: &RuntimeError) -> Json {
+        if error.token.token_type == TokenType::EOF {
+            let end = self.lines.len();
+            return self.lines.range(end..end);
+        }
+        self.tokens
+            .iter()
+            .find(|(token, _)| {
+                token.line_number == error.token.line_number
+                    && token.token_type == error.token.token_type
+                    && token.lexeme == error.token.lexeme
+            })
+            .map_or_else(
+                || self.lines.line_range(error.line),
+                |(_, range)| self.lines.range(range.clone()),
+            )
+    }
+
+    fn token_at(&self, offset: usize) -> Option<&(Token, Range<usize>)> {
+        self.tokens
+            .iter()
+            .find(|(_, range)| range.start <= offset && offset < range.end)
+            .or_else(|| {
+                // The cursor may sit just after an identifier.
+                self.tokens.iter().find(|(token, range)| {
+                    range.end == offset && token.token_type == TokenType::Identifier
+                })
+            })
+    }
+
+    fn hover(&self, offset: usize) -> Json {
+        let Some((token, range)) = self.token_at(offset) else {
+            return Json::Null;
+        };
+        let text = match token.token_type {
+            TokenType::Identifier => {
+                let resolution = self.resolution.as_ref();
+                match resolution.and_then(|resolution| resolution.name_at(offset)) {
+                    Some((_, Some(index))) => {
+                        let resolution = resolution.expect("resolved names have a resolution");
+                        let declaration = &resolution.declarations[index];
+                        let scope = if declaration.global {
+                            "global"
+                        } else {
+                            "local"
+                        };
+                        match declaration.kind {
+                            DeclarationKind::Function => {
+                                let params: Vec<&str> =
+                                    declaration.params.iter().map(|p| p.as_str()).collect();
+                                format!(
+                                    "```lox\nfun {}({})\n```\n{} function",
+                                    declaration.name,
+                                    params.join(", "),
+                                    scope
+                                )
+                            }
+                            DeclarationKind::Variable => {
+                                format!("```lox\nvar {}\n```\n{} variable", declaration.name, scope)
+                            }
+                            DeclarationKind::Parameter => {
+                                let function = declaration
+                                    .parent
+                                    .map(|parent| &resolution.declarations[parent].name);
+                                format!(
+                                    "```lox\n{}\n```\nparameter of `{}`",
+                                    declaration.name,
+                                    function.map_or("", |name| name.as_str())
+                                )
+                            }
+                        }
+                    }
+                    _ => format!(
+                        "```lox\n{}\n```\nglobal defined outside this file",
+                        token.lexeme
+                    ),
+                }
+            }
+            TokenType::String => "string literal".to_string(),
+            TokenType::Number => "number literal".to_string(),
+            TokenType::Comment => return Json::Null,
+            token_type if semantic_type(token_type) == Some("keyword") => {
+                format!("keyword `{}`", token.lexeme)
+            }
+            _ => return Json::Null,
+        };
+        Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::String("markdown".to_string())),
+                    ("value", Json::String(text)),
+                ]),
+            ),
+            ("range", self.lines.range(range.clone())),
+        ])
+    }
+
+    fn location(&self, uri: &str, range: Range<usize>) -> Json {
+        Json::object([
+            ("uri", Json::String(uri.to_string())),
+            ("range", self.lines.range(range)),
+        ])
+    }
+
+    fn definition(&self, uri: &str, offset: usize) -> Json {
+        let Some(resolution) = &self.resolution else {
+            return Json::Null;
+        };
+        match resolution.name_at(offset) {
+            Some((_, Some(index))) => {
+                self.location(uri, resolution.declarations[index].name_range.clone())
+            }
+            _ => Json::Null,
+        }
+    }
+
+    fn references(&self, uri: &str, offset: usize, include_declaration: bool) -> Json {
+        let Some(resolution) = &self.resolution else {
+            return Json::Null;
+        };
+        let Some((_, Some(index))) = resolution.name_at(offset) else {
+            return Json::Array(vec![]);
+        };
+        let mut ranges = vec![];
+        if include_declaration {
+            ranges.push(resolution.declarations[index].name_range.clone());
+        }
+        ranges.extend(
+            resolution
+                .references_to(index)
+                .map(|reference| reference.range.clone()),
+        );
+        Json::Array(
+            ranges
+                .into_iter()
+                .map(|range| self.location(uri, range))
+                .collect(),
+        )
+    }
+
+    /// Functions and variables, with each function's declarations nested inside it.
+    fn symbols(&self) -> Json {
+        let Some(resolution) = &self.resolution else {
+            return Json::Array(vec![]);
+        };
+        fn children(document: &Document, resolution: &Resolution, parent: Option<usize>) -> Json {
+            let symbols = resolution
+                .declarations
+                .iter()
+                .enumerate()
+                .filter(|(_, declaration)| {
+                    declaration.parent == parent && declaration.kind != DeclarationKind::Parameter
+                })
+                .map(|(index, declaration)| {
+                    let (kind, detail) = match declaration.kind {
+                        DeclarationKind::Function => {
+                            let params: Vec<&str> =
+                                declaration.params.iter().map(|p| p.as_str()).collect();
+                            (12.0, format!("({})", params.join(", ")))
+                        }
+                        _ => (13.0, String::new()),
+                    };
+                    Json::object([
+                        ("name", Json::String(declaration.name.to_string())),
+                        ("detail", Json::String(detail)),
+                        ("kind", Json::Number(kind)),
+                        ("range", document.lines.range(declaration.range.clone())),
+                        (
+                            "selectionRange",
+                            document.lines.range(declaration.name_range.clone()),
+                        ),
+                        ("children", children(document, resolution, Some(index))),
+                    ])
+                })
+                .collect();
+            Json::Array(symbols)
+        }
+        children(self, resolution, None)
+    }
+
+    /// Tokens in the relative encoding LSP uses: for each, the line delta, the start (relative
+    /// to the previous token on the same line), the length, the type and the modifiers.
+    fn semantic_tokens(&self) -> Json {
+        let mut data = vec![];
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for (token, range) in &self.tokens {
+            let Some((token_type, modifiers)) = self.classify(token, range) else {
+                continue;
+            };
+            // Tokens can't span lines, so strings and comments are split at line breaks.
+            for segment in self.lines.split_lines(range.clone()) {
+                let (line, start) = self.lines.position(segment.start);
+                let (_, end) = self.lines.position(segment.end);
+                if end == start {
+                    continue;
+                }
+                let delta_start = if line == previous_line {
+                    start - previous_start
+                } else {
+                    start
+                };
+                data.extend([
+                    line - previous_line,
+                    delta_start,
+                    end - start,
+                    token_type,
+                    modifiers,
+                ]);
+                (previous_line, previous_start) = (line, start);
+            }
+        }
+        let data = data
+            .into_iter()
+            .map(|value| Json::Number(value as f64))
+            .collect();
+        Json::object([("data", Json::Array(data))])
+    }
+
+    /// The semantic token type index and modifiers for a token, if it gets one.
+    fn classify(&self, token: &Token, range: &Range<usize>) -> Option<(usize, usize)> {
+        let name = if token.token_type == TokenType::Identifier {
+            let resolution = self.resolution.as_ref();
+            let declarations = resolution.map_or(&[][..], |r| &r.declarations[..]);
+            let declared = declarations
+                .iter()
+                .find(|declaration| declaration.name_range == *range);
+            let declaration = declared.or_else(|| {
+                let resolution = resolution?;
+                let reference = resolution
+                    .references
+                    .iter()
+                    .find(|reference| reference.range == *range)?;
+                Some(&declarations[reference.declaration?])
+            });
+            let modifiers = if declared.is_some() {
+                DECLARATION_MODIFIER
+            } else {
+                0
+            };
+            let name = match declaration.map(|declaration| declaration.kind) {
+                Some(DeclarationKind::Function) => "function",
+                Some(DeclarationKind::Parameter) => "parameter",
+                _ => "variable",
+            };
+            return Some((type_index(name), modifiers as usize));
+        } else {
+            semantic_type(token.token_type)?
+        };
+        Some((type_index(name), 0))
+    }
+}
+
+fn type_index(name: &str) -> usize {
+    TOKEN_TYPES
+        .iter()
+        .position(|&token_type| token_type == name)
+        .expect("semantic token types are in the legend")
+}
+
+/// The semantic token type of everything but identifiers, whose type depends on what they name.
+fn semantic_type(token_type: TokenType) -> Option<&'static str> {
+    use TokenType::*;
+    Some(match token_type {
+        And | Class | Else | False | Fun | For | If | Nil | Or | Print | Return | Super | This
+        | True | Var | While | Catch | Finally | Throw | Try | As | Import => "keyword",
+        String => "string",
+        Number => "number",
+        Comment => "comment",
+        Minus | Plus | Slash | Star | Bang | BangEqual | Equal | EqualEqual | Greater
+        | GreaterEqual | Less | LessEqual => "operator",
+        _ => return None,
+    })
+}
+
+/// Every token but whitespace and the end of file, with its character offsets.
+fn scan(text: &str) -> Vec<(Token, Range<usize>)> {
+    let Ok(tokens) = Scanner::new(text).with_trivia().scan_tokens() else {
+        return vec![];
+    };
+    let mut offset = 0;
+    let mut scanned = vec![];
+    for token in tokens {
+        let length = token.lexeme.as_str().chars().count();
+        if !matches!(token.token_type, TokenType::Whitespace | TokenType::EOF) {
+            scanned.push((token, offset..offset + length));
+        }
+        offset += length;
+    }
+    scanned
+}
+
+/// Converts between character offsets and LSP positions: zero-based lines and UTF-16 columns.
+struct LineIndex {
+    chars: Vec<char>,
+    /// The offset each line starts at.
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut starts = vec![0];
+        starts.extend(
+            chars
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| c == '\n')
+                .map(|(i, _)| i + 1),
+        );
+        Self { chars, starts }
+    }
+
+    fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.chars.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.chars[self.starts[line]..offset]
+            .iter()
+            .map(|c| c.len_utf16())
+            .sum();
+        (line, column)
+    }
+
+    fn offset(&self, line: usize, character: usize) -> usize {
+        let Some(&start) = self.starts.get(line) else {
+            return self.chars.len();
+        };
+        let mut offset = start;
+        let mut column = 0;
+        while offset < self.chars.len() && self.chars[offset] != '\n' && column < character {
+            column += self.chars[offset].len_utf16();
+            offset += 1;
+        }
+        offset
+    }
+
+    fn range(&self, range: Range<usize>) -> Json {
+        let position = |offset| {
+            let (line, character) = self.position(offset);
+            Json::object([
+                ("line", Json::Number(line as f64)),
+                ("character", Json::Number(character as f64)),
+            ])
+        };
+        Json::object([
+            ("start", position(range.start)),
+            ("end", position(range.end)),
+        ])
+    }
+
+    /// The whole of one-based `line`, as the scanner and parser count lines.
+    fn line_range(&self, line: usize) -> Json {
+        let index = line.saturating_sub(1).min(self.starts.len() - 1);
+        let start = self.starts[index];
+        let end = self
+            .starts
+            .get(index + 1)
+            .map_or(self.chars.len(), |next| next - 1);
+        self.range(start..end)
+    }
+
+    /// `range` cut at line breaks, which are left out.
+    fn split_lines(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut segments = vec![];
+        let mut start = range.start;
+        for offset in range.clone() {
+            if self.chars[offset] == '\n' {
+                segments.push(start..offset);
+                start = offset + 1;
+            }
+        }
+        segments.push(start..range.end);
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use crate::stream::SharedBuffer;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.lox";
+
+    fn session(messages: &[(Option<u32>, &str, String)]) -> Vec<Json> {
+        let mut input = String::new();
+        for (id, method, params) in messages {
+            let id = id.map_or(String::new(), |id| format!(r#""id":{},"#, id));
+            let message = format!(
+                r#"{{"jsonrpc":"2.0",{}"method":"{}","params":{}}}"#,
+                id, method, params
+            );
+            input += &format!("Content-Length: {}\r\n\r\n{}", message.len(), message);
+        }
+        replies(input)
+    }
+
+    /// Serves `input` as it is and parses what the server sends back.
+    fn replies(input: String) -> Vec<Json> {
+        let output = SharedBuffer::new();
+        serve(Cursor::new(input), output.clone()).unwrap();
+        output
+            .contents()
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|message| json::parse(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    fn at(line: u32, character: u32) -> String {
+        format!(
+            r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}},"context":{{"includeDeclaration":true}}}}"#,
+            URI, line, character
+        )
+    }
+
+    fn document() -> String {
+        format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI)
+    }
+
+    fn result(message: &Json) -> String {
+        message.get("result").unwrap().to_compact()
+    }
+
+    fn diagnostics(message: &Json) -> String {
+        let params = message.get("params").unwrap();
+        params.get("diagnostics").unwrap().to_compact()
+    }
+
+    #[test]
+    fn publishes_diagnostics_as_documents_change() {
+        let open = format!(
+            r#"{{"textDocument":{{"uri":"{}","languageId":"lox","version":1,"text":"var a = 1;\nprint a +;"}}}}"#,
+            URI
+        );
+        let change = |text: &str| {
+            format!(
+                r#"{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":"{}"}}]}}"#,
+                URI, text
+            )
+        };
+        let messages = session(&[
+            (None, "textDocument/didOpen", open),
+            (
+                None,
+                "textDocument/didChange",
+                change("{ var b = b; }\\n\\\"é"),
+            ),
+            (
+                None,
+                "textDocument/didChange",
+                change("var n: Number = \\\"one\\\";"),
+            ),
+            (None, "textDocument/didChange", change("print 1;")),
+            (Some(1), "textDocument/rename", at(0, 0)),
+        ]);
+
+        assert_eq!(
+            diagnostics(&messages[0]),
+            r#"[{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"severity":1,"source":"rlox","message":"Expected expression, but found Semicolon."}]"#
+        );
+        assert_eq!(
+            diagnostics(&messages[1]),
+            r#"[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":2}},"severity":1,"source":"rlox","message":"Unterminated string."}]"#
+        );
+        assert_eq!(
+            diagnostics(&messages[2]),
+            r#"[{"range":{"start":{"line":0,"character":16},"end":{"line":0,"character":21}},"severity":1,"source":"rlox","message":"Expected Number, but found String."}]"#
+        );
+        assert_eq!(diagnostics(&messages[3]), "[]");
+        assert_eq!(
+            messages[4].get("error").unwrap().to_compact(),
+            r#"{"code":-32601,"message":"Unhandled method textDocument/rename."}"#
+        );
+    }
+
+    #[test]
+    fn answers_questions_about_names() {
+        let text =
+            r#"// 😀\nfun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\nprint add(1, 2);"#;
+        let open = format!(
+            r#"{{"textDocument":{{"uri":"{}","languageId":"lox","version":1,"text":"{}"}}}}"#,
+            URI, text
+        );
+        let messages = session(&[
+            (Some(1), "initialize", "{}".to_string()),
+            (None, "initialized", "{}".to_string()),
+            (None, "textDocument/didOpen", open),
+            (Some(2), "textDocument/hover", at(5, 7)),
+            (Some(3), "textDocument/hover", at(2, 13)),
+            (Some(4), "textDocument/definition", at(3, 10)),
+            (Some(5), "textDocument/references", at(1, 8)),
+            (Some(6), "textDocument/documentSymbol", document()),
+            (Some(7), "textDocument/semanticTokens/full", document()),
+            (Some(8), "shutdown", "null".to_string()),
+            (None, "exit", "null".to_string()),
+        ]);
+
+        let capabilities = messages[0]
+            .get("result")
+            .unwrap()
+            .get("capabilities")
+            .unwrap();
+        assert_eq!(capabilities.get("hoverProvider"), Some(&Json::Bool(true)));
+        assert_eq!(diagnostics(&messages[1]), "[]");
+        assert_eq!(
+            result(&messages[2]),
+            r#"{"contents":{"kind":"markdown","value":"```lox\nfun add(a, b)\n```\nglobal function"},"range":{"start":{"line":5,"character":6},"end":{"line":5,"character":9}}}"#
+        );
+        assert_eq!(
+            result(&messages[3]),
+            r#"{"contents":{"kind":"markdown","value":"```lox\na\n```\nparameter of `add`"},"range":{"start":{"line":2,"character":12},"end":{"line":2,"character":13}}}"#
+        );
+        assert_eq!(
+            result(&messages[4]),
+            format!(
+                r#"{{"uri":"{}","range":{{"start":{{"line":2,"character":6}},"end":{{"line":2,"character":9}}}}}}"#,
+                URI
+            )
+        );
+        let references = messages[5].get("result").unwrap().as_array().unwrap();
+        let lines: Vec<String> = references
+            .iter()
+            .map(|location| {
+                location
+                    .get("range")
+                    .unwrap()
+                    .get("start")
+                    .unwrap()
+                    .to_compact()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"line":1,"character":8}"#,
+                r#"{"line":2,"character":12}"#
+            ]
+        );
+        assert_eq!(
+            result(&messages[6]),
+            r#"[{"name":"add","detail":"(a, b)","kind":12,"range":{"start":{"line":1,"character":0},"end":{"line":4,"character":1}},"selectionRange":{"start":{"line":1,"character":4},"end":{"line":1,"character":7}},"children":[{"name":"sum","detail":"","kind":13,"range":{"start":{"line":2,"character":2},"end":{"line":2,"character":18}},"selectionRange":{"start":{"line":2,"character":6},"end":{"line":2,"character":9}},"children":[]}]}]"#
+        );
+        // The comment's emoji is two UTF-16 units long.
+        let data = result(&messages[7]);
+        assert!(
+            data.starts_with(r#"{"data":[0,0,5,7,0,1,0,3,0,0,0,4,3,1,1,0,4,1,2,1"#),
+            "{}",
+            data
+        );
+        assert_eq!(result(&messages[8]), "null");
+        assert_eq!(messages.len(), 9);
+    }
+
+    #[test]
+    fn reports_malformed_messages_and_keeps_serving() {
+        let shutdown = r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#;
+        let input = [
+            "Content-Length: 5\r\n\r\n{oops".to_string(),
+            format!("Content-Type: json\r\n\r\n{}", shutdown),
+            format!("Content-Length: {}\r\n\r\n{}", shutdown.len(), shutdown),
+            "Content-Length: 99999999999\r\n\r\n{}".to_string(),
+        ];
+        let messages = replies(input.concat());
+
+        let errors: Vec<String> = messages
+            .iter()
+            .filter(|message| message.get("error").is_some())
+            .map(|message| {
+                let error = message.get("error").unwrap();
+                assert_eq!(message.get("id"), Some(&Json::Null));
+                assert_eq!(error.get("code").and_then(Json::as_f64), Some(PARSE_ERROR));
+                error
+                    .get("message")
+                    .and_then(Json::as_str)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                r#"Invalid message: Expected '"' at offset 1."#,
+                "A message had no Content-Length.",
+                "A message of 99999999999 bytes is too long.",
+            ]
+        );
+        assert_eq!(result(&messages[2]), "null");
+        assert_eq!(messages.len(), 4);
+    }
+}
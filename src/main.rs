@@ -1,87 +1,666 @@
-pub mod error;
-pub mod expr;
-pub mod interpreter;
-pub mod parser;
-pub mod scanner;
+use rlox::dap;
+use rlox::debugger::{Console, Debugger};
+use rlox::dump::{dump_ast, dump_tokens, DumpFormat};
+use rlox::formatter::format_source;
+use rlox::gc::GcConfig;
+use rlox::limits::Limits;
+use rlox::lsp;
+use rlox::repl::Repl;
+#[cfg(feature = "line-editor")]
+use rlox::repl::TerminalEditor;
+use rlox::{Backend, Interpreter, LiteralValue, LoxError};
 
-use parser::Parser;
+use std::fs::{self, read_to_string};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, io, process};
 
-use crate::scanner::*;
+const HELP: &str = "\
+rlox, an interpreter for the Lox language
 
-use std::fs::read_to_string;
-use std::io::{self, BufRead, Write};
-use std::{env, process};
+Usage:
+  rlox [options] [<script> | -e <code> | -] [args...]
+  rlox <command> [options] ...
 
-fn run_file(path: &str) -> Result<(), String> {
-    match read_to_string(path) {
-        Ok(file_content) => return run(&file_content),
-        Err(msg) => return Err(msg.to_string()),
-    };
+Commands:
+  run <script> [args...]          Run a script (the default when a script is given)
+  repl                            Start an interactive session (the default otherwise)
+  debug <script> [args...]        Run a script under the debugger; `help` lists its commands
+  dap                             Serve the Debug Adapter Protocol on stdin and stdout
+  lsp                             Serve the Language Server Protocol on stdin and stdout
+  check <script>...               Report errors, including type errors, without running
+  fmt [--check] <script>...       Format scripts in place, or list unformatted ones
+  tokens [--json] <script>        Print the token stream
+  ast [--json] <script>           Print the syntax tree as S-expressions or JSON
+  compile <script> [-o <output>]  Compile a script to bytecode
+  help                            Show this help
+
+  Wherever a script is expected, `-e <code>` runs code from the command line and `-`
+  reads the script from stdin. Arguments after the script are passed to it and can be
+  read with argc() and arg(i).
+
+Options:
+  --vm             Run on the bytecode VM instead of the tree-walker
+  --trace          Log every executed instruction or statement to stderr
+  --disassemble    Print the compiled bytecode instead of running the script
+  --gc-stress      Collect garbage before every allocation (VM only)
+  --no-opt         Don't constant-fold the program before running it
+  --no-cache       Don't cache compiled scripts in .rlox-cache (VM only)
+  --max-steps <n>  Stop scripts after n statements, or n instructions on the VM
+  --max-depth <n>  Allow n calls in progress at once (default 256)
+  --max-nesting <n>
+                   Allow code nested n deep, such as n parentheses (default 512)
+  --max-heap <n>   Stop scripts that keep more than n bytes of memory alive
+  --timeout <ms>   Stop scripts that run for longer than ms milliseconds
+  -q, --quiet      Don't print the REPL banner
+  -h, --help       Show this help
+
+  In a terminal, the REPL keeps its history in ~/.rlox_history, or in the file named by
+  the RLOX_HISTORY environment variable.
+
+Exit status:
+  0 on success, 1 when `fmt --check` finds unformatted files, 64 for usage errors,
+  65 for scan, parse and compile errors, 70 for runtime errors and 74 for I/O errors.
+";
+
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Input {
+    File(String),
+    Stdin,
+    Inline(String),
 }
 
-fn run_prompt() -> Result<(), String> {
-    loop {
-        print!(">>> ");
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run { input: Input, args: Vec<String> },
+    Repl,
+    Debug { input: Input, args: Vec<String> },
+    Dap,
+    Lsp,
+    Check { files: Vec<String> },
+    Fmt { files: Vec<String>, check: bool },
+    Tokens { input: Input, format: DumpFormat },
+    Ast { input: Input, format: DumpFormat },
+    Compile { script: String, out: Option<String> },
+    Help,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    vm: bool,
+    trace: bool,
+    disassemble: bool,
+    gc_stress: bool,
+    no_opt: bool,
+    no_cache: bool,
+    quiet: bool,
+    limits: Limits,
+}
 
-        io::stdout().flush().expect("Could not flush stdout");
+#[derive(Debug, PartialEq)]
+struct Cli {
+    options: Options,
+    command: Command,
+}
+
+const COMMANDS: [&str; 11] = [
+    "run", "repl", "debug", "dap", "lsp", "check", "fmt", "tokens", "ast", "compile", "help",
+];
+
+fn usage(command: &str) -> &'static str {
+    match command {
+        "repl" => "rlox repl [options]",
+        "debug" => "rlox debug [options] <script | -e <code>> [args...]",
+        "dap" => "rlox dap",
+        "lsp" => "rlox lsp",
+        "check" => "rlox check <script>...",
+        "fmt" => "rlox fmt [--check] <script>...",
+        "tokens" => "rlox tokens [--json] <script | -e <code> | ->",
+        "ast" => "rlox ast [--json] <script | -e <code> | ->",
+        "compile" => "rlox compile <script> [-o <output>]",
+        _ => "rlox [run] [options] <script | -e <code> | -> [args...]",
+    }
+}
 
-        let mut buffer = String::new();
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
+/// Parses the command line (without the program name). On failure, returns the message to
+/// show along with the usage of the command.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+    let mut args = args.into_iter();
+    let mut options = Options::default();
+    let mut command: Option<String> = None;
+    let mut positional: Vec<String> = vec![];
+    let mut inline = None;
+    let mut check = false;
+    let mut format = None;
+    let mut out = None;
 
-        match handle.read_line(&mut buffer) {
-            Ok(n) => {
-                if n <= 1 {
-                    return Ok(());
+    while let Some(arg) = args.next() {
+        // Once a script is known, everything after it belongs to the script.
+        let runs_script = matches!(command.as_deref(), None | Some("run") | Some("debug"));
+        match arg.as_str() {
+            "--" => positional.extend(args.by_ref()),
+            "--vm" => options.vm = true,
+            "--trace" => options.trace = true,
+            "--disassemble" => options.disassemble = true,
+            "--gc-stress" => options.gc_stress = true,
+            "--no-opt" => options.no_opt = true,
+            "--no-cache" => options.no_cache = true,
+            "-q" | "--quiet" => options.quiet = true,
+            "-h" | "--help" => command = Some("help".to_string()),
+            "--check" => check = true,
+            "--json" => format = Some(DumpFormat::Json),
+            "-o" => out = Some(args.next().ok_or("-o expects an output path")?),
+            "--max-steps" => options.limits.max_steps = Some(limit(&arg, args.next())?),
+            "--max-depth" => options.limits.max_depth = limit(&arg, args.next())?,
+            "--max-nesting" => options.limits.max_nesting = limit(&arg, args.next())?,
+            "--max-heap" => options.limits.max_heap_bytes = Some(limit(&arg, args.next())?),
+            "--timeout" => {
+                let millis = limit(&arg, args.next())?;
+                options.limits.timeout = Some(Duration::from_millis(millis));
+            }
+            "-e" => {
+                inline = Some(args.next().ok_or("-e expects code to run")?);
+                if runs_script {
+                    positional.extend(args.by_ref());
+                }
+            }
+            _ if arg.starts_with("--dump-tokens") || arg.starts_with("--dump-ast") => {
+                let (flag, value) = arg.split_once('=').unwrap_or((&arg, "text"));
+                format = Some(dump_format(value)?);
+                let name = if flag == "--dump-ast" {
+                    "ast"
+                } else {
+                    "tokens"
+                };
+                command = Some(name.to_string());
+            }
+            _ if arg.starts_with("--format=") => format = Some(dump_format(&arg[9..])?),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option '{}'.", arg))
+            }
+            _ if command.is_none() && positional.is_empty() && COMMANDS.contains(&&*arg) => {
+                command = Some(arg)
+            }
+            _ => {
+                positional.push(arg);
+                if runs_script {
+                    positional.extend(args.by_ref());
                 }
             }
-            Err(err) => return Err(err.to_string()),
         }
+    }
+
+    let name = command.as_deref().unwrap_or("run");
+    let fail = |message: &str| Err(format!("{}\nUsage: {}", message, usage(name)));
+    if check && name != "fmt" {
+        return fail("--check only applies to fmt.");
+    }
+    if out.is_some() && name != "compile" {
+        return fail("-o only applies to compile.");
+    }
+    if format.is_some() && !matches!(name, "tokens" | "ast") {
+        return fail("--json and --format only apply to tokens and ast.");
+    }
+
+    // `-e` takes the place of the script, so every positional argument is then extra.
+    let script = match (&inline, positional.first()) {
+        (Some(code), _) => Some(Input::Inline(code.clone())),
+        (None, Some(first)) if first == "-" => Some(Input::Stdin),
+        (None, Some(first)) => Some(Input::File(first.clone())),
+        (None, None) => None,
+    };
+    let extra = &positional[positional.len().min(usize::from(inline.is_none()))..];
+
+    let command = match name {
+        "help" => Command::Help,
+        "run" => match script {
+            Some(input) => Command::Run {
+                input,
+                args: extra.to_vec(),
+            },
+            None if command.is_none() => Command::Repl,
+            None => return fail("Expected a script to run."),
+        },
+        // The debugger reads its commands from stdin, so the script can't come from there.
+        "debug" if options.vm => return fail("The debugger only runs on the tree-walker."),
+        "debug" => match script {
+            Some(Input::Stdin) | None => return fail("Expected a script to debug."),
+            Some(input) => Command::Debug {
+                input,
+                args: extra.to_vec(),
+            },
+        },
+        "dap" if script.is_none() => Command::Dap,
+        "dap" => return fail("The debug adapter is told what to run by its client."),
+        "lsp" if script.is_none() => Command::Lsp,
+        "lsp" => return fail("The language server is sent its documents by its client."),
+        "repl" if script.is_none() => Command::Repl,
+        "repl" => return fail("The REPL does not take a script."),
+        "check" | "fmt" if positional.is_empty() => return fail("Expected at least one script."),
+        "check" => Command::Check { files: positional },
+        "fmt" => Command::Fmt {
+            files: positional,
+            check,
+        },
+        "tokens" | "ast" => {
+            let (Some(input), []) = (script, extra) else {
+                return fail("Expected exactly one script.");
+            };
+            let format = format.unwrap_or(DumpFormat::Text);
+            if name == "tokens" {
+                Command::Tokens { input, format }
+            } else {
+                Command::Ast { input, format }
+            }
+        }
+        "compile" => match &positional[..] {
+            [script] if inline.is_none() => Command::Compile {
+                script: script.clone(),
+                out,
+            },
+            _ => return fail("Expected exactly one script."),
+        },
+        _ => unreachable!("unknown command {}", name),
+    };
+
+    Ok(Cli { options, command })
+}
+
+fn limit<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} expects a whole number.", flag))
+}
+
+fn dump_format(value: &str) -> Result<DumpFormat, String> {
+    DumpFormat::from_flag(value)
+        .ok_or_else(|| format!("Unknown format '{}', expected text, sexpr or json.", value))
+}
+
+fn exit_code(error: &LoxError) -> i32 {
+    match error {
+        LoxError::Io(_) => EX_IOERR,
+        LoxError::Runtime(_) => EX_SOFTWARE,
+        LoxError::Load(_)
+        | LoxError::Scan(_)
+        | LoxError::Parse(_)
+        | LoxError::Compile(_)
+        | LoxError::Type(_) => EX_DATAERR,
+    }
+}
+
+fn read_input(input: &Input) -> Result<String, LoxError> {
+    match input {
+        Input::File(path) => {
+            read_to_string(path).map_err(|e| LoxError::Io(format!("{}: {}", path, e)))
+        }
+        Input::Stdin => io::read_to_string(io::stdin()).map_err(|e| LoxError::Io(e.to_string())),
+        Input::Inline(code) => Ok(code.clone()),
+    }
+}
+
+fn interpreter(options: &Options) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_bytecode_cache(!options.no_cache);
+    interpreter.set_optimize(!options.no_opt);
+    if options.vm {
+        interpreter.set_backend(Backend::Vm);
+    }
+    if options.trace {
+        interpreter.set_trace(io::stderr());
+    }
+    if options.gc_stress {
+        interpreter.set_gc_config(GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        });
+    }
+    interpreter.set_limits(options.limits);
+    interpreter
+}
+
+/// Exposes the script's command-line arguments as `argc()` and `arg(i)`.
+fn define_args(interpreter: &mut Interpreter, args: Vec<String>) {
+    let count = args.len();
+    interpreter.define_native("argc", 0, move |_| Ok(LiteralValue::Number(count as f64)));
+    interpreter.define_native("arg", 1, move |values| match values[0] {
+        LiteralValue::Number(n) if n >= 0.0 && n.fract() == 0.0 => {
+            Ok(args.get(n as usize).map_or(LiteralValue::Nil, |arg| {
+                LiteralValue::StringValue(arg.as_str().into())
+            }))
+        }
+        _ => Err("Argument index must be a non-negative integer.".to_string()),
+    });
+}
+
+fn run(options: &Options, input: Input, args: Vec<String>) -> Result<(), LoxError> {
+    let mut interpreter = interpreter(options);
+    define_args(&mut interpreter, args);
+
+    if options.disassemble {
+        print!("{}", interpreter.disassemble(&read_input(&input)?)?);
+        return Ok(());
+    }
+
+    match input {
+        // Files go through `run_file` so compiled scripts and the bytecode cache work.
+        Input::File(path) => interpreter.run_file(path),
+        input => interpreter.eval_str(&read_input(&input)?).map(|_| ()),
+    }
+}
+
+/// Runs a script under a console debugger that reads commands from stdin, pausing before the
+/// first statement.
+fn debug(options: &Options, input: Input, args: Vec<String>) -> Result<(), LoxError> {
+    let source = read_input(&input)?;
+    let mut interpreter = interpreter(options);
+    define_args(&mut interpreter, args);
+
+    let console = Console::new(&source, io::stdin().lock(), io::stdout());
+    interpreter.set_debugger(Debugger::new(console).stop_on_entry());
+    match &input {
+        Input::File(path) => interpreter.eval_file(path, &source),
+        _ => interpreter.eval_str(&source),
+    }
+    .map(|_| ())
+}
+
+fn compile(options: &Options, script: &str, out: Option<String>) -> Result<(), LoxError> {
+    let source = read_input(&Input::File(script.to_string()))?;
+    let bytes = interpreter(options).compile_to_bytes(&source)?;
+
+    let out = match out {
+        Some(out) => Path::new(&out).to_path_buf(),
+        None => Path::new(script).with_extension("loxc"),
+    };
+    fs::write(&out, bytes).map_err(|e| LoxError::Io(e.to_string()))
+}
+
+/// Checks every file, reporting each error, and returns the exit code.
+fn check_files(options: &Options, files: &[String]) -> i32 {
+    let interpreter = interpreter(options);
+    let mut code = 0;
+    for file in files {
+        let checked = read_input(&Input::File(file.clone())).and_then(|s| interpreter.check(&s));
+        if let Err(error) = checked {
+            eprintln!("Error: {}: {}", file, error.report());
+            code = code.max(exit_code(&error));
+        }
+    }
+    code
+}
 
-        if let Err(msg) = run(&buffer) {
-            println!("{}", msg);
+/// Formats each file in place, or with `check` only lists the files that are not formatted.
+/// Returns the exit code.
+fn format_files(files: &[String], check: bool) -> i32 {
+    let mut code = 0;
+    for file in files {
+        let formatted = read_input(&Input::File(file.clone())).and_then(|source| {
+            let formatted = format_source(&source)?;
+            Ok((source, formatted))
+        });
+        match formatted {
+            Ok((source, formatted)) if source != formatted => {
+                if check {
+                    println!("{}", file);
+                    code = code.max(1);
+                } else if let Err(e) = fs::write(file, formatted) {
+                    eprintln!("Error: {}: {}", file, e);
+                    code = EX_IOERR;
+                }
+            }
+            Ok(_) => {}
+            Err(error) => {
+                eprintln!("Error: {}: {}", file, error.report());
+                code = code.max(exit_code(&error));
+            }
         }
     }
+    code
+}
+
+#[cfg(feature = "line-editor")]
+fn history_path() -> Option<std::path::PathBuf> {
+    env::var_os("RLOX_HISTORY")
+        .map(Into::into)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".rlox_history")))
 }
 
-fn run(source: &str) -> Result<(), String> {
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens()?;
-    let mut parser = Parser::new(tokens);
-    let expr = parser.expression().map_err(|e| e.message)?;
+/// Runs the REPL, with line editing and a history file when stdin is a terminal.
+fn repl(interpreter: &mut Interpreter) -> Result<(), LoxError> {
+    let repl = Repl::new(interpreter);
 
-    match interpreter::interpret(&expr) {
-        Ok(result) => {
-            println!("{}", result);
-            Ok(())
+    #[cfg(feature = "line-editor")]
+    if io::IsTerminal::is_terminal(&io::stdin()) {
+        let mut repl = repl.with_editor(TerminalEditor::new()?);
+        if let Some(path) = history_path() {
+            repl = repl.with_history_file(path);
+        }
+        return repl.run();
+    }
+
+    let mut repl = repl;
+    repl.run()
+}
+
+fn report(result: Result<(), LoxError>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("Error: {}", error.report());
+            exit_code(&error)
         }
-        Err(error) => Err(error.report()),
     }
 }
 
 fn main() {
-    println!("Welcome to the rlox interpreter!");
+    let cli = match parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("Run `rlox help` for more information.");
+            process::exit(EX_USAGE);
+        }
+    };
 
-    let args: Vec<String> = env::args().collect();
+    // The main thread's stack size isn't known, so scripts run on one made to fit their limits.
+    let limits = cli.options.limits;
+    let code = limits
+        .with_stack(move || execute(cli))
+        .unwrap_or_else(|error| {
+            eprintln!("Error: {}", error);
+            EX_SOFTWARE
+        });
+    process::exit(code);
+}
 
-    if args.len() > 2 {
-        println!("Usage: rlox [script]");
-        process::exit(64);
-    } else if args.len() == 2 {
-        match run_file(&args[1]) {
-            Ok(_) => process::exit(0),
-            Err(msg) => {
-                println!("Error: {}", msg);
-                process::exit(65);
+/// Runs a command, returning the exit code.
+fn execute(cli: Cli) -> i32 {
+    let options = &cli.options;
+    match cli.command {
+        Command::Help => {
+            print!("{}", HELP);
+            0
+        }
+        Command::Run { input, args } => report(run(options, input, args)),
+        Command::Repl => {
+            if !options.quiet {
+                println!("Welcome to the rlox interpreter!");
             }
+            report(repl(&mut interpreter(options)))
         }
-    } else {
-        match run_prompt() {
-            Ok(_) => process::exit(0),
-            Err(msg) => {
-                println!("Error:\n{}", msg);
-                process::exit(1);
+        Command::Debug { input, args } => report(debug(options, input, args)),
+        Command::Dap => report(dap::serve(io::stdin().lock(), io::stdout())),
+        Command::Lsp => report(lsp::serve(io::stdin().lock(), io::stdout())),
+        Command::Check { files } => check_files(options, &files),
+        Command::Fmt { files, check } => format_files(&files, check),
+        Command::Tokens { input, format } => report(
+            read_input(&input)
+                .and_then(|source| dump_tokens(&source, format))
+                .map(|dump| print!("{}", dump)),
+        ),
+        Command::Ast { input, format } => report(
+            read_input(&input)
+                .and_then(|source| dump_ast(&source, format))
+                .map(|dump| print!("{}", dump)),
+        ),
+        Command::Compile { script, out } => report(compile(options, &script, out)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse(args).unwrap().command
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn runs_scripts_and_passes_their_arguments_through() {
+        assert_eq!(command(&[]), Command::Repl);
+        assert_eq!(
+            command(&["main.lox", "--vm", "x"]),
+            Command::Run {
+                input: Input::File("main.lox".into()),
+                args: strings(&["--vm", "x"]),
+            }
+        );
+        assert_eq!(
+            command(&["run", "-", "a"]),
+            Command::Run {
+                input: Input::Stdin,
+                args: strings(&["a"]),
             }
+        );
+
+        let cli = parse(&["--vm", "-q", "-e", "print 1;", "a", "b"]).unwrap();
+        assert!(cli.options.vm && cli.options.quiet);
+        assert_eq!(cli.options.limits, Limits::default());
+        assert_eq!(
+            cli.command,
+            Command::Run {
+                input: Input::Inline("print 1;".into()),
+                args: strings(&["a", "b"]),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(
+            command(&["fmt", "--check", "a.lox", "b.lox"]),
+            Command::Fmt {
+                files: strings(&["a.lox", "b.lox"]),
+                check: true,
+            }
+        );
+        assert_eq!(
+            command(&["ast", "--json", "a.lox"]),
+            Command::Ast {
+                input: Input::File("a.lox".into()),
+                format: DumpFormat::Json,
+            }
+        );
+        assert_eq!(
+            command(&["--dump-tokens", "-e", "1"]),
+            Command::Tokens {
+                input: Input::Inline("1".into()),
+                format: DumpFormat::Text,
+            }
+        );
+        assert_eq!(
+            command(&["compile", "a.lox", "-o", "a.out"]),
+            Command::Compile {
+                script: "a.lox".into(),
+                out: Some("a.out".into()),
+            }
+        );
+        assert_eq!(
+            command(&["debug", "a.lox", "--trace"]),
+            Command::Debug {
+                input: Input::File("a.lox".into()),
+                args: strings(&["--trace"]),
+            }
+        );
+        assert_eq!(command(&["fmt", "--help"]), Command::Help);
+    }
+
+    #[test]
+    fn parses_limits() {
+        let cli = parse(&[
+            "--max-steps",
+            "1000",
+            "--max-heap",
+            "65536",
+            "--timeout",
+            "250",
+            "a.lox",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.options.limits,
+            Limits {
+                max_steps: Some(1000),
+                max_heap_bytes: Some(65536),
+                timeout: Some(Duration::from_millis(250)),
+                ..Limits::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--max-depth", "64", "a.lox"])
+                .unwrap()
+                .options
+                .limits
+                .max_depth,
+            64
+        );
+        assert_eq!(
+            parse(&["--max-nesting", "2000", "a.lox"])
+                .unwrap()
+                .options
+                .limits
+                .max_nesting,
+            2000
+        );
+    }
+
+    #[test]
+    fn rejects_misused_options() {
+        for args in [
+            &["--bogus"][..],
+            &["run"],
+            &["repl", "a.lox"],
+            &["fmt"],
+            &["ast", "a.lox", "b.lox"],
+            &["--check", "a.lox"],
+            &["tokens", "--format=xml", "a.lox"],
+            &["-e"],
+            &["debug", "-"],
+            &["debug", "--vm", "a.lox"],
+            &["dap", "a.lox"],
+            &["lsp", "a.lox"],
+            &["--max-steps", "lots", "a.lox"],
+            &["--max-depth", "-1", "a.lox"],
+            &["--max-nesting", "a.lox"],
+            &["--max-heap"],
+            &["--timeout", "1.5", "a.lox"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
         }
     }
 }
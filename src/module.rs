@@ -0,0 +1,73 @@
+//! Finding and loading the files scripts `import`. Both backends resolve and parse modules
+//! here; each keeps its own cache of the modules it has run.
+
+use crate::environment::Environment;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// An imported module on the tree-walker. Its bindings are the module's top-level scope,
+/// which its functions keep using after the import.
+#[derive(Debug)]
+pub struct Module {
+    pub file: Rc<str>,
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+/// How `print` names the module loaded from `file`: the file name without its extension.
+pub fn name(file: &str) -> &str {
+    Path::new(file)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(file)
+}
+
+/// Finds the file an `import` names, relative to the directory of the importing file, or to
+/// the working directory for code that didn't come from a file.
+pub fn resolve(importer: Option<&str>, path: &str) -> Result<PathBuf, String> {
+    let dir = importer
+        .and_then(|importer| Path::new(importer).parent())
+        .unwrap_or(Path::new(""));
+    dir.join(path)
+        .canonicalize()
+        .map_err(|e| format!("Could not open module '{}': {}.", path, e))
+}
+
+/// Reads and parses a module, describing any failure in one message for the `import` that
+/// asked for it to report.
+pub fn parse(file: &Path, optimize: bool, max_nesting: usize) -> Result<Vec<Stmt>, String> {
+    let failed = |report: String| format!("Could not load module '{}': {}", file.display(), report);
+    let source = fs::read_to_string(file).map_err(|e| failed(e.to_string()))?;
+    let tokens = Scanner::new(&source).scan_tokens().map_err(failed)?;
+    let statements = Parser::new(tokens)
+        .max_nesting(max_nesting)
+        .parse()
+        .map_err(|error| failed(error.report()))?;
+    Ok(if optimize {
+        optimizer::optimize(statements)
+    } else {
+        statements
+    })
+}
+
+/// Fails if `file` is still loading, listing the imports that lead back to it. `loading`
+/// holds the modules being loaded, outermost first; the main script, `main`, counts as one.
+pub fn check_cycle(main: Option<&str>, loading: &[PathBuf], file: &Path) -> Result<(), String> {
+    let main = main.and_then(|main| Path::new(main).canonicalize().ok());
+    let chain: Vec<&Path> = main.iter().chain(loading).map(PathBuf::as_path).collect();
+    let Some(start) = chain.iter().position(|&path| path == file) else {
+        return Ok(());
+    };
+    let names: Vec<String> = chain[start..]
+        .iter()
+        .copied()
+        .chain([file])
+        .map(|path| path.display().to_string())
+        .collect();
+    Err(format!("Import cycle: {}.", names.join(" -> ")))
+}
@@ -0,0 +1,332 @@
+//! A constant-folding pass over the AST, run between parsing and execution.
+//!
+//! The pass only rewrites an expression when the result is guaranteed to behave the same at
+//! runtime, including which errors are raised. Operations on literals that would fail, such as
+//! `-"a"` or `1 + nil`, are left in place so the error is still reported when (and if) the
+//! code runs.
+
+use crate::expr::{fold_children, Expr, Folder, LiteralValue};
+use crate::scanner::{Token, TokenType};
+use crate::stmt::Stmt;
+use std::rc::Rc;
+
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().map(optimize_stmt).collect()
+}
+
+fn optimize_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Block { statements } => Stmt::Block {
+            statements: optimize(statements),
+        },
+        Stmt::Expression { expression } => Stmt::Expression {
+            expression: optimize_expr(expression),
+        },
+        Stmt::For {
+            keyword,
+            initializer,
+            condition,
+            increment,
+            body,
+        } => Stmt::For {
+            keyword,
+            initializer: initializer.map(|stmt| Box::new(optimize_stmt(*stmt))),
+            condition: condition.map(optimize_expr),
+            increment: increment.map(optimize_expr),
+            body: Box::new(optimize_stmt(*body)),
+        },
+        Stmt::Function { name, params, body } => Stmt::Function {
+            name,
+            params,
+            body: Rc::new(optimize(Rc::unwrap_or_clone(body))),
+        },
+        Stmt::If {
+            keyword,
+            condition,
+            then_branch,
+            else_branch,
+        } => Stmt::If {
+            keyword,
+            condition: optimize_expr(condition),
+            then_branch: Box::new(optimize_stmt(*then_branch)),
+            else_branch: else_branch.map(|stmt| Box::new(optimize_stmt(*stmt))),
+        },
+        Stmt::Print {
+            keyword,
+            expression,
+        } => Stmt::Print {
+            keyword,
+            expression: optimize_expr(expression),
+        },
+        Stmt::Return { keyword, value } => Stmt::Return {
+            keyword,
+            value: value.map(optimize_expr),
+        },
+        Stmt::Import { .. } => stmt,
+        Stmt::Throw { keyword, value } => Stmt::Throw {
+            keyword,
+            value: optimize_expr(value),
+        },
+        Stmt::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        } => Stmt::Try {
+            keyword,
+            body: optimize(body),
+            catch: catch.map(|(name, body)| (name, optimize(body))),
+            finally: finally.map(optimize),
+        },
+        Stmt::Var { name, initializer } => Stmt::Var {
+            name,
+            initializer: initializer.map(optimize_expr),
+        },
+        Stmt::While {
+            keyword,
+            condition,
+            body,
+        } => Stmt::While {
+            keyword,
+            condition: optimize_expr(condition),
+            body: Box::new(optimize_stmt(*body)),
+        },
+    }
+}
+
+pub fn optimize_expr(expr: Expr) -> Expr {
+    ConstantFolder.fold_expr(expr)
+}
+
+struct ConstantFolder;
+
+impl Folder for ConstantFolder {
+    /// Simplifies a node after its children have been simplified.
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        simplify(fold_children(self, expr))
+    }
+}
+
+fn simplify(expr: Expr) -> Expr {
+    match expr {
+        // The tree already encodes precedence, so groupings carry no meaning at runtime.
+        Expr::Grouping { expression } => *expression,
+        Expr::Unary { operator, right } => match (operator.token_type, *right) {
+            (
+                TokenType::Minus,
+                Expr::Literal {
+                    value: LiteralValue::Number(n),
+                },
+            ) => number(-n),
+            (TokenType::Bang, Expr::Literal { value }) => {
+                literal(LiteralValue::from(!is_truthy(&value)))
+            }
+            // Negation always yields a number (or fails), so negating twice is a no-op; the
+            // same goes for `!` on something that is already a boolean.
+            (
+                TokenType::Minus,
+                Expr::Unary {
+                    operator: inner,
+                    right: operand,
+                },
+            ) if inner.token_type == TokenType::Minus && is_numeric(&operand) => *operand,
+            (
+                TokenType::Bang,
+                Expr::Unary {
+                    operator: inner,
+                    right: operand,
+                },
+            ) if inner.token_type == TokenType::Bang && is_boolean(&operand) => *operand,
+            (_, right) => Expr::Unary {
+                operator,
+                right: Box::new(right),
+            },
+        },
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            if let (Expr::Literal { value: l }, Expr::Literal { value: r }) = (&*left, &*right) {
+                if let Some(value) = fold_binary(operator.token_type, l, r) {
+                    return literal(value);
+                }
+            }
+
+            simplify_identity(operator, *left, *right)
+        }
+        Expr::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            // `and`/`or` produce one of their operands, so a literal left side decides which.
+            if let Expr::Literal { value } = &*left {
+                let short_circuits = match operator.token_type {
+                    TokenType::Or => is_truthy(value),
+                    _ => !is_truthy(value),
+                };
+                return if short_circuits { *left } else { *right };
+            }
+
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            }
+        }
+        _ => expr,
+    }
+}
+
+/// Evaluates a binary operator on two literals, or returns `None` if doing so at runtime
+/// would raise an error.
+fn fold_binary(
+    operator: TokenType,
+    left: &LiteralValue,
+    right: &LiteralValue,
+) -> Option<LiteralValue> {
+    use LiteralValue::{Number, StringValue};
+
+    let value = match (operator, left, right) {
+        (TokenType::Plus, Number(l), Number(r)) => Number(l + r),
+        (TokenType::Plus, StringValue(_) | Number(_), StringValue(_) | Number(_)) => {
+            StringValue(format!("{}{}", left, right).into())
+        }
+        (TokenType::Minus, Number(l), Number(r)) => Number(l - r),
+        (TokenType::Star, Number(l), Number(r)) => Number(l * r),
+        (TokenType::Slash, Number(l), Number(r)) => Number(l / r),
+        (TokenType::Greater, Number(l), Number(r)) => LiteralValue::from(l > r),
+        (TokenType::GreaterEqual, Number(l), Number(r)) => LiteralValue::from(l >= r),
+        (TokenType::Less, Number(l), Number(r)) => LiteralValue::from(l < r),
+        (TokenType::LessEqual, Number(l), Number(r)) => LiteralValue::from(l <= r),
+        (TokenType::Greater, StringValue(l), StringValue(r)) => LiteralValue::from(l > r),
+        (TokenType::GreaterEqual, StringValue(l), StringValue(r)) => LiteralValue::from(l >= r),
+        (TokenType::Less, StringValue(l), StringValue(r)) => LiteralValue::from(l < r),
+        (TokenType::LessEqual, StringValue(l), StringValue(r)) => LiteralValue::from(l <= r),
+        (TokenType::EqualEqual, _, _) => LiteralValue::from(literals_equal(left, right)?),
+        (TokenType::BangEqual, _, _) => LiteralValue::from(!literals_equal(left, right)?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Equality with the interpreter's rules: numbers and strings only compare with their own
+/// type, and booleans and nil compare with each other.
+fn literals_equal(left: &LiteralValue, right: &LiteralValue) -> Option<bool> {
+    use LiteralValue::{False, Nil, Number, StringValue, True};
+
+    match (left, right) {
+        (Number(l), Number(r)) => Some(l == r),
+        (StringValue(l), StringValue(r)) => Some(l == r),
+        (True | False | Nil, True | False | Nil) => Some(left == right),
+        _ => None,
+    }
+}
+
+/// Rewrites `n * 1`, `1 * n`, `n / 1` and `n - 0` to `n` when `n` is known to produce a
+/// number. `n + 0` is left alone because it turns `-0` into `0`.
+fn simplify_identity(operator: Token, left: Expr, right: Expr) -> Expr {
+    let is_number = |expr: &Expr, n: f64| matches!(expr, Expr::Literal { value: LiteralValue::Number(value) } if *value == n);
+
+    match operator.token_type {
+        TokenType::Star if is_number(&right, 1.0) && is_numeric(&left) => left,
+        TokenType::Star if is_number(&left, 1.0) && is_numeric(&right) => right,
+        TokenType::Slash if is_number(&right, 1.0) && is_numeric(&left) => left,
+        TokenType::Minus if is_number(&right, 0.0) && is_numeric(&left) => left,
+        _ => Expr::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        },
+    }
+}
+
+/// Whether `expr` always evaluates to a number or raises an error.
+fn is_numeric(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal { value } => matches!(value, LiteralValue::Number(_)),
+        Expr::Unary { operator, .. } => operator.token_type == TokenType::Minus,
+        Expr::Binary { operator, .. } => matches!(
+            operator.token_type,
+            TokenType::Minus | TokenType::Star | TokenType::Slash
+        ),
+        _ => false,
+    }
+}
+
+/// Whether `expr` always evaluates to a boolean or raises an error.
+fn is_boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal { value } => matches!(value, LiteralValue::True | LiteralValue::False),
+        Expr::Unary { operator, .. } => operator.token_type == TokenType::Bang,
+        Expr::Binary { operator, .. } => matches!(
+            operator.token_type,
+            TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::Less
+                | TokenType::LessEqual
+                | TokenType::EqualEqual
+                | TokenType::BangEqual
+        ),
+        _ => false,
+    }
+}
+
+fn is_truthy(value: &LiteralValue) -> bool {
+    !matches!(value, LiteralValue::False | LiteralValue::Nil)
+}
+
+fn literal(value: LiteralValue) -> Expr {
+    Expr::Literal { value }
+}
+
+fn number(n: f64) -> Expr {
+    literal(LiteralValue::Number(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn optimized(source: &str) -> String {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let statements = optimize(Parser::new(tokens).parse().unwrap());
+        match &statements[..] {
+            [Stmt::Expression { expression }] => expression.to_string(),
+            other => panic!("expected one expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn folds_constant_subtrees() {
+        assert_eq!(optimized("(1 + 2) * 3"), "9");
+        assert_eq!(optimized("\"n = \" + (4 / 2)"), "n = 2");
+        assert_eq!(optimized("!(1 < 2) == false"), "true");
+        assert_eq!(optimized("nil or x"), "x");
+        assert_eq!(optimized("x + (2 - 1)"), "(+ x 1)");
+    }
+
+    #[test]
+    fn keeps_operations_that_fail_at_runtime() {
+        assert_eq!(optimized("-\"a\""), "(- a)");
+        assert_eq!(optimized("1 + nil"), "(+ 1 nil)");
+        assert_eq!(optimized("1 == \"1\""), "(== 1 1)");
+        assert_eq!(optimized("1 < (2 + \"\")"), "(< 1 2)");
+    }
+
+    #[test]
+    fn simplifies_identities_only_for_known_numbers() {
+        assert_eq!(optimized("-x * 1"), "(- x)");
+        assert_eq!(optimized("(a - b) / 1"), "(- a b)");
+        assert_eq!(optimized("- -(a * b)"), "(* a b)");
+        assert_eq!(optimized("!!(a < b)"), "(< a b)");
+
+        // `x` might be a string, where `* 1` is an error, and `!!x` converts to a boolean.
+        assert_eq!(optimized("x * 1"), "(* x 1)");
+        assert_eq!(optimized("!!x"), "(! (! x))");
+        assert_eq!(optimized("-x + 0"), "(+ (- x) 0)");
+    }
+}
@@ -1,247 +1,657 @@
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
 use crate::error::RuntimeError;
-use crate::expr::{Expr, LiteralValue as ExprLiteralValue};
-use crate::scanner::{LiteralValue as ScannerLiteralValue, Token, TokenType};
-
+use crate::limits::{self, Limits, StackGuard};
+use crate::scanner::{Token, TokenType};
+use crate::stmt::Stmt;
+use std::iter::Peekable;
+use std::vec;
+
+/// The Lox grammar. It builds a concrete syntax tree, and [`Parser::parse`] lowers that tree
+/// to the statements the backends run, so every tool reads the language the same way.
 pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
+    tokens: Peekable<vec::IntoIter<SyntaxToken>>,
+    function_depth: usize,
+    /// How deeply the syntax tree built so far nests at the current token.
+    depth: usize,
+    max_nesting: usize,
 }
 
+type ParseResult = Result<SyntaxElement, RuntimeError>;
+
 impl Parser {
+    /// Takes the tokens of a whole source, ending with `EOF`. Any whitespace and comment
+    /// tokens among them become the trivia of the tree.
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens: SyntaxToken::group(tokens).into_iter().peekable(),
+            function_depth: 0,
+            depth: 0,
+            max_nesting: Limits::default().max_nesting,
+        }
     }
 
-    pub fn expression(&mut self) -> Result<Expr, RuntimeError> {
-        self.equality()
+    /// Rejects code that nests statements and expressions more than `max_nesting` deep, which
+    /// the recursive passes over the syntax tree couldn't handle.
+    pub fn max_nesting(mut self, max_nesting: usize) -> Self {
+        self.max_nesting = max_nesting;
+        self
     }
 
-    fn equality(&mut self) -> Result<Expr, RuntimeError> {
-        let mut expr = self.comparison()?;
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, RuntimeError> {
+        Ok(self.parse_tree()?.to_stmts())
+    }
 
-        while self.matches(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous().clone();
-            let right = self.comparison()?;
+    /// Parses the whole token stream into a `Program` node.
+    pub fn parse_tree(&mut self) -> Result<SyntaxNode, RuntimeError> {
+        let _stack = StackGuard::new(Limits::default().max_stack_bytes);
+        let mut children = vec![];
+        while !self.is_at_end() {
+            children.push(self.nested(Self::declaration)?);
+        }
+        children.push(self.advance());
+        Ok(SyntaxNode::new(SyntaxKind::Program, children))
+    }
 
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    fn declaration(&mut self) -> ParseResult {
+        if self.check(TokenType::Fun) {
+            return self.function();
         }
+        if self.check(TokenType::Var) {
+            return self.var_declaration();
+        }
+        if self.check(TokenType::Import) {
+            return self.import_declaration();
+        }
+        self.statement()
+    }
 
-        Ok(expr)
+    fn import_declaration(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        self.expect(
+            &mut children,
+            TokenType::String,
+            "Expect module path after 'import'",
+        )?;
+        self.expect(
+            &mut children,
+            TokenType::As,
+            "Expect 'as' after module path",
+        )?;
+        self.expect(&mut children, TokenType::Identifier, "Expect module name")?;
+        self.expect(
+            &mut children,
+            TokenType::Semicolon,
+            "Expect ';' after import",
+        )?;
+        Ok(node(SyntaxKind::Import, children))
     }
 
-    fn comparison(&mut self) -> Result<Expr, RuntimeError> {
-        let mut expr = self.term()?;
+    fn function(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        self.expect(&mut children, TokenType::Identifier, "Expect function name")?;
+
+        let mut params = vec![];
+        self.expect(
+            &mut params,
+            TokenType::LeftParen,
+            "Expect '(' after function name",
+        )?;
+        if !self.check(TokenType::RightParen) {
+            let mut count = 0;
+            loop {
+                if count >= 255 {
+                    return Err(self.error("Can't have more than 255 parameters."));
+                }
+                self.expect(&mut params, TokenType::Identifier, "Expect parameter name")?;
+                self.type_annotation(&mut params)?;
+                count += 1;
+
+                if !self.eat(&mut params, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(
+            &mut params,
+            TokenType::RightParen,
+            "Expect ')' after parameters",
+        )?;
+        children.push(SyntaxNode::new(SyntaxKind::ParamList, params).into());
+        self.type_annotation(&mut children)?;
+
+        if !self.check(TokenType::LeftBrace) {
+            return Err(self.expected("Expect '{' before function body"));
+        }
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        children.push(body?);
 
-        while self.matches(&[
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
-            let operator = self.previous().clone();
-            let right = self.term()?;
+        Ok(node(SyntaxKind::FunDecl, children))
+    }
 
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    fn var_declaration(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        self.expect(&mut children, TokenType::Identifier, "Expect variable name")?;
+        self.type_annotation(&mut children)?;
+        if self.eat(&mut children, TokenType::Equal) {
+            children.push(self.expression()?);
         }
-
-        Ok(expr)
+        self.expect(
+            &mut children,
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration",
+        )?;
+        Ok(node(SyntaxKind::VarDecl, children))
     }
 
-    fn term(&mut self) -> Result<Expr, RuntimeError> {
-        let mut expr = self.factor()?;
+    /// An optional `: Type` annotation. It gets a node of its own, so the type name isn't
+    /// mistaken for one of the names around it. Only `typecheck` reads annotations; lowering
+    /// drops them, so running code ignores them.
+    fn type_annotation(&mut self, children: &mut Vec<SyntaxElement>) -> Result<(), RuntimeError> {
+        if self.check(TokenType::Colon) {
+            let mut annotation = vec![self.advance()];
+            self.expect(
+                &mut annotation,
+                TokenType::Identifier,
+                "Expect type name after ':'",
+            )?;
+            children.push(node(SyntaxKind::TypeAnnotation, annotation));
+        }
+        Ok(())
+    }
 
-        while self.matches(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right = self.factor()?;
+    fn statement(&mut self) -> ParseResult {
+        match self.peek().token_type {
+            TokenType::For => self.for_statement(),
+            TokenType::If => self.if_statement(),
+            TokenType::Print => {
+                let mut children = vec![self.advance(), self.expression()?];
+                self.expect(
+                    &mut children,
+                    TokenType::Semicolon,
+                    "Expect ';' after value",
+                )?;
+                Ok(node(SyntaxKind::Print, children))
+            }
+            TokenType::Return => self.return_statement(),
+            TokenType::Throw => {
+                let mut children = vec![self.advance(), self.expression()?];
+                self.expect(
+                    &mut children,
+                    TokenType::Semicolon,
+                    "Expect ';' after thrown value",
+                )?;
+                Ok(node(SyntaxKind::Throw, children))
+            }
+            TokenType::Try => self.try_statement(),
+            TokenType::While => {
+                let mut children = vec![self.advance()];
+                self.expect(
+                    &mut children,
+                    TokenType::LeftParen,
+                    "Expect '(' after 'while'",
+                )?;
+                children.push(self.expression()?);
+                self.expect(
+                    &mut children,
+                    TokenType::RightParen,
+                    "Expect ')' after condition",
+                )?;
+                children.push(self.nested(Self::statement)?);
+                Ok(node(SyntaxKind::While, children))
+            }
+            TokenType::LeftBrace => self.block(),
+            _ => self.expression_statement(),
+        }
+    }
 
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    fn for_statement(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        self.expect(
+            &mut children,
+            TokenType::LeftParen,
+            "Expect '(' after 'for'",
+        )?;
+
+        if !self.eat(&mut children, TokenType::Semicolon) {
+            if self.check(TokenType::Var) {
+                children.push(self.var_declaration()?);
+            } else {
+                children.push(self.expression_statement()?);
+            }
         }
 
-        Ok(expr)
+        if !self.check(TokenType::Semicolon) {
+            children.push(self.expression()?);
+        }
+        self.expect(
+            &mut children,
+            TokenType::Semicolon,
+            "Expect ';' after loop condition",
+        )?;
+
+        if !self.check(TokenType::RightParen) {
+            children.push(self.expression()?);
+        }
+        self.expect(
+            &mut children,
+            TokenType::RightParen,
+            "Expect ')' after for clauses",
+        )?;
+
+        children.push(self.nested(Self::statement)?);
+        Ok(node(SyntaxKind::For, children))
     }
 
-    fn factor(&mut self) -> Result<Expr, RuntimeError> {
-        let mut expr = self.unary()?;
-
-        while self.matches(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
+    fn if_statement(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        self.expect(&mut children, TokenType::LeftParen, "Expect '(' after 'if'")?;
+        children.push(self.expression()?);
+        self.expect(
+            &mut children,
+            TokenType::RightParen,
+            "Expect ')' after if condition",
+        )?;
+
+        children.push(self.nested(Self::statement)?);
+        if self.eat(&mut children, TokenType::Else) {
+            children.push(self.nested(Self::statement)?);
+        }
+        Ok(node(SyntaxKind::If, children))
+    }
 
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    fn return_statement(&mut self) -> ParseResult {
+        if self.function_depth == 0 {
+            return Err(self.error("Can't return from top-level code."));
         }
 
-        Ok(expr)
+        let mut children = vec![self.advance()];
+        if !self.check(TokenType::Semicolon) {
+            children.push(self.expression()?);
+        }
+        self.expect(
+            &mut children,
+            TokenType::Semicolon,
+            "Expect ';' after return value",
+        )?;
+        Ok(node(SyntaxKind::Return, children))
     }
 
-    fn unary(&mut self) -> Result<Expr, RuntimeError> {
-        if self.matches(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
+    fn try_statement(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        if !self.check(TokenType::LeftBrace) {
+            return Err(self.expected("Expect '{' after 'try'"));
+        }
+        children.push(self.block()?);
+
+        if self.check(TokenType::Catch) {
+            let mut catch = vec![self.advance()];
+            self.expect(&mut catch, TokenType::LeftParen, "Expect '(' after 'catch'")?;
+            self.expect(
+                &mut catch,
+                TokenType::Identifier,
+                "Expect exception variable name",
+            )?;
+            self.expect(
+                &mut catch,
+                TokenType::RightParen,
+                "Expect ')' after exception variable",
+            )?;
+            if !self.check(TokenType::LeftBrace) {
+                return Err(self.expected("Expect '{' before catch body"));
+            }
+            catch.push(self.block()?);
+            children.push(node(SyntaxKind::Catch, catch));
+        }
 
-            return Ok(Expr::Unary {
-                operator,
-                right: Box::new(right),
-            });
+        if self.check(TokenType::Finally) {
+            let mut finally = vec![self.advance()];
+            if !self.check(TokenType::LeftBrace) {
+                return Err(self.expected("Expect '{' after 'finally'"));
+            }
+            finally.push(self.block()?);
+            children.push(node(SyntaxKind::Finally, finally));
         }
 
-        self.primary()
+        if children.len() == 2 {
+            return Err(self.expected("Expect 'catch' or 'finally' after try block"));
+        }
+        Ok(node(SyntaxKind::Try, children))
     }
 
-    fn primary(&mut self) -> Result<Expr, RuntimeError> {
-        if self.matches(&[TokenType::False]) {
-            return Ok(Expr::Literal {
-                value: ExprLiteralValue::False,
-            });
+    fn block(&mut self) -> ParseResult {
+        let mut children = vec![self.advance()];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            children.push(self.nested(Self::declaration)?);
         }
+        self.expect(
+            &mut children,
+            TokenType::RightBrace,
+            "Expect '}' after block",
+        )?;
+        Ok(node(SyntaxKind::Block, children))
+    }
 
-        if self.matches(&[TokenType::True]) {
-            return Ok(Expr::Literal {
-                value: ExprLiteralValue::True,
-            });
+    fn expression_statement(&mut self) -> ParseResult {
+        let mut children = vec![self.expression()?];
+        // A trailing expression may omit its semicolon, so `1 + 2` is still a valid script.
+        if !self.is_at_end() {
+            self.expect(
+                &mut children,
+                TokenType::Semicolon,
+                "Expect ';' after expression",
+            )?;
         }
+        Ok(node(SyntaxKind::ExprStmt, children))
+    }
 
-        if self.matches(&[TokenType::Nil]) {
-            return Ok(Expr::Literal {
-                value: ExprLiteralValue::Nil,
-            });
-        }
+    fn expression(&mut self) -> ParseResult {
+        self.nested(Self::assignment)
+    }
 
-        if self.matches(&[TokenType::Number]) {
-            if let Some(ScannerLiteralValue::FloatValue(n)) = &self.previous().literal {
-                return Ok(Expr::Literal {
-                    value: ExprLiteralValue::Number(*n),
-                });
-            }
-        }
+    fn assignment(&mut self) -> ParseResult {
+        let target = self.or()?;
+
+        if self.check(TokenType::Equal) {
+            let equals = self.tokens.next().expect("token stream ends with EOF");
+            let value = self.nested(Self::assignment)?;
 
-        if self.matches(&[TokenType::String]) {
-            if let Some(ScannerLiteralValue::StringValue(s)) = &self.previous().literal {
-                return Ok(Expr::Literal {
-                    value: ExprLiteralValue::StringValue(s.clone()),
-                });
+            if matches!(&target, SyntaxElement::Node(node) if node.kind() == SyntaxKind::Variable) {
+                return Ok(node(SyntaxKind::Assign, vec![target, equals.into(), value]));
             }
+
+            return Err(RuntimeError::new(
+                "Invalid assignment target.",
+                equals.token().line_number,
+                equals.token().clone(),
+            ));
         }
 
-        if self.matches(&[TokenType::LeftParen]) {
-            let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expect ')' after expression")?;
-            return Ok(Expr::Grouping {
-                expression: Box::new(expr),
-            });
+        Ok(target)
+    }
+
+    fn or(&mut self) -> ParseResult {
+        self.binary(SyntaxKind::Logical, &[TokenType::Or], Self::and)
+    }
+
+    fn and(&mut self) -> ParseResult {
+        self.binary(SyntaxKind::Logical, &[TokenType::And], Self::equality)
+    }
+
+    fn equality(&mut self) -> ParseResult {
+        self.binary(
+            SyntaxKind::Binary,
+            &[TokenType::BangEqual, TokenType::EqualEqual],
+            Self::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> ParseResult {
+        self.binary(
+            SyntaxKind::Binary,
+            &[
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::Less,
+                TokenType::LessEqual,
+            ],
+            Self::term,
+        )
+    }
+
+    fn term(&mut self) -> ParseResult {
+        self.binary(
+            SyntaxKind::Binary,
+            &[TokenType::Minus, TokenType::Plus],
+            Self::factor,
+        )
+    }
+
+    fn factor(&mut self) -> ParseResult {
+        self.binary(
+            SyntaxKind::Binary,
+            &[TokenType::Slash, TokenType::Star],
+            Self::unary,
+        )
+    }
+
+    /// A left-associative chain of `operand (operator operand)*`.
+    fn binary(
+        &mut self,
+        kind: SyntaxKind,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> ParseResult,
+    ) -> ParseResult {
+        let mut expr = operand(self)?;
+        let depth = self.depth;
+        while operators.iter().any(|&operator| self.check(operator)) {
+            let operator = self.advance();
+            self.enter()?;
+            let right = operand(self)?;
+            expr = node(kind, vec![expr, operator, right]);
         }
+        self.depth = depth;
+        Ok(expr)
+    }
 
-        Err(RuntimeError::new(
-            "Expected expression, but found identifier.",
-            self.peek().line_number,
-            self.peek().clone(),
-        ))
+    fn unary(&mut self) -> ParseResult {
+        if self.check(TokenType::Bang) || self.check(TokenType::Minus) {
+            let operator = self.advance();
+            return Ok(node(
+                SyntaxKind::Unary,
+                vec![operator, self.nested(Self::unary)?],
+            ));
+        }
+        self.call()
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, RuntimeError> {
-        if self.check(token_type) {
-            return Ok(self.advance());
+    fn call(&mut self) -> ParseResult {
+        let mut expr = self.primary()?;
+
+        let depth = self.depth;
+        loop {
+            if self.check(TokenType::LeftParen) {
+                let open = self.advance();
+                self.enter()?;
+                let arguments = self.arguments(open)?;
+                expr = node(SyntaxKind::Call, vec![expr, arguments]);
+            } else if self.check(TokenType::Dot) {
+                let mut children = vec![expr, self.advance()];
+                self.enter()?;
+                self.expect(
+                    &mut children,
+                    TokenType::Identifier,
+                    "Expect property name after '.'",
+                )?;
+                expr = node(SyntaxKind::Get, children);
+            } else {
+                break;
+            }
         }
 
-        Err(RuntimeError::new(
-            &format!("{}, but found {}", message, self.peek().token_type),
-            self.peek().line_number,
-            self.peek().clone(),
-        ))
+        self.depth = depth;
+        Ok(expr)
     }
 
-    fn matches(&mut self, token_types: &[TokenType]) -> bool {
-        for &token_type in token_types {
-            if self.check(token_type) {
-                self.advance();
-                return true;
+    fn arguments(&mut self, open: SyntaxElement) -> ParseResult {
+        let mut arguments = vec![open];
+        if !self.check(TokenType::RightParen) {
+            let mut count = 0;
+            loop {
+                if count >= 255 {
+                    return Err(self.error("Can't have more than 255 arguments."));
+                }
+                arguments.push(self.expression()?);
+                count += 1;
+
+                if !self.eat(&mut arguments, TokenType::Comma) {
+                    break;
+                }
             }
         }
-        false
+        self.expect(
+            &mut arguments,
+            TokenType::RightParen,
+            "Expect ')' after arguments",
+        )?;
+        Ok(node(SyntaxKind::ArgList, arguments))
     }
 
-    fn check(&self, token_type: TokenType) -> bool {
-        if self.is_at_end() {
-            return false;
+    fn primary(&mut self) -> ParseResult {
+        let kind = match self.peek().token_type {
+            TokenType::False | TokenType::True | TokenType::Nil => Some(SyntaxKind::Literal),
+            TokenType::Number | TokenType::String => Some(SyntaxKind::Literal),
+            TokenType::Identifier => Some(SyntaxKind::Variable),
+            TokenType::LeftParen => {
+                let mut children = vec![self.advance(), self.expression()?];
+                self.expect(
+                    &mut children,
+                    TokenType::RightParen,
+                    "Expect ')' after expression",
+                )?;
+                return Ok(node(SyntaxKind::Grouping, children));
+            }
+            _ => None,
+        };
+
+        match kind {
+            Some(kind) => Ok(node(kind, vec![self.advance()])),
+            None => {
+                let found = self.peek().token_type;
+                Err(self.error(&format!("Expected expression, but found {}.", found)))
+            }
         }
+    }
 
-        return self.peek().token_type == token_type;
+    /// Consumes a token of the given type into `children`, or fails saying what was expected.
+    fn expect(
+        &mut self,
+        children: &mut Vec<SyntaxElement>,
+        token_type: TokenType,
+        message: &str,
+    ) -> Result<(), RuntimeError> {
+        if self.eat(children, token_type) {
+            Ok(())
+        } else {
+            Err(self.expected(message))
+        }
     }
 
-    fn advance(&mut self) -> &Token {
-        if !self.is_at_end() {
-            self.current += 1;
+    fn eat(&mut self, children: &mut Vec<SyntaxElement>, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
         }
-        self.previous()
+        children.push(self.advance());
+        true
     }
 
-    fn is_at_end(&self) -> bool {
-        return self.peek().token_type == TokenType::EOF;
+    /// Parses something nested one level deeper than the code around it.
+    fn nested(&mut self, parse: fn(&mut Self) -> ParseResult) -> ParseResult {
+        self.enter()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
-    fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+    /// Goes one level deeper, failing if that is deeper than `max_nesting` or the parser has
+    /// run out of stack on the way.
+    fn enter(&mut self) -> Result<(), RuntimeError> {
+        if self.depth >= self.max_nesting || limits::stack_exhausted() {
+            return Err(self.error("Code is nested too deeply."));
+        }
+        self.depth += 1;
+        Ok(())
     }
 
-    fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
+    fn expected(&mut self, message: &str) -> RuntimeError {
+        let found = self.peek().token_type;
+        self.error(&format!("{}, but found {}", message, found))
     }
 
-    #[allow(unused)]
-    fn synchronize(&mut self) {
-        self.advance();
+    fn error(&mut self, message: &str) -> RuntimeError {
+        let token = self.peek().clone();
+        RuntimeError::new(message, token.line_number, token)
+    }
 
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
+    fn check(&mut self, token_type: TokenType) -> bool {
+        !self.is_at_end() && self.peek().token_type == token_type
+    }
 
-            match self.peek().token_type {
-                TokenType::Class
-                | TokenType::Fun
-                | TokenType::Var
-                | TokenType::For
-                | TokenType::If
-                | TokenType::While
-                | TokenType::Print
-                | TokenType::Return => return,
-                _ => (),
-            }
+    fn is_at_end(&mut self) -> bool {
+        self.peek().token_type == TokenType::EOF
+    }
 
-            self.advance();
-        }
+    fn peek(&mut self) -> &Token {
+        self.tokens
+            .peek()
+            .expect("token stream ends with EOF")
+            .token()
+    }
+
+    fn advance(&mut self) -> SyntaxElement {
+        self.tokens
+            .next()
+            .expect("token stream ends with EOF")
+            .into()
     }
 }
 
+fn node(kind: SyntaxKind, children: Vec<SyntaxElement>) -> SyntaxElement {
+    SyntaxNode::new(kind, children).into()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::Scanner;
+    use crate::scanner::Scanner;
 
     use super::*;
 
     #[test]
     fn test_parse_expression() {
         let tokens = Scanner::new("(1 + 2) * 3").scan_tokens().unwrap();
-        let mut parser = Parser::new(tokens);
-        let expr = parser.expression();
-        match expr {
-            Ok(expr) => println!("Successfully parsed: {:?}", expr),
-            Err(err) => println!("Parser error: {:?}", err.to_string()),
+        let statements = Parser::new(tokens).parse().unwrap();
+
+        assert_eq!(statements.len(), 1);
+        assert_eq!(statements[0].to_string(), "(; (* (group (+ 1 2)) 3))");
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let source = "var a = 1; fun f(x) { return x + a; } if (a) print f(2); else a = 3;";
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+
+        assert_eq!(statements.len(), 3);
+        assert!(matches!(statements[1], Stmt::Function { ref params, .. } if params.len() == 1));
+    }
+
+    #[test]
+    fn test_missing_semicolon_is_reported() {
+        let tokens = Scanner::new("print 1 print 2;").scan_tokens().unwrap();
+        let err = Parser::new(tokens).parse().unwrap_err();
+
+        assert_eq!(err.message, "Expect ';' after value, but found Print");
+    }
+
+    #[test]
+    fn test_deep_nesting_is_reported() {
+        let parse = |source: &str| {
+            let tokens = Scanner::new(source).scan_tokens().unwrap();
+            Parser::new(tokens).max_nesting(8).parse()
+        };
+
+        assert!(parse("print ((1 + 2) * 3);").is_ok());
+        for source in [
+            "print ((((((((1))))))));",
+            "print 1 + 2 + 3 + 4 + 5 + 6 + 7 + 8;",
+            "print !!!!!!!!true;",
+            "f()()()()()()()();",
+            "{{{{{{{{{}}}}}}}}}",
+            "if (a) if (b) if (c) if (d) if (e) if (f) if (g) if (h) print 1;",
+        ] {
+            let err = parse(source).unwrap_err();
+            assert_eq!(err.message, "Code is nested too deeply.", "{}", source);
         }
     }
 }
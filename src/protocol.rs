@@ -0,0 +1,74 @@
+//! The base protocol shared by the debug adapter and the language server: every message is a
+//! JSON body preceded by a `Content-Length` header and a blank line.
+
+use crate::error::LoxError;
+use crate::json::{self, Json};
+use std::io::{self, BufRead, Read, Write};
+
+/// The largest body accepted, so that a bad header can't make the reader allocate without
+/// bound. Larger messages are skipped.
+const MAX_MESSAGE_BYTES: usize = 64 << 20;
+
+/// Reads the next message, or `None` at the end of the input.
+///
+/// A message that can't be understood fails with a `Load` error once it has been skipped, so
+/// the caller can report it and read on. Other errors mean the input itself has failed.
+pub(crate) fn read_message(input: &mut dyn BufRead) -> Result<Option<Json>, LoxError> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(io_error)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        // A body sent without a Content-Length runs into the header of the next message, so
+        // look for the header anywhere on the line.
+        let header = match header.to_ascii_lowercase().rfind("content-length:") {
+            Some(start) => &header[start..],
+            None => header,
+        };
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length =
+        length.ok_or_else(|| LoxError::Load("A message had no Content-Length.".to_string()))?;
+    if length > MAX_MESSAGE_BYTES {
+        io::copy(&mut input.take(length as u64), &mut io::sink()).map_err(io_error)?;
+        return Err(LoxError::Load(format!(
+            "A message of {} bytes is too long.",
+            length
+        )));
+    }
+    let mut body = Vec::new();
+    input
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(io_error)?;
+    if body.len() < length {
+        return Err(LoxError::Io(
+            "The input ended in the middle of a message.".to_string(),
+        ));
+    }
+    let body = String::from_utf8(body).map_err(|e| LoxError::Load(e.to_string()))?;
+    json::parse(&body)
+        .map(Some)
+        .map_err(|e| LoxError::Load(format!("Invalid message: {}", e)))
+}
+
+pub(crate) fn write_message(output: &mut dyn Write, message: &Json) -> Result<(), LoxError> {
+    let body = message.to_compact();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(io_error)
+}
+
+fn io_error(error: io::Error) -> LoxError {
+    LoxError::Io(error.to_string())
+}
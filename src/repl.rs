@@ -0,0 +1,577 @@
+//! The interactive prompt behind `rlox repl`.
+//!
+//! Input is read a line at a time and run once it forms a complete program: source that stops
+//! inside a string, group or block, or partway through a statement, continues on the next line
+//! under a `... ` prompt, and a blank line runs it as it is. Everything runs in one interpreter,
+//! so definitions carry over from one input to the next.
+//!
+//! Lines starting with `:` are commands to the REPL itself rather than Lox source; `:help`
+//! lists them.
+//!
+//! Where lines come from is up to a [`LineEditor`]. Without one, the REPL reads the
+//! interpreter's input stream and writes prompts to its output, which suits piped input and
+//! tests; with the `line-editor` feature, [`TerminalEditor`] adds line editing, history
+//! recall and tab completion.
+
+use crate::dump::{self, DumpFormat};
+use crate::error::LoxError;
+use crate::expr::LiteralValue;
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, TokenType, KEYWORDS};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+const PROMPT: &str = ">>> ";
+const CONTINUATION_PROMPT: &str = "... ";
+/// How many entries the history file keeps.
+const HISTORY_LIMIT: usize = 1000;
+
+const COMMANDS: [&str; 7] = [
+    ":help", ":load", ":reset", ":env", ":ast", ":tokens", ":time",
+];
+
+const HELP: &str = "\
+:help            Show this list
+:load <file>     Run a script in this session
+:reset           Forget everything defined in this session
+:env             List the global variables and their values
+:ast <source>    Show the syntax tree of some source
+:tokens <source> Show the tokens of some source
+:time <source>   Run some source and show how long it took
+";
+
+pub enum ReadLine {
+    Line(String),
+    /// The user pressed Ctrl-C: the input typed so far is discarded.
+    Interrupted,
+    Eof,
+}
+
+pub trait LineEditor {
+    fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine>;
+
+    /// Makes an entry available for recall. Called for each complete input, and on startup for
+    /// the entries loaded from the history file.
+    fn add_history(&mut self, _entry: &str) {}
+
+    /// Called before each line is read with the names of the globals currently defined, for
+    /// completion.
+    fn set_globals(&mut self, _names: &[String]) {}
+}
+
+pub struct Repl<'a> {
+    interpreter: &'a mut Interpreter,
+    editor: Option<Box<dyn LineEditor + 'a>>,
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            interpreter,
+            editor: None,
+            history: vec![],
+            history_file: None,
+        }
+    }
+
+    pub fn with_editor<E: LineEditor + 'a>(mut self, editor: E) -> Self {
+        let mut editor = Box::new(editor);
+        for entry in &self.history {
+            editor.add_history(entry);
+        }
+        self.editor = Some(editor);
+        self
+    }
+
+    /// Loads earlier entries from `path` and appends each new entry to it. History is a
+    /// convenience, so a file that can't be read or written is silently skipped.
+    pub fn with_history_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        let path = path.into();
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let lines: Vec<&str> = contents.lines().collect();
+        let kept = &lines[lines.len().saturating_sub(HISTORY_LIMIT)..];
+        if kept.len() < lines.len() {
+            let _ = fs::write(&path, kept.join("\n") + "\n");
+        }
+
+        for entry in kept.iter().map(|line| unescape(line)) {
+            if let Some(editor) = &mut self.editor {
+                editor.add_history(&entry);
+            }
+            self.history.push(entry);
+        }
+        self.history_file = Some(path);
+        self
+    }
+
+    /// Every complete input, oldest first, including those loaded from the history file.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Reads and runs inputs until the input ends.
+    pub fn run(&mut self) -> Result<(), LoxError> {
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            if let Some(editor) = &mut self.editor {
+                let names: Vec<String> = self
+                    .interpreter
+                    .globals()
+                    .into_iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect();
+                editor.set_globals(&names);
+            }
+            let line = match self.read_line(prompt).map_err(io_error)? {
+                ReadLine::Line(line) => line,
+                ReadLine::Interrupted => {
+                    buffer.clear();
+                    continue;
+                }
+                ReadLine::Eof => return Ok(()),
+            };
+
+            let blank = line.trim().is_empty();
+            if blank && buffer.is_empty() {
+                continue;
+            }
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                let command = line.trim();
+                self.add_history(command);
+                self.command(command)?;
+                continue;
+            }
+            if !buffer.is_empty() {
+                buffer.push('\n');
+            }
+            buffer.push_str(&line);
+            if !blank && is_incomplete(&buffer) {
+                continue;
+            }
+
+            let source = std::mem::take(&mut buffer);
+            let source = source.trim_end();
+            self.add_history(source);
+            self.eval(source)?;
+        }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        if let Some(editor) = &mut self.editor {
+            return editor.read_line(prompt);
+        }
+
+        let output = self.interpreter.output();
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+        Ok(match self.interpreter.read_input_line()? {
+            Some(line) => ReadLine::Line(line),
+            None => ReadLine::Eof,
+        })
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        if self.history.last().is_some_and(|last| last == entry) {
+            return;
+        }
+        self.history.push(entry.to_string());
+        if let Some(editor) = &mut self.editor {
+            editor.add_history(entry);
+        }
+        if let Some(path) = &self.history_file {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", escape(entry));
+            }
+        }
+    }
+
+    /// Runs `source`, echoing its value unless it is nil, or the error it failed with.
+    fn eval(&mut self, source: &str) -> Result<(), LoxError> {
+        let result = self.interpreter.eval_str(source);
+        self.echo(result)
+    }
+
+    fn echo(&mut self, result: Result<LiteralValue, LoxError>) -> Result<(), LoxError> {
+        let output = self.interpreter.output();
+        let written = match result {
+            Ok(LiteralValue::Nil) => Ok(()),
+            Ok(value) => writeln!(output, "{}", value),
+            Err(error) => writeln!(output, "{}", error.report()),
+        };
+        written.map_err(io_error)
+    }
+
+    /// Runs a `:` command. Errors from the command itself are echoed like those from source;
+    /// only failing to write the output ends the session.
+    fn command(&mut self, line: &str) -> Result<(), LoxError> {
+        let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        let result = match (name, argument) {
+            (":help", _) => Ok(HELP.to_string()),
+            (":load", "") => Ok("Usage: :load <file>\n".to_string()),
+            (":load", path) => self.interpreter.run_file(path).map(|_| String::new()),
+            (":reset", _) => {
+                self.interpreter.reset();
+                Ok(String::new())
+            }
+            (":env", _) => Ok(self
+                .interpreter
+                .globals()
+                .into_iter()
+                .map(|(name, value)| format!("{} = {}\n", name, value))
+                .collect()),
+            (":ast", source) => dump::dump_ast(source, DumpFormat::Text),
+            (":tokens", source) => dump::dump_tokens(source, DumpFormat::Text),
+            (":time", source) => {
+                let start = Instant::now();
+                let result = self.interpreter.eval_str(source);
+                let elapsed = start.elapsed();
+                self.echo(result)?;
+                Ok(format!("Took {:?}.\n", elapsed))
+            }
+            _ => Ok(format!(
+                "Unknown command '{}'. Type :help for a list of commands.\n",
+                name
+            )),
+        };
+
+        let output = self.interpreter.output();
+        let written = match result {
+            Ok(text) => write!(output, "{}", text),
+            Err(error) => writeln!(output, "{}", error.report()),
+        };
+        written.map_err(io_error)
+    }
+}
+
+/// Completes the word that ends at byte offset `pos` in `line`: a command name when the line
+/// is one, otherwise a keyword or one of `globals`. Returns where the word starts and the
+/// candidates in order.
+pub fn complete(line: &str, pos: usize, globals: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    if before.starts_with(':') && !before.contains(char::is_whitespace) {
+        let commands = COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(before));
+        return (0, commands.map(|command| command.to_string()).collect());
+    }
+
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| {
+            i + before[i..].chars().next().map_or(1, char::len_utf8)
+        });
+    let word = &before[start..];
+    if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+        return (start, vec![]);
+    }
+
+    let keywords = KEYWORDS.iter().map(|&(keyword, _)| keyword);
+    let mut candidates: Vec<String> = keywords
+        .chain(globals.iter().map(String::as_str))
+        .filter(|name| name.starts_with(word))
+        .map(str::to_string)
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// Whether `source` stops partway through a string, group, block or statement, so that more
+/// input may complete it.
+pub fn is_incomplete(source: &str) -> bool {
+    match Scanner::new(source).scan_tokens() {
+        // An unterminated string runs to the end of the input, so it is always the last error.
+        Err(errors) => errors.ends_with("Unterminated string."),
+        Ok(tokens) => Parser::new(tokens)
+            .parse()
+            .is_err_and(|error| error.token.token_type == TokenType::EOF),
+    }
+}
+
+/// History files hold one entry per line, so line breaks in multi-line entries are escaped.
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+fn io_error(error: io::Error) -> LoxError {
+    LoxError::Io(error.to_string())
+}
+
+/// A line editor for terminals, with cursor movement, history recall, tab completion and
+/// Ctrl-C handling.
+#[cfg(feature = "line-editor")]
+pub struct TerminalEditor {
+    editor: rustyline::Editor<Completions, rustyline::history::DefaultHistory>,
+}
+
+#[cfg(feature = "line-editor")]
+impl TerminalEditor {
+    pub fn new() -> Result<Self, LoxError> {
+        let mut editor = rustyline::Editor::new().map_err(|e| LoxError::Io(e.to_string()))?;
+        editor.set_helper(Some(Completions::default()));
+        Ok(Self { editor })
+    }
+}
+
+/// Hooks [`complete`] into rustyline.
+#[cfg(feature = "line-editor")]
+#[derive(Default)]
+pub struct Completions {
+    globals: Vec<String>,
+}
+
+#[cfg(feature = "line-editor")]
+impl rustyline::completion::Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.globals))
+    }
+}
+
+#[cfg(feature = "line-editor")]
+impl rustyline::hint::Hinter for Completions {
+    type Hint = String;
+}
+
+#[cfg(feature = "line-editor")]
+impl rustyline::highlight::Highlighter for Completions {}
+
+#[cfg(feature = "line-editor")]
+impl rustyline::validate::Validator for Completions {}
+
+#[cfg(feature = "line-editor")]
+impl rustyline::Helper for Completions {}
+
+#[cfg(feature = "line-editor")]
+impl LineEditor for TerminalEditor {
+    fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        use rustyline::error::ReadlineError;
+
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(ReadLine::Line(line)),
+            Err(ReadlineError::Interrupted) => Ok(ReadLine::Interrupted),
+            Err(ReadlineError::Eof) => Ok(ReadLine::Eof),
+            Err(ReadlineError::Io(error)) => Err(error),
+            Err(error) => Err(io::Error::other(error.to_string())),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        let _ = self.editor.add_history_entry(entry);
+    }
+
+    fn set_globals(&mut self, names: &[String]) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.globals = names.to_vec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::SharedBuffer;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    /// Replays canned input and records the prompts it was asked for and the entries it was
+    /// given to recall.
+    #[derive(Default)]
+    struct Scripted {
+        input: VecDeque<ReadLine>,
+        prompts: Rc<RefCell<Vec<String>>>,
+        recalled: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Scripted {
+        fn new(lines: &[Option<&str>]) -> Self {
+            let input = lines
+                .iter()
+                .map(|line| match line {
+                    Some(line) => ReadLine::Line(line.to_string()),
+                    None => ReadLine::Interrupted,
+                })
+                .collect();
+            Self {
+                input,
+                ..Self::default()
+            }
+        }
+    }
+
+    impl LineEditor for Scripted {
+        fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+            self.prompts.borrow_mut().push(prompt.to_string());
+            Ok(self.input.pop_front().unwrap_or(ReadLine::Eof))
+        }
+
+        fn add_history(&mut self, entry: &str) {
+            self.recalled.borrow_mut().push(entry.to_string());
+        }
+    }
+
+    fn run(editor: Scripted) -> String {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_io(io::empty(), output.clone());
+        Repl::new(&mut interpreter)
+            .with_editor(editor)
+            .run()
+            .unwrap();
+        output.contents()
+    }
+
+    #[test]
+    fn continues_incomplete_input_on_the_next_line() {
+        let editor = Scripted::new(&[
+            Some("fun add(a, b) {"),
+            Some("  return a + b;"),
+            Some("}"),
+            Some("add(1,"),
+            Some("2)"),
+            Some("print \"two"),
+            Some("lines\";"),
+        ]);
+        let prompts = editor.prompts.clone();
+
+        assert_eq!(run(editor), "3\ntwo\nlines\n");
+        assert_eq!(
+            prompts.borrow().join(""),
+            ">>> ... ... >>> ... >>> ... >>> "
+        );
+    }
+
+    #[test]
+    fn ctrl_c_discards_input_and_a_blank_line_submits_it() {
+        let editor = Scripted::new(&[
+            Some("print (1 +"),
+            None,
+            Some("print 2"),
+            Some(""),
+            Some("print 3;"),
+        ]);
+
+        assert_eq!(
+            run(editor),
+            "LINE 1: Expect ';' after value, but found EOF\n3\n"
+        );
+    }
+
+    #[test]
+    fn history_is_saved_and_reloaded() {
+        let path = std::env::temp_dir().join(format!("rlox-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_io(io::empty(), output.clone());
+        let editor = Scripted::new(&[
+            Some("var a = \"\\n\";"),
+            Some("{"),
+            Some("}"),
+            Some("{"),
+            Some("}"),
+        ]);
+        Repl::new(&mut interpreter)
+            .with_history_file(&path)
+            .with_editor(editor)
+            .run()
+            .unwrap();
+
+        let editor = Scripted::new(&[]);
+        let recalled = editor.recalled.clone();
+        let repl = Repl::new(&mut interpreter)
+            .with_editor(editor)
+            .with_history_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(repl.history(), ["var a = \"\\n\";", "{\n}"]);
+        assert_eq!(*recalled.borrow(), repl.history());
+    }
+
+    #[test]
+    fn runs_commands() {
+        let path = std::env::temp_dir().join(format!("rlox-load-{}.lox", std::process::id()));
+        fs::write(&path, "var loaded = 1 + 1;").unwrap();
+        let load = format!(":load {}", path.display());
+        let editor = Scripted::new(&[
+            Some(&load),
+            Some("fun f() {}"),
+            Some(":env"),
+            Some(":reset"),
+            Some("loaded"),
+            Some(":ast 1 + 2 * x"),
+            Some(":tokens !="),
+            Some(":time 6 * 7"),
+            Some(":nope"),
+        ]);
+        let output = run(editor);
+        let _ = fs::remove_file(&path);
+
+        let (output, timing) = output.rsplit_once("Took ").unwrap();
+        assert_eq!(
+            output,
+            "clock = <native fn>\n\
+             errorLine = <native fn>\n\
+             errorMessage = <native fn>\n\
+             f = <fn f>\n\
+             loaded = 2\n\
+             readLine = <native fn>\n\
+             LINE 1: ('loaded') Undefined variable 'loaded'.\n\
+             (; (+ 1 (* 2 x)))\n   \
+             1 BangEqual != None\n   \
+             1 EOF  None\n\
+             42\n"
+        );
+        assert!(
+            timing.ends_with(".\nUnknown command ':nope'. Type :help for a list of commands.\n")
+        );
+    }
+
+    #[test]
+    fn completes_commands_keywords_and_globals() {
+        let globals = ["fib".to_string(), "format".to_string()];
+        assert_eq!(complete(":l", 2, &globals), (0, vec![":load".to_string()]));
+        assert_eq!(
+            complete("print fo", 8, &globals),
+            (6, vec!["for".to_string(), "format".to_string()])
+        );
+        assert_eq!(complete("fi(1)", 2, &globals).1, ["fib", "finally"]);
+        assert_eq!(complete("x + 1", 5, &globals).1, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,286 @@
+//! Static name resolution over the concrete syntax tree, for editor tooling.
+//!
+//! The interpreter looks names up when code runs, walking out through the enclosing scopes.
+//! This pass makes the same walk ahead of time: a name refers to the innermost declaration of
+//! it that comes earlier in an enclosing block or function, and failing that to a global. Since
+//! globals are only looked up when the code runs, a global may be declared anywhere in the
+//! file; the last declaration before the use wins, or else the first one after it.
+
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::scanner::TokenType;
+use crate::symbol::Symbol;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Variable,
+    Function,
+    Parameter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub name: Symbol,
+    pub kind: DeclarationKind,
+    /// Where the name is written in the declaration.
+    pub name_range: Range<usize>,
+    /// The whole declaration, e.g. a function from `fun` to its closing brace.
+    pub range: Range<usize>,
+    pub global: bool,
+    /// The function the declaration is inside, as an index into the declarations.
+    pub parent: Option<usize>,
+    /// For functions, the names of the parameters.
+    pub params: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: Symbol,
+    pub range: Range<usize>,
+    /// The declaration the name refers to, as an index into the declarations, or `None` for
+    /// names declared nowhere in the file, like natives.
+    pub declaration: Option<usize>,
+}
+
+/// Every declaration and every use of a name in a program. Ranges are character offsets.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub declarations: Vec<Declaration>,
+    pub references: Vec<Reference>,
+}
+
+impl Resolution {
+    /// The name at `offset`, whether declared or used there, and the declaration it refers to.
+    pub fn name_at(&self, offset: usize) -> Option<(Symbol, Option<usize>)> {
+        let covers = |range: &Range<usize>| range.start <= offset && offset <= range.end;
+        if let Some(index) = self
+            .declarations
+            .iter()
+            .position(|declaration| covers(&declaration.name_range))
+        {
+            return Some((self.declarations[index].name.clone(), Some(index)));
+        }
+        self.references
+            .iter()
+            .find(|reference| covers(&reference.range))
+            .map(|reference| (reference.name.clone(), reference.declaration))
+    }
+
+    pub fn references_to(&self, declaration: usize) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.declaration == Some(declaration))
+    }
+}
+
+pub fn resolve(program: &SyntaxNode) -> Resolution {
+    let mut resolver = Resolver {
+        resolution: Resolution::default(),
+        scopes: vec![],
+        function: None,
+        unresolved: vec![],
+    };
+    resolver.node(program);
+
+    // Globals can be used before they are declared, so they are bound last.
+    for index in resolver.unresolved {
+        let reference = &resolver.resolution.references[index];
+        let globals = || {
+            resolver
+                .resolution
+                .declarations
+                .iter()
+                .enumerate()
+                .filter(|(_, declaration)| declaration.global && declaration.name == reference.name)
+        };
+        let before = globals()
+            .rfind(|(_, declaration)| declaration.name_range.start < reference.range.start);
+        let declaration = before.or_else(|| globals().next()).map(|(index, _)| index);
+        resolver.resolution.references[index].declaration = declaration;
+    }
+    resolver.resolution
+}
+
+struct Resolver {
+    resolution: Resolution,
+    /// The local scopes around the current node, innermost last, with the declarations made so
+    /// far in each.
+    scopes: Vec<Vec<usize>>,
+    function: Option<usize>,
+    /// References that no local declaration matched.
+    unresolved: Vec<usize>,
+}
+
+impl Resolver {
+    fn node(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            SyntaxKind::Import => {
+                let name = identifier(node);
+                self.declare(name, DeclarationKind::Variable, node.range(), vec![]);
+            }
+            SyntaxKind::VarDecl => {
+                self.children(node);
+                let name = identifier(node);
+                self.declare(name, DeclarationKind::Variable, node.range(), vec![]);
+            }
+            SyntaxKind::FunDecl => {
+                let params = node.child_nodes().next();
+                let params: Vec<&SyntaxToken> = params.map_or(vec![], identifiers);
+                let names = params
+                    .iter()
+                    .map(|param| param.token().lexeme.symbol())
+                    .collect();
+                let function = self.declare(
+                    identifier(node),
+                    DeclarationKind::Function,
+                    node.range(),
+                    names,
+                );
+
+                let enclosing = self.function.replace(function);
+                self.scopes.push(vec![]);
+                for param in params {
+                    self.declare(param, DeclarationKind::Parameter, param.range(), vec![]);
+                }
+                // The body shares the parameters' scope.
+                self.children(node.body());
+                self.scopes.pop();
+                self.function = enclosing;
+            }
+            SyntaxKind::Block | SyntaxKind::For => {
+                self.scopes.push(vec![]);
+                self.children(node);
+                self.scopes.pop();
+            }
+            SyntaxKind::Catch => {
+                self.scopes.push(vec![]);
+                let name = identifier(node);
+                self.declare(name, DeclarationKind::Variable, node.range(), vec![]);
+                self.children(node);
+                self.scopes.pop();
+            }
+            SyntaxKind::Variable => self.reference(identifier(node)),
+            _ => self.children(node),
+        }
+    }
+
+    fn children(&mut self, node: &SyntaxNode) {
+        for child in node.child_nodes() {
+            self.node(child);
+        }
+    }
+
+    fn declare(
+        &mut self,
+        name: &SyntaxToken,
+        kind: DeclarationKind,
+        range: Range<usize>,
+        params: Vec<Symbol>,
+    ) -> usize {
+        let index = self.resolution.declarations.len();
+        self.resolution.declarations.push(Declaration {
+            name: name.token().lexeme.symbol(),
+            kind,
+            name_range: name.range(),
+            range,
+            global: self.scopes.is_empty(),
+            parent: self.function,
+            params,
+        });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(index);
+        }
+        index
+    }
+
+    fn reference(&mut self, name: &SyntaxToken) {
+        let symbol = name.token().lexeme.symbol();
+        let declarations = &self.resolution.declarations;
+        let declaration = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| {
+                scope
+                    .iter()
+                    .rev()
+                    .find(|&&index| declarations[index].name == symbol)
+            })
+            .copied();
+
+        if declaration.is_none() {
+            self.unresolved.push(self.resolution.references.len());
+        }
+        self.resolution.references.push(Reference {
+            name: symbol,
+            range: name.range(),
+            declaration,
+        });
+    }
+}
+
+fn identifiers(node: &SyntaxNode) -> Vec<&SyntaxToken> {
+    node.children()
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Token(token) if token.token().token_type == TokenType::Identifier => {
+                Some(token)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The name a declaration or variable node introduces or uses.
+pub(crate) fn identifier(node: &SyntaxNode) -> &SyntaxToken {
+    identifiers(node)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cst;
+    use crate::limits::Limits;
+
+    /// Each reference as `name@offset -> declaration offset`.
+    fn bindings(source: &str) -> Vec<String> {
+        let resolution = resolve(&cst::parse(source, Limits::default().max_nesting).unwrap());
+        resolution
+            .references
+            .iter()
+            .map(|reference| {
+                let target = reference.declaration.map_or("?".to_string(), |index| {
+                    resolution.declarations[index].name_range.start.to_string()
+                });
+                format!("{}@{} -> {}", reference.name, reference.range.start, target)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binds_names_to_the_innermost_earlier_declaration() {
+        //                     0         1         2         3         4         5
+        //                     0123456789012345678901234567890123456789012345678901234
+        let source = "var a = 1; fun f(a) { print a; { var a = a; print a; } } print a;";
+        assert_eq!(
+            bindings(source),
+            ["a@28 -> 17", "a@41 -> 17", "a@50 -> 37", "a@63 -> 4",]
+        );
+    }
+
+    #[test]
+    fn globals_can_be_used_before_they_are_declared() {
+        let source = "fun f() { return g() + clock(); } fun g() { return f; } var x = x;";
+        assert_eq!(
+            bindings(source),
+            ["g@17 -> 38", "clock@23 -> ?", "f@51 -> 4", "x@64 -> 60"]
+        );
+
+        let resolution = resolve(&cst::parse(source, Limits::default().max_nesting).unwrap());
+        assert_eq!(
+            resolution.name_at(39),
+            Some((crate::symbol::intern("g"), Some(1)))
+        );
+        assert_eq!(resolution.references_to(1).count(), 1);
+    }
+}
@@ -1,47 +1,84 @@
+use crate::symbol::{intern, Symbol};
 use std::collections::HashMap;
-use std::sync::LazyLock;
-
-static KEYWORDS: LazyLock<HashMap<String, TokenType>> = LazyLock::new(|| {
-    HashMap::from([
-        ("and".to_string(), TokenType::And),
-        ("class".to_string(), TokenType::Class),
-        ("else".to_string(), TokenType::Else),
-        ("false".to_string(), TokenType::False),
-        ("for".to_string(), TokenType::For),
-        ("fun".to_string(), TokenType::Fun),
-        ("if".to_string(), TokenType::If),
-        ("nil".to_string(), TokenType::Nil),
-        ("or".to_string(), TokenType::Or),
-        ("print".to_string(), TokenType::Print),
-        ("return".to_string(), TokenType::Return),
-        ("super".to_string(), TokenType::Super),
-        ("this".to_string(), TokenType::This),
-        ("true".to_string(), TokenType::True),
-        ("var".to_string(), TokenType::Var),
-        ("while".to_string(), TokenType::While),
-    ])
-});
+use std::fmt;
+use std::rc::Rc;
+
+/// Reserved words.
+pub const KEYWORDS: [(&str, TokenType); 22] = [
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+    ("catch", TokenType::Catch),
+    ("finally", TokenType::Finally),
+    ("throw", TokenType::Throw),
+    ("try", TokenType::Try),
+    ("as", TokenType::As),
+    ("import", TokenType::Import),
+];
+
+thread_local! {
+    /// The keywords by symbol, so that the scanner interns a word once and then finds out
+    /// whether it is reserved with a lookup by address.
+    static KEYWORD_TYPES: HashMap<Symbol, TokenType> = KEYWORDS
+        .iter()
+        .map(|&(keyword, token_type)| (intern(keyword), token_type))
+        .collect();
+}
 
 pub struct Scanner {
-    source: String,
+    source: Vec<char>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    errors: Vec<String>,
+    keep_comments: bool,
+    keep_whitespace: bool,
 }
 
 impl Scanner {
     pub fn new(source: &str) -> Self {
         Self {
-            source: source.to_string(),
+            source: source.chars().collect(),
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
+            errors: vec![],
+            keep_comments: false,
+            keep_whitespace: false,
         }
     }
 
-    pub fn scan_tokens(self: &mut Self) -> Result<Vec<Token>, String> {
+    /// Emits `//` comments as `Comment` tokens instead of discarding them. The parser does not
+    /// accept these; this is for tools that work on the token stream, like the formatter.
+    pub fn with_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Keeps comments and also emits each run of whitespace as a `Whitespace` token, so the
+    /// lexemes of the scanned tokens concatenate back to the source exactly.
+    pub fn with_trivia(mut self) -> Self {
+        self.keep_comments = true;
+        self.keep_whitespace = true;
+        self
+    }
+
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, String> {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token();
@@ -49,15 +86,19 @@ impl Scanner {
 
         self.tokens.push(Token {
             token_type: TokenType::EOF,
-            lexeme: "".to_string(),
+            lexeme: Lexeme::Text("".into()),
             literal: None,
             line_number: self.line,
         });
 
+        if !self.errors.is_empty() {
+            return Err(self.errors.join("\n"));
+        }
+
         Ok(self.tokens.clone())
     }
 
-    fn scan_token(self: &mut Self) {
+    fn scan_token(&mut self) {
         let c = self.advance();
         match c {
             '(' => self.add_token(TokenType::LeftParen),
@@ -65,6 +106,7 @@ impl Scanner {
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
             ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
@@ -100,9 +142,12 @@ impl Scanner {
             }
             '/' => {
                 if self.match_char('/') {
-                    while self.peek() != '\n' && self.is_at_end() {
+                    while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -110,9 +155,18 @@ impl Scanner {
             '"' => {
                 self.string();
             }
-            ' ' | '\r' | '\t' => {}
-            '\n' => {
-                self.line += 1;
+            ' ' | '\r' | '\t' | '\n' => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                while matches!(self.peek(), ' ' | '\r' | '\t' | '\n') {
+                    if self.advance() == '\n' {
+                        self.line += 1;
+                    }
+                }
+                if self.keep_whitespace {
+                    self.add_token(TokenType::Whitespace);
+                }
             }
             _ => {
                 if self.is_digit(c) {
@@ -120,36 +174,47 @@ impl Scanner {
                 } else if self.is_alpha(c) {
                     self.identifier();
                 } else {
-                    println!("LINE {}: Unexpected character.", self.line);
+                    self.error("Unexpected character.");
                 }
             }
         }
     }
 
-    fn is_digit(self: &Self, c: char) -> bool {
-        c >= '0' && c <= '9'
+    fn error(&mut self, message: &str) {
+        self.errors.push(format!("LINE {}: {}", self.line, message));
+    }
+
+    fn is_digit(&self, c: char) -> bool {
+        c.is_ascii_digit()
     }
 
-    fn identifier(self: &mut Self) {
+    fn identifier(&mut self) {
         while self.is_alpha_numeric(self.peek()) {
             self.advance();
         }
 
-        let text = self.source[self.start..self.current].to_string();
-        let token_type = KEYWORDS.get(&text).unwrap_or(&TokenType::Identifier);
+        let symbol = intern(&self.text(self.start, self.current));
+        let token_type = KEYWORD_TYPES
+            .with(|keywords| keywords.get(&symbol).copied())
+            .unwrap_or(TokenType::Identifier);
 
-        self.add_token(token_type.clone());
+        self.tokens.push(Token {
+            token_type,
+            lexeme: Lexeme::Symbol(symbol),
+            literal: None,
+            line_number: self.line,
+        });
     }
 
-    fn is_alpha(self: &Self, c: char) -> bool {
-        return c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z' || c == '_';
+    fn is_alpha(&self, c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_'
     }
 
-    fn is_alpha_numeric(self: &Self, c: char) -> bool {
+    fn is_alpha_numeric(&self, c: char) -> bool {
         self.is_alpha(c) || self.is_digit(c)
     }
 
-    fn number(self: &mut Self) {
+    fn number(&mut self) {
         while self.is_digit(self.peek()) {
             self.advance();
         }
@@ -161,7 +226,7 @@ impl Scanner {
             }
         }
 
-        let double_substring = self.source[self.start..self.current].to_string();
+        let double_substring = self.text(self.start, self.current);
         self.add_token_literal(
             TokenType::Number,
             Some(LiteralValue::FloatValue(
@@ -170,7 +235,7 @@ impl Scanner {
         );
     }
 
-    fn string(self: &mut Self) {
+    fn string(&mut self) {
         while (self.peek() != '"') && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
@@ -179,66 +244,70 @@ impl Scanner {
         }
 
         if self.is_at_end() {
-            println!("LINE {}: Unterminated string.", self.line);
+            self.error("Unterminated string.");
             return;
         }
 
         self.advance();
-        let value_string = self.source[self.start + 1..self.current - 1].to_string();
+        let value_string = self.text(self.start + 1, self.current - 1);
 
         self.add_token_literal(
             TokenType::String,
-            Some(LiteralValue::StringValue(value_string)),
+            Some(LiteralValue::StringValue(value_string.into())),
         );
     }
 
-    fn peek(self: &Self) -> char {
+    fn peek(&self) -> char {
         if self.is_at_end() {
             return '\0';
         }
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current]
     }
 
-    fn peek_next(self: &Self) -> char {
+    fn peek_next(&self) -> char {
         if self.current + 1 >= self.source.len() {
             return '\0';
         }
-        self.source.chars().nth(self.current + 1).unwrap()
+        self.source[self.current + 1]
     }
 
-    fn match_char(self: &mut Self, expected: char) -> bool {
+    fn match_char(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
 
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.source[self.current] != expected {
             return false;
         }
 
         self.current += 1;
 
-        return true;
+        true
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
+        let c = self.source[self.current];
         self.current += 1;
         c
     }
 
-    fn is_at_end(self: &Self) -> bool {
+    fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
+    fn text(&self, start: usize, end: usize) -> String {
+        self.source[start..end].iter().collect()
+    }
+
     fn add_token(&mut self, token_type: TokenType) {
         self.add_token_literal(token_type, None);
     }
 
+    /// Adds a token that isn't a name; `identifier` adds those.
     fn add_token_literal(&mut self, token_type: TokenType, literal: Option<LiteralValue>) {
-        let text = self.source[self.start..self.current].to_string();
         self.tokens.push(Token {
             token_type,
-            lexeme: text,
+            lexeme: Lexeme::Text(self.text(self.start, self.current).into()),
             literal,
             line_number: self.line,
         });
@@ -253,6 +322,7 @@ pub enum TokenType {
     LeftBrace,
     RightBrace,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -292,7 +362,16 @@ pub enum TokenType {
     True,
     Var,
     While,
-
+    Catch,
+    Finally,
+    Throw,
+    Try,
+    As,
+    Import,
+
+    // Trivia, only produced on request
+    Comment,
+    Whitespace,
     EOF,
 }
 
@@ -306,14 +385,76 @@ impl std::fmt::Display for TokenType {
 pub enum LiteralValue {
     IntValue(i64),
     FloatValue(f64),
-    StringValue(String),
-    IdentifierValue(String),
+    StringValue(Rc<str>),
+    IdentifierValue(Rc<str>),
+}
+
+/// A token's source text. Identifiers and keywords are interned, so that names compare by
+/// address; every other token owns its text, which is freed along with it.
+#[derive(Clone)]
+pub enum Lexeme {
+    Symbol(Symbol),
+    Text(Rc<str>),
+}
+
+impl Lexeme {
+    pub fn new(token_type: TokenType, text: &str) -> Self {
+        let is_name = token_type == TokenType::Identifier
+            || KEYWORDS.iter().any(|&(_, keyword)| keyword == token_type);
+        if is_name {
+            Lexeme::Symbol(intern(text))
+        } else {
+            Lexeme::Text(text.into())
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Lexeme::Symbol(symbol) => symbol.as_str(),
+            Lexeme::Text(text) => text,
+        }
+    }
+
+    /// The lexeme as a name. Only tokens that aren't names need interning here.
+    pub fn symbol(&self) -> Symbol {
+        match self {
+            Lexeme::Symbol(symbol) => symbol.clone(),
+            Lexeme::Text(text) => intern(text),
+        }
+    }
+}
+
+impl PartialEq for Lexeme {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Lexeme::Symbol(l), Lexeme::Symbol(r)) => l == r,
+            _ => self.as_str() == other.as_str(),
+        }
+    }
+}
+
+impl PartialEq<&str> for Lexeme {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub literal: Option<LiteralValue>,
     pub line_number: usize,
 }
@@ -321,19 +462,67 @@ pub struct Token {
 impl Token {
     pub fn new(
         token_type: TokenType,
-        lexeme: String,
+        lexeme: &str,
         literal: Option<LiteralValue>,
         line_number: usize,
     ) -> Self {
         Self {
             token_type,
-            lexeme,
+            lexeme: Lexeme::new(token_type, lexeme),
             literal,
             line_number,
         }
     }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {:?}", self.token_type, self.lexeme, self.literal)
+    }
+}
 
-    pub fn to_string(self: &Self) -> String {
-        format!("{} {} {:?}", self.token_type, self.lexeme, self.literal)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_comments_run_to_the_end_of_the_line() {
+        let tokens = Scanner::new("1 // one + 2\n3").scan_tokens().unwrap();
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            [TokenType::Number, TokenType::Number, TokenType::EOF]
+        );
+        assert_eq!(tokens[1].line_number, 2);
+    }
+
+    #[test]
+    fn scan_errors_are_returned_instead_of_printed() {
+        let err = Scanner::new("1 @ 2\n\"open").scan_tokens().unwrap_err();
+        assert_eq!(
+            err,
+            "LINE 1: Unexpected character.\nLINE 2: Unterminated string."
+        );
+    }
+
+    #[test]
+    fn keywords_are_reserved_and_names_are_interned() {
+        let tokens = Scanner::new("or orchid import \"or\"")
+            .scan_tokens()
+            .unwrap();
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Or,
+                TokenType::Identifier,
+                TokenType::Import,
+                TokenType::String,
+                TokenType::EOF
+            ]
+        );
+        assert!(matches!(&tokens[0].lexeme, Lexeme::Symbol(symbol) if *symbol == intern("or")));
+        assert!(matches!(&tokens[1].lexeme, Lexeme::Symbol(symbol) if *symbol == intern("orchid")));
+        assert!(matches!(&tokens[3].lexeme, Lexeme::Text(text) if &**text == "\"or\""));
     }
 }
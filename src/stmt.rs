@@ -0,0 +1,190 @@
+use crate::expr::Expr;
+use crate::scanner::Token;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Block {
+        statements: Vec<Stmt>,
+    },
+    Expression {
+        expression: Expr,
+    },
+    For {
+        keyword: Token,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Rc<Vec<Stmt>>,
+    },
+    If {
+        keyword: Token,
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Print {
+        keyword: Token,
+        expression: Expr,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    /// `import "path" as name;`, binding `name` to the module loaded from `path`.
+    Import {
+        keyword: Token,
+        path: Rc<str>,
+        name: Token,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    /// A `try` block with a `catch` clause, a `finally` clause, or both. The catch clause
+    /// names the variable the exception is bound to.
+    Try {
+        keyword: Token,
+        body: Vec<Stmt>,
+        catch: Option<(Token, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Expr>,
+    },
+    While {
+        keyword: Token,
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+}
+
+impl Stmt {
+    /// The line the statement starts on. Blocks have none of their own, and neither do
+    /// expression statements made only of literals.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Block { .. } => None,
+            Stmt::Expression { expression } => expression.line(),
+            Stmt::For { keyword, .. }
+            | Stmt::If { keyword, .. }
+            | Stmt::Print { keyword, .. }
+            | Stmt::Return { keyword, .. }
+            | Stmt::Import { keyword, .. }
+            | Stmt::Throw { keyword, .. }
+            | Stmt::Try { keyword, .. }
+            | Stmt::While { keyword, .. } => Some(keyword.line_number),
+            Stmt::Function { name, .. } | Stmt::Var { name, .. } => Some(name.line_number),
+        }
+    }
+}
+
+/// Renders a statement as an S-expression, in the same style as `Expr`'s `Display`.
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Block { statements } => {
+                write!(f, "(block")?;
+                for statement in statements {
+                    write!(f, " {}", statement)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Expression { expression } => write!(f, "(; {})", expression),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                write!(f, "(for ")?;
+                match initializer {
+                    Some(initializer) => write!(f, "{}", initializer)?,
+                    None => write!(f, "nil")?,
+                }
+                for clause in [condition, increment] {
+                    match clause {
+                        Some(clause) => write!(f, " {}", clause)?,
+                        None => write!(f, " nil")?,
+                    }
+                }
+                write!(f, " {})", body)
+            }
+            Stmt::Function { name, params, body } => {
+                write!(f, "(fun {} (", name.lexeme)?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", param.lexeme)?;
+                }
+                write!(f, ")")?;
+                for statement in body.iter() {
+                    write!(f, " {}", statement)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                write!(f, "(if {} {}", condition, then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " {}", else_branch)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Print { expression, .. } => write!(f, "(print {})", expression),
+            Stmt::Return { value, .. } => match value {
+                Some(value) => write!(f, "(return {})", value),
+                None => write!(f, "(return)"),
+            },
+            Stmt::Import { path, name, .. } => write!(f, "(import \"{}\" {})", path, name.lexeme),
+            Stmt::Throw { value, .. } => write!(f, "(throw {})", value),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                write!(f, "(try (block")?;
+                for statement in body {
+                    write!(f, " {}", statement)?;
+                }
+                write!(f, ")")?;
+                if let Some((name, body)) = catch {
+                    write!(f, " (catch {}", name.lexeme)?;
+                    for statement in body {
+                        write!(f, " {}", statement)?;
+                    }
+                    write!(f, ")")?;
+                }
+                if let Some(body) = finally {
+                    write!(f, " (finally")?;
+                    for statement in body {
+                        write!(f, " {}", statement)?;
+                    }
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Var { name, initializer } => match initializer {
+                Some(initializer) => write!(f, "(var {} {})", name.lexeme, initializer),
+                None => write!(f, "(var {})", name.lexeme),
+            },
+            Stmt::While {
+                condition, body, ..
+            } => write!(f, "(while {} {})", condition, body),
+        }
+    }
+}
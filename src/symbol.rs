@@ -0,0 +1,148 @@
+//! String interning for names.
+//!
+//! Identifiers and keywords are interned once and then handled as a `Symbol`, which compares
+//! and hashes by address instead of by content. A symbol owns its text: the table only holds
+//! weak references to it, so the text is freed once the last token, syntax tree or scope that
+//! names it is dropped. Other source text, like string literals and comments, isn't interned.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+/// Interned text. While any symbol for a given text is alive, every symbol for that text
+/// shares one allocation, so equal symbols are the ones at the same address. The text is
+/// behind a thin pointer to keep symbols, and the tokens that hold them, small.
+#[derive(Clone)]
+pub struct Symbol(Rc<String>);
+
+struct Interner {
+    symbols: HashMap<Box<str>, Weak<String>>,
+    /// How many entries the table may hold before the dead ones are dropped.
+    prune_at: usize,
+}
+
+const MIN_PRUNE_AT: usize = 256;
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        symbols: HashMap::new(),
+        prune_at: MIN_PRUNE_AT,
+    });
+}
+
+impl Interner {
+    fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(text).and_then(Weak::upgrade) {
+            return Symbol(symbol);
+        }
+
+        if self.symbols.len() >= self.prune_at {
+            self.symbols.retain(|_, symbol| symbol.strong_count() > 0);
+            self.prune_at = (self.symbols.len() * 2).max(MIN_PRUNE_AT);
+        }
+        let symbol = Rc::new(text.to_string());
+        self.symbols.insert(text.into(), Rc::downgrade(&symbol));
+        Symbol(symbol)
+    }
+}
+
+pub fn intern(text: &str) -> Symbol {
+    INTERNER.with(|interner| interner.borrow_mut().intern(text))
+}
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+    }
+}
+
+/// Symbols sort by their text.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Self {
+        intern(text)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_text_interns_to_the_same_symbol() {
+        let a = intern("counter");
+        let b = intern(&String::from("counter"));
+
+        assert_eq!(a, b);
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_ne!(a, intern("Counter"));
+        assert_eq!(a.as_str(), "counter");
+    }
+
+    #[test]
+    fn text_is_freed_with_its_last_symbol() {
+        let symbol = intern("short-lived");
+        let text = Rc::downgrade(&symbol.0);
+        drop(symbol);
+        assert_eq!(text.strong_count(), 0);
+
+        // Dead entries are dropped as the table grows, so it stays bounded.
+        for i in 0..10 * MIN_PRUNE_AT {
+            intern(&format!("name{}", i));
+        }
+        let entries = INTERNER.with(|interner| interner.borrow().symbols.len());
+        assert!(entries <= 2 * MIN_PRUNE_AT, "{} entries", entries);
+    }
+}
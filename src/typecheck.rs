@@ -0,0 +1,645 @@
+//! Static type checking for `rlox check`, over the concrete syntax tree.
+//!
+//! Variables, parameters and function returns may be annotated with a type, as in
+//! `var x: Number = 1;` or `fun f(n: Number): String { ... }`. The checker infers a type for
+//! each expression from its literals, operators and annotated names, and reports values used
+//! where their type can't go. Anything unannotated has type `Any`, which fits everywhere, so
+//! code without annotations stays dynamically typed. Annotations have no effect on running
+//! code.
+//!
+//! Nil is a type of its own, so an annotated variable needs an initializer, and a function
+//! with an annotated return type needs a `return` on every path through its body, unless the
+//! annotation is `Nil` or `Any`.
+
+use crate::cst::{SyntaxElement, SyntaxKind, SyntaxNode};
+use crate::resolver::{self, identifier};
+use crate::scanner::TokenType;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// The type of anything unannotated, which may hold any value.
+    Any,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    /// A function, with its signature if it is one whose declaration has annotations.
+    Function(Option<Rc<Signature>>),
+    Error,
+    Module,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub returns: Type,
+}
+
+impl Type {
+    /// The type an annotation names.
+    pub fn named(name: &str) -> Option<Type> {
+        Some(match name {
+            "Any" => Type::Any,
+            "Nil" => Type::Nil,
+            "Boolean" => Type::Boolean,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Function" => Type::Function(None),
+            "Error" => Type::Error,
+            "Module" => Type::Module,
+            _ => return None,
+        })
+    }
+
+    /// Whether a value of type `found` can be used where this type is expected.
+    pub fn accepts(&self, found: &Type) -> bool {
+        match (self, found) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(_), Type::Function(_)) => true,
+            _ => self == found,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Type::Any => "Any",
+            Type::Nil => "Nil",
+            Type::Boolean => "Boolean",
+            Type::Number => "Number",
+            Type::String => "String",
+            Type::Function(_) => "Function",
+            Type::Error => "Error",
+            Type::Module => "Module",
+        })
+    }
+}
+
+/// A value used where its type can't go, or an annotation naming no type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    /// Character offsets of the code the error is about.
+    pub range: Range<usize>,
+    /// Where the range starts, counting lines and columns from 1.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LINE {}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Every type error in a program, in source order.
+pub fn check(program: &SyntaxNode) -> Vec<TypeError> {
+    let resolution = resolver::resolve(program);
+    let mut checker = Checker {
+        references: resolution
+            .references
+            .iter()
+            .filter_map(|reference| {
+                let declaration = &resolution.declarations[reference.declaration?];
+                Some((reference.range.start, declaration.name_range.start))
+            })
+            .collect(),
+        declared: HashMap::new(),
+        returns: Type::Any,
+        errors: vec![],
+    };
+    // Globals can be used before they are declared, so every annotation is read first.
+    checker.declarations(program);
+    checker.statement(program);
+
+    let source: Vec<char> = program.to_string().chars().collect();
+    let mut errors: Vec<TypeError> = checker
+        .errors
+        .into_iter()
+        .map(|(message, range)| {
+            let before = &source[..range.start];
+            let line_start = before.iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+            TypeError {
+                message,
+                line: before.iter().filter(|&&c| c == '\n').count() + 1,
+                column: range.start - line_start + 1,
+                range,
+            }
+        })
+        .collect();
+    errors.sort_by_key(|error| error.range.start);
+    errors
+}
+
+struct Checker {
+    /// The offset of each name that refers to a declaration in the program, mapped to the
+    /// offset of the name in that declaration.
+    references: HashMap<usize, usize>,
+    /// The types of annotated declarations, by the offset of the name they declare.
+    declared: HashMap<usize, Type>,
+    /// What the function being checked returns.
+    returns: Type,
+    errors: Vec<(String, Range<usize>)>,
+}
+
+impl Checker {
+    fn declarations(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            SyntaxKind::VarDecl => {
+                if let Some(annotation) = node.type_annotation() {
+                    let declared = self.annotation(annotation);
+                    self.declared
+                        .insert(identifier(node).range().start, declared);
+                }
+            }
+            SyntaxKind::FunDecl => {
+                let mut params = vec![];
+                let mut annotated = false;
+                let param_list = node.child_nodes().next().expect("malformed syntax tree");
+                for child in param_list.children() {
+                    match child {
+                        SyntaxElement::Token(token)
+                            if token.token().token_type == TokenType::Identifier =>
+                        {
+                            params.push((token.range().start, Type::Any));
+                        }
+                        SyntaxElement::Node(annotation) => {
+                            let declared = self.annotation(annotation);
+                            if let Some((_, param)) = params.last_mut() {
+                                *param = declared;
+                            }
+                            annotated = true;
+                        }
+                        SyntaxElement::Token(_) => {}
+                    }
+                }
+                let returns = node.type_annotation().map(|annotation| {
+                    annotated = true;
+                    self.annotation(annotation)
+                });
+
+                // A function without annotations is as dynamic as any other unannotated code,
+                // so calls to it aren't checked.
+                if annotated {
+                    let signature = Signature {
+                        params: params.iter().map(|(_, param)| param.clone()).collect(),
+                        returns: returns.unwrap_or(Type::Any),
+                    };
+                    let name = identifier(node).range().start;
+                    self.declared
+                        .insert(name, Type::Function(Some(Rc::new(signature))));
+                    self.declared.extend(params);
+                }
+            }
+            _ => {}
+        }
+        for child in node.child_nodes() {
+            self.declarations(child);
+        }
+    }
+
+    fn annotation(&mut self, annotation: &SyntaxNode) -> Type {
+        let name = identifier(annotation);
+        Type::named(name.token().lexeme.as_str()).unwrap_or_else(|| {
+            let message = format!("Unknown type '{}'.", name.token().lexeme);
+            self.errors.push((message, name.range()));
+            Type::Any
+        })
+    }
+
+    fn statement(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            // A variable without an initializer starts out nil, and a function that runs off
+            // the end of its body returns nil, so both are checked as if they said so.
+            SyntaxKind::VarDecl => {
+                let name = identifier(node);
+                let declared = self.declared(name.range().start);
+                match node.initializer() {
+                    Some(initializer) => {
+                        let found = self.expression(initializer);
+                        self.expect(&declared, &found, initializer.range());
+                    }
+                    None => self.expect(&declared, &Type::Nil, name.range()),
+                }
+            }
+            SyntaxKind::FunDecl => {
+                let returns = match self.declared(identifier(node).range().start) {
+                    Type::Function(Some(signature)) => signature.returns.clone(),
+                    _ => Type::Any,
+                };
+                let body = node.body();
+                let enclosing = mem::replace(&mut self.returns, returns.clone());
+                self.statement(body);
+                self.returns = enclosing;
+                if !always_exits(body) {
+                    let end = match body.children().last() {
+                        Some(SyntaxElement::Token(brace)) => brace.range(),
+                        _ => body.range(),
+                    };
+                    self.expect(&returns, &Type::Nil, end);
+                }
+            }
+            SyntaxKind::Return => {
+                let (found, range) = match node.child_nodes().next() {
+                    Some(value) => (self.expression(value), value.range()),
+                    None => (Type::Nil, node.range()),
+                };
+                let returns = self.returns.clone();
+                self.expect(&returns, &found, range);
+            }
+            _ => {
+                for child in node.child_nodes() {
+                    if is_expression(child.kind()) {
+                        self.expression(child);
+                    } else {
+                        self.statement(child);
+                    }
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, node: &SyntaxNode) -> Type {
+        let operator = || {
+            node.child_tokens()
+                .next()
+                .expect("malformed syntax tree")
+                .token_type
+        };
+        match node.kind() {
+            SyntaxKind::Literal => match operator() {
+                TokenType::Number => Type::Number,
+                TokenType::String => Type::String,
+                TokenType::True | TokenType::False => Type::Boolean,
+                _ => Type::Nil,
+            },
+            SyntaxKind::Variable => self.variable(node),
+            SyntaxKind::Grouping => self.expression(nth_node(node, 0)),
+            SyntaxKind::Assign => {
+                let value = nth_node(node, 1);
+                let found = self.expression(value);
+                let declared = self.variable(nth_node(node, 0));
+                self.expect(&declared, &found, value.range());
+                found
+            }
+            SyntaxKind::Unary => {
+                let operand = nth_node(node, 0);
+                let found = self.expression(operand);
+                if operator() == TokenType::Bang {
+                    return Type::Boolean;
+                }
+                let message = "Operand must be a number.";
+                result(
+                    self.operand(&found, operand, &[Type::Number], message),
+                    Type::Number,
+                )
+            }
+            SyntaxKind::Binary => self.binary(node, operator()),
+            SyntaxKind::Logical => {
+                let left = self.expression(nth_node(node, 0));
+                let right = self.expression(nth_node(node, 1));
+                if left == right {
+                    left
+                } else {
+                    Type::Any
+                }
+            }
+            SyntaxKind::Call => self.call(node),
+            SyntaxKind::Get => {
+                let object = nth_node(node, 0);
+                let found = self.expression(object);
+                let message = "Only modules have properties.";
+                self.operand(&found, object, &[Type::Module], message);
+                Type::Any
+            }
+            kind => unreachable!("{:?} is not an expression", kind),
+        }
+    }
+
+    fn binary(&mut self, node: &SyntaxNode, operator: TokenType) -> Type {
+        let (left_node, right_node) = (nth_node(node, 0), nth_node(node, 1));
+        let left = self.expression(left_node);
+        let right = self.expression(right_node);
+        let operands = |this: &mut Self, allowed: &[Type], message: &str| {
+            let left_ok = this.operand(&left, left_node, allowed, message);
+            this.operand(&right, right_node, allowed, message) && left_ok
+        };
+
+        match operator {
+            TokenType::Minus | TokenType::Slash | TokenType::Star => {
+                let valid = operands(self, &[Type::Number], "Operands must be numbers.");
+                result(valid, Type::Number)
+            }
+            // Adding a string to a number concatenates them.
+            TokenType::Plus => {
+                let message = "Operands must be two numbers or two strings.";
+                if !operands(self, &[Type::Number, Type::String], message) {
+                    return Type::Any;
+                }
+                match (left, right) {
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::String, _) | (_, Type::String) => Type::String,
+                    _ => Type::Any,
+                }
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                let message = "Operands must be numbers or strings.";
+                operands(self, &[Type::Number, Type::String], message);
+                if left != Type::Any && right != Type::Any && left != right {
+                    self.errors.push((message.to_string(), node.range()));
+                }
+                Type::Boolean
+            }
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                let message = "Operands must be numbers, strings, or booleans.";
+                let allowed = [Type::Nil, Type::Boolean, Type::Number, Type::String];
+                if operands(self, &allowed, message) && !comparable(&left, &right) {
+                    self.errors.push((message.to_string(), node.range()));
+                }
+                Type::Boolean
+            }
+            _ => Type::Boolean,
+        }
+    }
+
+    fn call(&mut self, node: &SyntaxNode) -> Type {
+        let callee = nth_node(node, 0);
+        let found = self.expression(callee);
+        let arguments: Vec<(&SyntaxNode, Type)> = nth_node(node, 1)
+            .child_nodes()
+            .map(|argument| (argument, self.expression(argument)))
+            .collect();
+
+        match found {
+            Type::Function(Some(signature)) => {
+                if arguments.len() != signature.params.len() {
+                    let message = format!(
+                        "Expected {} arguments but got {}.",
+                        signature.params.len(),
+                        arguments.len()
+                    );
+                    self.errors.push((message, nth_node(node, 1).range()));
+                } else {
+                    for ((argument, found), param) in arguments.iter().zip(&signature.params) {
+                        self.expect(param, found, argument.range());
+                    }
+                }
+                signature.returns.clone()
+            }
+            Type::Function(None) | Type::Any => Type::Any,
+            _ => {
+                let message = "Can only call functions.".to_string();
+                self.errors.push((message, callee.range()));
+                Type::Any
+            }
+        }
+    }
+
+    /// The type of the variable a `Variable` node names.
+    fn variable(&self, node: &SyntaxNode) -> Type {
+        let offset = identifier(node).range().start;
+        match self.references.get(&offset) {
+            Some(&declaration) => self.declared(declaration),
+            None => Type::Any,
+        }
+    }
+
+    fn declared(&self, name: usize) -> Type {
+        self.declared.get(&name).cloned().unwrap_or(Type::Any)
+    }
+
+    /// Reports `message` if an operand is known to be none of the `allowed` types, and
+    /// returns whether it could be one of them.
+    fn operand(
+        &mut self,
+        found: &Type,
+        node: &SyntaxNode,
+        allowed: &[Type],
+        message: &str,
+    ) -> bool {
+        let valid = allowed.iter().any(|allowed| allowed.accepts(found));
+        if !valid {
+            self.errors.push((message.to_string(), node.range()));
+        }
+        valid
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, range: Range<usize>) {
+        if !expected.accepts(found) {
+            let message = format!("Expected {}, but found {}.", expected, found);
+            self.errors.push((message, range));
+        }
+    }
+}
+
+/// The type an operation gives, or `Any` after an error in it, which has been reported already
+/// and shouldn't be again wherever the result is used.
+fn result(valid: bool, result: Type) -> Type {
+    if valid {
+        result
+    } else {
+        Type::Any
+    }
+}
+
+/// Whether values of these types can be compared for equality. As at runtime, nil and booleans
+/// compare with each other, while numbers and strings only compare with their own type.
+fn comparable(left: &Type, right: &Type) -> bool {
+    matches!(
+        (left, right),
+        (Type::Any, _)
+            | (_, Type::Any)
+            | (Type::Nil | Type::Boolean, Type::Nil | Type::Boolean)
+            | (Type::Number, Type::Number)
+            | (Type::String, Type::String)
+    )
+}
+
+/// Whether running a statement always ends in a `return` or a `throw`, or never ends, so that
+/// it can't run on into the code after it. Loops only count when their condition is absent or
+/// the literal `true`, since Lox has no `break`.
+fn always_exits(node: &SyntaxNode) -> bool {
+    let forever = |condition: Option<&SyntaxNode>| {
+        condition.is_none_or(|condition| {
+            condition.kind() == SyntaxKind::Literal
+                && condition
+                    .child_tokens()
+                    .next()
+                    .map(|token| token.token_type)
+                    == Some(TokenType::True)
+        })
+    };
+    match node.kind() {
+        SyntaxKind::Return | SyntaxKind::Throw => true,
+        SyntaxKind::Block => node.child_nodes().any(always_exits),
+        SyntaxKind::If => {
+            let mut branches = node.child_nodes().skip(1);
+            match (branches.next(), branches.next()) {
+                (Some(then_branch), Some(else_branch)) => {
+                    always_exits(then_branch) && always_exits(else_branch)
+                }
+                _ => false,
+            }
+        }
+        SyntaxKind::While => forever(node.child_nodes().next()),
+        SyntaxKind::For => forever(node.for_clauses()[1]),
+        SyntaxKind::Try => {
+            let (mut body, mut catch, mut finally) = (false, true, false);
+            for child in node.child_nodes() {
+                match child.kind() {
+                    SyntaxKind::Catch => catch = always_exits(nth_node(child, 0)),
+                    SyntaxKind::Finally => finally = always_exits(nth_node(child, 0)),
+                    _ => body = always_exits(child),
+                }
+            }
+            finally || (body && catch)
+        }
+        _ => false,
+    }
+}
+
+fn is_expression(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::Assign
+            | SyntaxKind::Binary
+            | SyntaxKind::Call
+            | SyntaxKind::Get
+            | SyntaxKind::Grouping
+            | SyntaxKind::Literal
+            | SyntaxKind::Logical
+            | SyntaxKind::Unary
+            | SyntaxKind::Variable
+    )
+}
+
+fn nth_node(node: &SyntaxNode, n: usize) -> &SyntaxNode {
+    node.child_nodes().nth(n).expect("malformed syntax tree")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cst;
+    use crate::limits::Limits;
+
+    /// Each error as `line:column message`.
+    fn errors(source: &str) -> Vec<String> {
+        check(&cst::parse(source, Limits::default().max_nesting).unwrap())
+            .iter()
+            .map(|error| format!("{}:{} {}", error.line, error.column, error.message))
+            .collect()
+    }
+
+    #[test]
+    fn unannotated_code_stays_dynamic() {
+        let source =
+            "var x = 1; x = \"one\"; fun f(a, b) { return a + b; } f(x); print f(1, nil) * 2;";
+        assert!(errors(source).is_empty());
+    }
+
+    #[test]
+    fn reports_mismatches_against_annotations() {
+        let source = "var n: Number = \"one\";
+fun greet(name: String, times: Number): String {
+  if (times < 1) return nil;
+  return name + times;
+}
+greet(1, 2);
+greet(\"a\");
+var s: String = greet(\"a\", 3);
+s = -s;
+var b: Boolean = greet;
+var q: Quux;";
+        assert_eq!(
+            errors(source),
+            [
+                "1:17 Expected Number, but found String.",
+                "3:25 Expected String, but found Nil.",
+                "6:7 Expected String, but found Number.",
+                "7:6 Expected 2 arguments but got 1.",
+                "9:6 Operand must be a number.",
+                "10:18 Expected Boolean, but found Function.",
+                "11:8 Unknown type 'Quux'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn infers_types_through_operators_and_calls() {
+        let source = "fun half(n: Number): Number { return n / 2; }
+var x = half(4) + \"!\";
+print -(\"a\" + 1);
+print 1 < \"b\";
+print true + x;
+half(2)();
+print \"s\".length;
+print nil == 1;";
+        assert_eq!(
+            errors(source),
+            [
+                "3:8 Operand must be a number.",
+                "4:7 Operands must be numbers or strings.",
+                "5:7 Operands must be two numbers or two strings.",
+                "6:1 Can only call functions.",
+                "7:7 Only modules have properties.",
+                "8:7 Operands must be numbers, strings, or booleans.",
+            ]
+        );
+    }
+    #[test]
+    fn equality_follows_the_runtime_rules() {
+        let source = "fun f() {}
+var x: Number = 1;
+print nil == nil;
+print true == nil;
+print f() == nil;
+print x != 2;
+print x != nil;
+print 1 == \"a\";
+print g == g;
+fun g(): Number { return 1; }";
+        assert_eq!(
+            errors(source),
+            [
+                "7:7 Operands must be numbers, strings, or booleans.",
+                "8:7 Operands must be numbers, strings, or booleans.",
+                "9:7 Operands must be numbers, strings, or booleans.",
+                "9:12 Operands must be numbers, strings, or booleans.",
+            ]
+        );
+    }
+
+    #[test]
+    fn annotated_names_never_hold_nil_by_default() {
+        let source = "var n: Number;
+var m: Number = nil;
+var a;
+var maybe: Nil;
+fun none(): Number {}
+fun some(x): Number { if (x) return 1; else { print x; return 2; } }
+fun sometimes(x): Number { if (x) return 1; }
+fun loops(): Number { while (true) {} }
+fun fails(): Number { try { return 1; } catch (e) { print e; } }
+fun rethrows(): Number { try { return 1; } finally { throw \"no\"; } }";
+        assert_eq!(
+            errors(source),
+            [
+                "1:5 Expected Number, but found Nil.",
+                "2:17 Expected Number, but found Nil.",
+                "5:21 Expected Number, but found Nil.",
+                "7:45 Expected Number, but found Nil.",
+                "9:64 Expected Number, but found Nil.",
+            ]
+        );
+    }
+}
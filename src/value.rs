@@ -0,0 +1,185 @@
+use crate::chunk::Chunk;
+use crate::gc::GcRef;
+use crate::symbol::Symbol;
+use std::fmt;
+use std::rc::Rc;
+
+/// A runtime value of the bytecode virtual machine. Strings, closures and other objects live
+/// on the VM's garbage-collected heap and are referred to by handle, so values are `Copy`.
+///
+/// With the `nan-boxing` feature a value is a single `u64`: numbers are stored as their IEEE
+/// bits and everything else is packed into the payload of a quiet NaN. Otherwise it wraps a
+/// `ValueKind`. Either way, inspect a value by matching on `kind()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value(Repr);
+
+/// The decoded form of a `Value`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(GcRef),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+type Repr = ValueKind;
+
+#[cfg(feature = "nan-boxing")]
+type Repr = u64;
+
+#[cfg(feature = "nan-boxing")]
+mod nan {
+    pub const QNAN: u64 = 0x7ffc_0000_0000_0000;
+    pub const SIGN: u64 = 0x8000_0000_0000_0000;
+    pub const NIL: u64 = QNAN | 1;
+    pub const FALSE: u64 = QNAN | 2;
+    pub const TRUE: u64 = QNAN | 3;
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub const NIL: Value = Value(ValueKind::Nil);
+
+    pub fn bool(b: bool) -> Self {
+        Value(ValueKind::Bool(b))
+    }
+
+    pub fn number(n: f64) -> Self {
+        Value(ValueKind::Number(n))
+    }
+
+    pub fn obj(reference: GcRef) -> Self {
+        Value(ValueKind::Obj(reference))
+    }
+
+    pub fn kind(self) -> ValueKind {
+        self.0
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub const NIL: Value = Value(nan::NIL);
+
+    pub fn bool(b: bool) -> Self {
+        Value(if b { nan::TRUE } else { nan::FALSE })
+    }
+
+    pub fn number(n: f64) -> Self {
+        // Every NaN is stored as the canonical one so its bits can't be mistaken for a tag.
+        let n = if n.is_nan() { f64::NAN } else { n };
+        Value(n.to_bits())
+    }
+
+    pub fn obj(reference: GcRef) -> Self {
+        Value(nan::SIGN | nan::QNAN | reference.index() as u64)
+    }
+
+    pub fn kind(self) -> ValueKind {
+        let bits = self.0;
+        if bits & nan::QNAN != nan::QNAN {
+            ValueKind::Number(f64::from_bits(bits))
+        } else if bits & nan::SIGN != 0 {
+            ValueKind::Obj(GcRef::from_index(bits as u32))
+        } else {
+            match bits {
+                nan::NIL => ValueKind::Nil,
+                nan::FALSE => ValueKind::Bool(false),
+                nan::TRUE => ValueKind::Bool(true),
+                _ => unreachable!("invalid value bits {:#x}", bits),
+            }
+        }
+    }
+}
+
+/// An entry in a chunk's constant pool. Constants are immutable and outlive any single run
+/// of the VM, so they are reference counted rather than allocated on the heap.
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    /// The name of a global or a property.
+    Name(Symbol),
+    Function(Rc<Function>),
+}
+
+/// A compiled function body. The top-level script is compiled into a function named `""`.
+#[derive(Debug, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl Value {
+    pub fn is_falsey(self) -> bool {
+        matches!(self.kind(), ValueKind::Nil | ValueKind::Bool(false))
+    }
+
+    pub fn as_obj(self) -> Option<GcRef> {
+        match self.kind() {
+            ValueKind::Obj(reference) => Some(reference),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", n),
+            Constant::String(s) => write!(f, "{}", s),
+            Constant::Name(name) => write!(f, "{}", name),
+            Constant::Function(function) => write!(f, "{}", function),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    #[test]
+    fn values_round_trip_through_kind() {
+        let reference = GcRef::from_index(u32::MAX);
+        let numbers = [
+            0.0,
+            -0.0,
+            1.5,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::MIN_POSITIVE,
+        ];
+
+        assert_eq!(Value::NIL.kind(), ValueKind::Nil);
+        assert_eq!(Value::bool(true).kind(), ValueKind::Bool(true));
+        assert_eq!(Value::bool(false).kind(), ValueKind::Bool(false));
+        assert_eq!(Value::obj(reference).kind(), ValueKind::Obj(reference));
+        for n in numbers {
+            match Value::number(n).kind() {
+                ValueKind::Number(m) => assert_eq!(m.to_bits(), n.to_bits()),
+                other => panic!("{} decoded as {:?}", n, other),
+            }
+        }
+        assert!(matches!(Value::number(-f64::NAN).kind(), ValueKind::Number(n) if n.is_nan()));
+    }
+
+    #[test]
+    fn values_are_compact() {
+        let expected = if cfg!(feature = "nan-boxing") { 8 } else { 16 };
+        assert_eq!(mem::size_of::<Value>(), expected);
+    }
+}
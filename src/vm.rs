@@ -0,0 +1,976 @@
+use crate::chunk::OpCode;
+use crate::compiler;
+use crate::debug::{disassemble_instruction, format_stack};
+use crate::error::{RuntimeError, StackFrame};
+use crate::expr::LiteralValue;
+use crate::gc::{
+    GcConfig, GcRef, Heap, HeapStats, ObjClosure, ObjFunction, ObjModule, ObjUpvalue, Object,
+};
+use crate::limits::{Interrupt, Limit, Limits};
+use crate::module;
+use crate::scanner::{Token, TokenType};
+use crate::symbol::{intern, Symbol};
+use crate::value::{Constant, Function, Value, ValueKind};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+struct CallFrame {
+    closure: GcRef,
+    function: Rc<Function>,
+    constants: GcRef,
+    ip: usize,
+    slots: usize,
+    /// The module whose globals the code uses, or `None` for the main script's.
+    module: Option<GcRef>,
+    /// Whether the frame runs the top-level code of a module being imported.
+    import: bool,
+}
+
+/// A `try` block in progress. Unwinding to it restores the frame and stack depth it was
+/// entered at and jumps to its handler.
+struct Handler {
+    frames: usize,
+    stack: usize,
+    loading: usize,
+    ip: usize,
+}
+
+/// A stack-based virtual machine that runs functions produced by `compiler::compile`.
+///
+/// Every object the VM creates lives on its garbage-collected `Heap`. Collections are
+/// triggered by allocation and treat the value stack, call frames, open upvalues, globals and
+/// imported modules as roots.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Value>,
+    /// Globals set by the host, which every module starts out with.
+    builtins: HashMap<Symbol, Value>,
+    /// Modules that have been imported, by canonical path.
+    modules: HashMap<PathBuf, GcRef>,
+    /// Modules whose top-level code is running, outermost first.
+    loading: Vec<PathBuf>,
+    /// The file the main script came from, which its imports are relative to.
+    file: Option<Rc<str>>,
+    optimize: bool,
+    open_upvalues: Vec<GcRef>,
+    handlers: Vec<Handler>,
+    /// The value raised by the last `throw`, until a handler takes it.
+    thrown: Option<Value>,
+    limits: Limits,
+    /// Instructions executed since `interpret` was called.
+    steps: u64,
+    interrupt: Interrupt,
+    /// When the running script times out.
+    deadline: Option<Instant>,
+    heap: Heap,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        let value = *self.globals.get(&intern(name))?;
+        self.heap.to_literal(value)
+    }
+
+    /// Every global with its value as `print` shows it, in no particular order.
+    pub fn globals(&self) -> Vec<(Symbol, String)> {
+        self.globals
+            .iter()
+            .map(|(name, &value)| (name.clone(), self.heap.display(value).to_string()))
+            .collect()
+    }
+
+    /// Defines a global from a host value, in the main script and in every module imported
+    /// after this. Returns false if the value has no VM equivalent.
+    pub fn set_global(&mut self, name: &str, value: &LiteralValue) -> bool {
+        match self.heap.from_literal(value) {
+            Some(value) => {
+                self.globals.insert(intern(name), value);
+                self.builtins.insert(intern(name), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names the file the next script comes from, so its imports can be found.
+    pub fn set_file(&mut self, file: Option<Rc<str>>) {
+        self.file = file;
+    }
+
+    /// Whether imported modules are constant-folded before they are compiled.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn gc_config(&self) -> GcConfig {
+        self.heap.config()
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_config(config);
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// A handle other threads can use to stop the script this VM is running.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Makes the VM answer to an existing handle instead of its own.
+    pub fn set_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt = interrupt;
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn collect_garbage(&mut self) {
+        let mut roots = self.stack.clone();
+        for frame in &self.frames {
+            roots.push(Value::obj(frame.closure));
+            roots.push(Value::obj(frame.constants));
+        }
+        roots.extend(
+            self.open_upvalues
+                .iter()
+                .map(|&upvalue| Value::obj(upvalue)),
+        );
+        roots.extend(self.globals.values().copied());
+        roots.extend(self.builtins.values().copied());
+        roots.extend(self.modules.values().copied().map(Value::obj));
+        roots.extend(self.thrown);
+
+        self.heap.collect(roots);
+    }
+
+    /// Runs a compiled script and returns the value it leaves behind. Globals persist across
+    /// calls; the stack is reset if the script fails with an error no `try` block handles.
+    /// When `trace` is given, the value stack and each instruction are logged to it before the
+    /// instruction executes.
+    pub fn interpret(
+        &mut self,
+        function: Function,
+        output: &mut dyn Write,
+        mut trace: Option<&mut dyn Write>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        let function = Rc::new(function);
+        let constants = self.load_function(function.clone());
+        let closure = self.heap.alloc(Object::Closure(ObjClosure {
+            function: constants,
+            upvalues: vec![],
+            module: None,
+        }));
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.stack.push(Value::obj(closure));
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            constants,
+            ip: 0,
+            slots: 0,
+            module: None,
+            import: false,
+        });
+
+        let result = loop {
+            match self.run(
+                output,
+                trace.as_mut().map(|trace| &mut **trace as &mut dyn Write),
+            ) {
+                Err(error) if !error.fatal && !self.handlers.is_empty() => self.unwind(error),
+                result => break result,
+            }
+        };
+
+        match result {
+            Ok(value) => Ok(self.heap.to_literal(value).unwrap_or(LiteralValue::Nil)),
+            Err(mut error) => {
+                if error.backtrace.is_empty() {
+                    error.backtrace = self.backtrace(error.line).into();
+                }
+                self.stack.clear();
+                self.frames.clear();
+                self.open_upvalues.clear();
+                self.handlers.clear();
+                self.loading.clear();
+                self.thrown = None;
+                Err(error)
+            }
+        }
+    }
+
+    /// Unwinds to the innermost handler, which finds the thrown value and an error object
+    /// describing where it came from on top of the stack.
+    fn unwind(&mut self, mut error: RuntimeError) {
+        if error.backtrace.is_empty() {
+            error.backtrace = self.backtrace(error.line).into();
+        }
+        let error = Value::obj(self.allocate(Object::Error(Rc::new(error))));
+        let value = self.thrown.take().unwrap_or(error);
+
+        let handler = self.handlers.pop().expect("handler");
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.frames.truncate(handler.frames);
+        self.loading.truncate(handler.loading);
+        self.frame_mut().ip = handler.ip;
+        self.push(value);
+        self.push(error);
+    }
+
+    /// Copies a compiled function and everything in its constant pool onto the heap. Nothing
+    /// is collected while loading, so the partially built objects need no rooting.
+    fn load_function(&mut self, function: Rc<Function>) -> GcRef {
+        let mut constants = Vec::with_capacity(function.chunk.constants.len());
+        for constant in &function.chunk.constants {
+            constants.push(match constant {
+                Constant::Number(n) => Value::number(*n),
+                Constant::String(s) => Value::obj(self.heap.alloc_string(s)),
+                Constant::Name(name) => Value::obj(self.heap.alloc_string(name.as_str())),
+                Constant::Function(nested) => Value::obj(self.load_function(nested.clone())),
+            });
+        }
+
+        self.heap.alloc(Object::Function(ObjFunction {
+            function,
+            constants,
+        }))
+    }
+
+    fn allocate(&mut self, object: Object) -> GcRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(object)
+    }
+
+    fn allocate_string(&mut self, text: &str) -> GcRef {
+        if let Some(existing) = self.heap.find_string(text) {
+            return existing;
+        }
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc_string(text)
+    }
+
+    fn run(
+        &mut self,
+        output: &mut dyn Write,
+        mut trace: Option<&mut dyn Write>,
+    ) -> Result<Value, RuntimeError> {
+        loop {
+            let start = self.frame().ip;
+            self.check_limits(start)?;
+
+            if let Some(trace) = trace.as_mut() {
+                let (instruction, _) = disassemble_instruction(&self.frame().function.chunk, start);
+                let stack = format_stack(&self.stack, &self.heap);
+                let _ = writeln!(trace, "{}\n{}", stack, instruction);
+            }
+
+            let op = OpCode::from_byte(self.read_byte()).expect("valid opcode");
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::NIL),
+                OpCode::True => self.push(Value::bool(true)),
+                OpCode::False => self.push(Value::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    match self.frame_globals().get(&name) {
+                        Some(&value) => self.push(value),
+                        None => return Err(self.undefined_variable(start, name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.frame_globals().insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0);
+                    match self.frame_globals().get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined_variable(start, name)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = match self.heap.upvalue(upvalue) {
+                        ObjUpvalue::Open(slot) => self.stack[*slot],
+                        ObjUpvalue::Closed(value) => *value,
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    let value = self.peek(0);
+                    match self.heap.upvalue_mut(upvalue) {
+                        ObjUpvalue::Open(slot) => self.stack[*slot] = value,
+                        ObjUpvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    match self.values_equal(left, right) {
+                        Some(equal) => self.push(Value::bool(equal == (op == OpCode::Equal))),
+                        None => {
+                            return Err(self.error(
+                                start,
+                                op,
+                                "Operands must be numbers, strings, or booleans.",
+                            ))
+                        }
+                    }
+                }
+                OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let ordering = match (left.kind(), right.kind()) {
+                        (ValueKind::Number(l), ValueKind::Number(r)) => l.partial_cmp(&r),
+                        _ => match (self.as_str(left), self.as_str(right)) {
+                            (Some(l), Some(r)) => l.partial_cmp(r),
+                            _ => {
+                                return Err(self.error(
+                                    start,
+                                    op,
+                                    "Operands must be numbers or strings.",
+                                ))
+                            }
+                        },
+                    };
+                    let result = match ordering {
+                        Some(ordering) => match op {
+                            OpCode::Greater => ordering.is_gt(),
+                            OpCode::GreaterEqual => ordering.is_ge(),
+                            OpCode::Less => ordering.is_lt(),
+                            _ => ordering.is_le(),
+                        },
+                        None => false,
+                    };
+                    self.push(Value::bool(result));
+                }
+                OpCode::Add => {
+                    let right = self.peek(0);
+                    let left = self.peek(1);
+                    let result = match (left.kind(), right.kind()) {
+                        (ValueKind::Number(l), ValueKind::Number(r)) => Value::number(l + r),
+                        _ => {
+                            let is_text = |value: Value| {
+                                matches!(value.kind(), ValueKind::Number(_))
+                                    || self.as_str(value).is_some()
+                            };
+                            if !is_text(left) || !is_text(right) {
+                                return Err(self.error(
+                                    start,
+                                    op,
+                                    "Operands must be two numbers or two strings.",
+                                ));
+                            }
+
+                            // Both operands stay on the stack until the result is allocated,
+                            // so a collection here cannot free them.
+                            let text =
+                                format!("{}{}", self.heap.display(left), self.heap.display(right));
+                            Value::obj(self.allocate_string(&text))
+                        }
+                    };
+                    self.pop();
+                    self.pop();
+                    self.push(result);
+                }
+                OpCode::Subtract | OpCode::Multiply | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let (l, r) = match (left.kind(), right.kind()) {
+                        (ValueKind::Number(l), ValueKind::Number(r)) => (l, r),
+                        _ => return Err(self.error(start, op, "Operands must be numbers.")),
+                    };
+                    self.push(Value::number(match op {
+                        OpCode::Subtract => l - r,
+                        OpCode::Multiply => l * r,
+                        _ => l / r,
+                    }));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::bool(value.is_falsey()));
+                }
+                OpCode::Negate => match self.pop().kind() {
+                    ValueKind::Number(n) => self.push(Value::number(-n)),
+                    _ => return Err(self.error(start, op, "Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    if let Err(e) = writeln!(output, "{}", self.heap.display(value)) {
+                        let message = format!("Could not write output: {}.", e);
+                        return Err(self.error(start, op, &message));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                    self.check_interrupt(start)?;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(start, arg_count)?;
+                }
+                OpCode::Closure => {
+                    let function = self
+                        .read_constant()
+                        .as_obj()
+                        .expect("closure operand is a function");
+                    let upvalue_count = self.heap.function(function).function.upvalue_count;
+
+                    // Push the closure before capturing, so it is rooted while upvalues are
+                    // allocated.
+                    let closure = self.allocate(Object::Closure(ObjClosure {
+                        function,
+                        upvalues: Vec::with_capacity(upvalue_count),
+                        module: self.frame().module,
+                    }));
+                    self.push(Value::obj(closure));
+
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.heap.closure(self.frame().closure).upvalues[index]
+                        };
+
+                        if let Object::Closure(closure) = self.heap.get_mut(closure) {
+                            closure.upvalues.push(upvalue);
+                        }
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Try => {
+                    let offset = self.read_u16() as usize;
+                    self.handlers.push(Handler {
+                        frames: self.frames.len(),
+                        stack: self.stack.len(),
+                        loading: self.loading.len(),
+                        ip: self.frame().ip + offset,
+                    });
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    let mut error = match self.as_error(value) {
+                        Some(error) => RuntimeError::clone(error),
+                        None => {
+                            let message = self.heap.display(value).to_string();
+                            self.error(start, op, &message)
+                        }
+                    };
+                    error.value = self.heap.to_literal(value).map(Box::new);
+                    self.thrown = Some(value);
+                    return Err(error);
+                }
+                OpCode::Rethrow => {
+                    let error = self.pop();
+                    let value = self.pop();
+                    // Only a handler's own error object is rethrown, but a loaded script
+                    // could hand it anything.
+                    let error = match self.as_error(error) {
+                        Some(error) => RuntimeError::clone(error),
+                        None => self.error(start, op, &self.heap.display(error).to_string()),
+                    };
+                    self.thrown = Some(value);
+                    return Err(error);
+                }
+                OpCode::Import => {
+                    let path = self.read_string();
+                    self.import(start, &path)?;
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.pop();
+                    let module = match object.as_obj().map(|object| self.heap.get(object)) {
+                        Some(Object::Module(module)) => module,
+                        _ => {
+                            let message = "Only modules have properties.";
+                            return Err(self.property_error(start, name, message));
+                        }
+                    };
+                    match module.globals.get(&name) {
+                        Some(&value) => self.push(value),
+                        None => {
+                            let message = format!("Undefined property '{}'.", name);
+                            return Err(self.property_error(start, name, &message));
+                        }
+                    }
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("call frame");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    if frame.import {
+                        // The import evaluates to the module, which is already on the stack.
+                        let file = self.loading.pop().expect("module being loaded");
+                        self.modules
+                            .insert(file, frame.module.expect("imported module"));
+                    } else {
+                        self.push(result);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, start: usize, arg_count: usize) -> Result<(), RuntimeError> {
+        self.check_interrupt(start)?;
+        let callee_slot = self.stack.len() - 1 - arg_count;
+        let callee = match self.stack[callee_slot].as_obj() {
+            Some(callee) => callee,
+            None => return Err(self.error(start, OpCode::Call, "Can only call functions.")),
+        };
+
+        match self.heap.get(callee) {
+            Object::Closure(closure) => {
+                let constants = closure.function;
+                let function = self.heap.function(constants).function.clone();
+                self.check_arity(start, function.arity, arg_count)?;
+                if self.frames.len() > self.limits.max_depth {
+                    return Err(RuntimeError::exceeded(Limit::Depth, self.line_at(start)));
+                }
+                let module = closure.module;
+                self.frames.push(CallFrame {
+                    closure: callee,
+                    function,
+                    constants,
+                    ip: 0,
+                    slots: callee_slot,
+                    module,
+                    import: false,
+                });
+                Ok(())
+            }
+            Object::Native(native) => {
+                let native = native.clone();
+                self.check_arity(start, native.arity, arg_count)?;
+
+                let mut arguments = Vec::with_capacity(arg_count);
+                for &argument in &self.stack[callee_slot + 1..] {
+                    match self.heap.to_literal(argument) {
+                        Some(argument) => arguments.push(argument),
+                        None => {
+                            return Err(self.error(
+                                start,
+                                OpCode::Call,
+                                "Can't pass a compiled function to a native function.",
+                            ))
+                        }
+                    }
+                }
+
+                let result = (native.fun)(&arguments)
+                    .map_err(|message| self.error(start, OpCode::Call, &message))?;
+
+                if self.heap.should_collect() {
+                    self.collect_garbage();
+                }
+                let result = self.heap.from_literal(&result).ok_or_else(|| {
+                    self.error(
+                        start,
+                        OpCode::Call,
+                        "Native function returned an unsupported value.",
+                    )
+                })?;
+
+                self.stack.truncate(callee_slot);
+                self.push(result);
+                Ok(())
+            }
+            _ => Err(self.error(start, OpCode::Call, "Can only call functions.")),
+        }
+    }
+
+    /// Pushes the module `path` names, running its top-level code first unless it has been
+    /// imported before. The module starts out with the globals set by the host.
+    fn import(&mut self, start: usize, path: &str) -> Result<(), RuntimeError> {
+        let importer = match self.frame().module {
+            Some(module) => Some(self.heap.module(module).file.clone()),
+            None => self.file.clone(),
+        };
+        let file = module::resolve(importer.as_deref(), path)
+            .map_err(|message| self.error(start, OpCode::Import, &message))?;
+        if let Some(&module) = self.modules.get(&file) {
+            self.push(Value::obj(module));
+            return Ok(());
+        }
+        module::check_cycle(self.file.as_deref(), &self.loading, &file)
+            .map_err(|message| self.error(start, OpCode::Import, &message))?;
+        let function = module::parse(&file, self.optimize, self.limits.max_nesting)
+            .and_then(|statements| {
+                compiler::compile(&statements).map_err(|error| {
+                    format!(
+                        "Could not load module '{}': {}",
+                        file.display(),
+                        error.report()
+                    )
+                })
+            })
+            .map_err(|message| self.error(start, OpCode::Import, &message))?;
+
+        let module = self.allocate(Object::Module(ObjModule {
+            file: file.display().to_string().into(),
+            globals: self.builtins.clone(),
+        }));
+        self.push(Value::obj(module));
+        // Nothing is collected until the closure is on the stack.
+        let function = Rc::new(function);
+        let constants = self.load_function(function.clone());
+        let closure = self.heap.alloc(Object::Closure(ObjClosure {
+            function: constants,
+            upvalues: vec![],
+            module: Some(module),
+        }));
+        self.push(Value::obj(closure));
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            constants,
+            ip: 0,
+            slots: self.stack.len() - 1,
+            module: Some(module),
+            import: true,
+        });
+        self.loading.push(file);
+        Ok(())
+    }
+
+    /// Counts the instruction at `start` against the step limit and, once the heap has grown
+    /// past its limit, collects garbage to see whether it still is.
+    fn check_limits(&mut self, start: usize) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(RuntimeError::exceeded(Limit::Steps, self.line_at(start)));
+        }
+        if let Some(max) = self.limits.max_heap_bytes {
+            if self.heap.stats().bytes_allocated > max {
+                self.collect_garbage();
+                if self.heap.stats().bytes_allocated > max {
+                    return Err(RuntimeError::exceeded(Limit::Heap, self.line_at(start)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with an error no handler can catch if the script has been interrupted or has
+    /// run out of time.
+    fn check_interrupt(&self, start: usize) -> Result<(), RuntimeError> {
+        if self.interrupt.poll(self.deadline) {
+            return Err(RuntimeError::exceeded(Limit::Interrupt, self.line_at(start)).fatal());
+        }
+        Ok(())
+    }
+
+    fn check_arity(
+        &self,
+        start: usize,
+        arity: usize,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
+        if arity == arg_count {
+            return Ok(());
+        }
+
+        let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+        Err(self.error(start, OpCode::Call, &message))
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> GcRef {
+        let heap = &self.heap;
+        let existing = self.open_upvalues.iter().find(
+            |&&upvalue| matches!(heap.upvalue(upvalue), ObjUpvalue::Open(open) if *open == slot),
+        );
+        if let Some(&upvalue) = existing {
+            return upvalue;
+        }
+
+        let upvalue = self.allocate(Object::Upvalue(ObjUpvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, last: usize) {
+        let heap = &mut self.heap;
+        let stack = &self.stack;
+        self.open_upvalues.retain(|&upvalue| {
+            let upvalue = heap.upvalue_mut(upvalue);
+            let slot = match upvalue {
+                ObjUpvalue::Open(slot) if *slot >= last => *slot,
+                ObjUpvalue::Open(_) => return true,
+                ObjUpvalue::Closed(_) => return false,
+            };
+
+            *upvalue = ObjUpvalue::Closed(stack[slot]);
+            false
+        });
+    }
+
+    /// Equality with the same rules as the tree-walker: mixing numbers or strings with other
+    /// types is an error, reported by returning `None`.
+    fn values_equal(&self, left: Value, right: Value) -> Option<bool> {
+        match (left.kind(), right.kind()) {
+            (ValueKind::Number(l), ValueKind::Number(r)) => Some(l == r),
+            (ValueKind::Bool(l), ValueKind::Bool(r)) => Some(l == r),
+            (ValueKind::Bool(_), ValueKind::Nil) | (ValueKind::Nil, ValueKind::Bool(_)) => {
+                Some(false)
+            }
+            (ValueKind::Nil, ValueKind::Nil) => Some(true),
+            // Strings are interned, so equal text means the same handle.
+            (ValueKind::Obj(l), ValueKind::Obj(r))
+                if self.as_str(left).is_some() && self.as_str(right).is_some() =>
+            {
+                Some(l == r)
+            }
+            _ => None,
+        }
+    }
+
+    fn as_str(&self, value: Value) -> Option<&str> {
+        value
+            .as_obj()
+            .and_then(|reference| self.heap.as_str(reference))
+    }
+
+    fn as_error(&self, value: Value) -> Option<&Rc<RuntimeError>> {
+        match self.heap.get(value.as_obj()?) {
+            Object::Error(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// The globals the innermost frame's code uses.
+    fn frame_globals(&mut self) -> &mut HashMap<Symbol, Value> {
+        match self.frame().module {
+            Some(module) => &mut self.heap.module_mut(module).globals,
+            None => &mut self.globals,
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("call frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16() as usize;
+        self.heap.function(self.frame().constants).constants[index]
+    }
+
+    fn read_name(&mut self) -> Symbol {
+        let index = self.read_u16() as usize;
+        match &self.frame().function.chunk.constants[index] {
+            Constant::Name(name) => name.clone(),
+            other => panic!("variable name is not a name: {:?}", other),
+        }
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        let index = self.read_u16() as usize;
+        match &self.frame().function.chunk.constants[index] {
+            Constant::String(string) => string.clone(),
+            other => panic!("expected a string constant: {:?}", other),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The call frames, innermost first. The innermost one is at `line`, where an error was
+    /// raised; the others are at the calls they are waiting on.
+    fn backtrace(&self, line: usize) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(index, frame)| StackFrame {
+                name: match frame.function.name.as_str() {
+                    "" => "script".to_string(),
+                    name => name.to_string(),
+                },
+                file: frame
+                    .module
+                    .map(|module| self.heap.module(module).file.to_string()),
+                line: match index {
+                    0 => line,
+                    _ => frame.function.chunk.lines[frame.ip - 1],
+                },
+            })
+            .collect()
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.frame().function.chunk.lines[offset]
+    }
+
+    fn error(&self, start: usize, op: OpCode, message: &str) -> RuntimeError {
+        let line = self.line_at(start);
+        let (token_type, lexeme) = op.operator_token();
+        RuntimeError::new(message, line, Token::new(token_type, lexeme, None, line))
+    }
+
+    fn property_error(&self, start: usize, name: Symbol, message: &str) -> RuntimeError {
+        let line = self.line_at(start);
+        let token = Token::new(TokenType::Identifier, name.as_str(), None, line);
+        RuntimeError::new(message, line, token)
+    }
+
+    fn undefined_variable(&self, start: usize, name: Symbol) -> RuntimeError {
+        let line = self.line_at(start);
+        RuntimeError::new(
+            &format!("Undefined variable '{}'.", name),
+            line,
+            Token::new(TokenType::Identifier, name.as_str(), None, line),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use std::io;
+
+    fn run(vm: &mut Vm, source: &str) -> Result<LiteralValue, RuntimeError> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        let function = compile(&Parser::new(tokens).parse().unwrap()).unwrap();
+        vm.interpret(function, &mut io::sink(), None)
+    }
+
+    #[test]
+    fn self_referencing_closures_are_collected() {
+        let mut vm = Vm::new();
+        let source = "fun make() {
+                        var self;
+                        fun f() { return self; }
+                        self = f;
+                        return f;
+                      }
+                      for (var i = 0; i < 100; i = i + 1) make();";
+        run(&mut vm, source).unwrap();
+
+        vm.collect_garbage();
+        let baseline = vm.heap_stats().live_objects;
+        run(&mut vm, source).unwrap();
+        vm.collect_garbage();
+
+        // Only the globals survive; the cycles built by each call to make() are gone.
+        assert_eq!(vm.heap_stats().live_objects, baseline);
+        assert!(vm.heap_stats().objects_freed >= 300);
+    }
+
+    #[test]
+    fn stress_mode_keeps_reachable_objects_alive() {
+        let mut vm = Vm::new();
+        vm.set_gc_config(GcConfig {
+            stress: true,
+            ..GcConfig::default()
+        });
+
+        let source = "fun counter(prefix) {
+                        var n = 0;
+                        fun next() { n = n + 1; return prefix + n; }
+                        return next;
+                      }
+                      var a = counter(\"a\"); var b = counter(\"b\");
+                      a(); b(); a() + b()";
+        let result = run(&mut vm, source).unwrap();
+
+        assert_eq!(result, LiteralValue::StringValue("a2b2".into()));
+        assert!(vm.heap_stats().collections > 10);
+    }
+}